@@ -5,33 +5,267 @@
 // Each function returns a command string to be executed via SSH.
 // =============================================================================
 
+use crate::config::OsType;
+use base64::Engine;
+use std::sync::OnceLock;
+
 /// Simple test command - returns hostname
 pub fn test_cmd() -> &'static str {
     "hostname"
 }
 
-/// Get system info for Linux servers
+/// Get system info for Linux servers, one `key=value` line per ServerInfo field
 pub fn info_cmd_linux() -> &'static str {
-    "echo 'OS:' $(cat /etc/os-release 2>/dev/null | grep PRETTY_NAME | cut -d'=' -f2 | tr -d '\"') && \
-     echo 'Kernel:' $(uname -r) && \
-     echo 'Uptime:' $(uptime -p 2>/dev/null || uptime) && \
-     echo 'CPU:' $(grep -m1 'model name' /proc/cpuinfo 2>/dev/null | cut -d':' -f2 | xargs || echo 'Unknown') && \
-     echo 'Memory:' $(free -h 2>/dev/null | awk '/^Mem:/ {print $3 \"/\" $2}' || echo 'Unknown') && \
-     echo 'Disk:' $(df -h / 2>/dev/null | awk 'NR==2 {print $3 \"/\" $2 \" (\" $5 \" used)\"}' || echo 'Unknown')"
+    r#"echo "os=$(sed -n 's/^PRETTY_NAME=//p' /etc/os-release 2>/dev/null | tr -d '"')"
+echo "kernel=$(uname -r)"
+echo "uptime=$(uptime -p 2>/dev/null || uptime)"
+echo "cpu=$(grep -m1 'model name' /proc/cpuinfo 2>/dev/null | cut -d':' -f2 | xargs)"
+echo "memory=$(free -h 2>/dev/null | awk '/^Mem:/ {print $3 "/" $2}')"
+echo "disk=$(df -h / 2>/dev/null | awk 'NR==2 {print $3 "/" $2 " (" $5 " used)"}')""#
 }
 
-/// Get system info for Windows servers
+/// Get system info for Windows servers, in the same `key=value` form as Linux
 pub fn info_cmd_windows() -> &'static str {
-    r#"powershell -Command "Write-Host '=== Windows Info ==='; Write-Host \"Hostname: $env:COMPUTERNAME\"; $os = Get-CimInstance Win32_OperatingSystem; Write-Host \"OS: $($os.Caption)\"; Write-Host \"Build: $($os.BuildNumber)\"; Write-Host \"Uptime: $((Get-Date) - $os.LastBootUpTime)\"""#
+    r#"powershell -NoProfile -Command "$os = Get-CimInstance Win32_OperatingSystem; $cpu = Get-CimInstance Win32_Processor | Select-Object -First 1; $up = (Get-Date) - $os.LastBootUpTime; $c = Get-PSDrive C; 'os=' + $os.Caption; 'kernel=' + $os.Version; 'uptime=' + $up.Days + 'd ' + $up.Hours + 'h ' + $up.Minutes + 'm'; 'cpu=' + $cpu.Name.Trim(); 'memory=' + [math]::Round(($os.TotalVisibleMemorySize - $os.FreePhysicalMemory) / 1MB, 1) + 'G/' + [math]::Round($os.TotalVisibleMemorySize / 1MB, 1) + 'G'; 'disk=' + [math]::Round($c.Used / 1GB, 1) + 'G/' + [math]::Round(($c.Used + $c.Free) / 1GB, 1) + 'G'""#
+}
+
+/// Get system info for macOS servers, in the same `key=value` form as Linux
+pub fn info_cmd_macos() -> &'static str {
+    r#"echo "os=$(sw_vers -productName) $(sw_vers -productVersion)"
+echo "kernel=$(uname -r)"
+echo "uptime=$(uptime | sed -E 's/.* up ([^,]*), .*/\1/')"
+echo "cpu=$(sysctl -n machdep.cpu.brand_string 2>/dev/null)"
+echo "memory=$(vm_stat | awk -v total=$(sysctl -n hw.memsize) '/page size of/ {size=$8} /Pages (active|wired down|occupied by compressor)/ {used+=$NF} END {printf "%.1fG/%.1fG", used*size/1073741824, total/1073741824}')"
+echo "disk=$(df -h / 2>/dev/null | awk 'NR==2 {print $3 "/" $2 " (" $5 " used)"}')""#
 }
 
-/// Linux update command - apt update && upgrade
+/// What the info commands report, parsed from their output
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServerInfo {
+    pub os: String,
+    pub kernel: String,
+    pub uptime: String,
+    pub cpu: String,
+    pub memory: String,
+    pub disk: String,
+}
+
+impl ServerInfo {
+    /// Percent of the root (Linux) or C: (Windows) disk in use
+    pub fn disk_percent(&self) -> Option<f32> {
+        usage_percent(&self.disk)
+    }
+
+    /// Percent of physical memory in use
+    pub fn memory_percent(&self) -> Option<f32> {
+        usage_percent(&self.memory)
+    }
+}
+
+/// Usage from `df`'s "31%" when present, otherwise from "used/total" sizes
+/// such as `1.2Gi/3.8Gi` or `512M/2G`
+fn usage_percent(value: &str) -> Option<f32> {
+    if let Some(percent) = value.split(|c: char| c.is_whitespace() || c == '(')
+        .find_map(|word| word.strip_suffix('%')?.parse().ok())
+    {
+        return Some(percent);
+    }
+    let (used, total) = value.split_once('/')?;
+    let (used, total) = (parse_size(used)?, parse_size(total.split_whitespace().next()?)?);
+    (total > 0.0).then(|| used / total * 100.0)
+}
+
+/// Bytes in a human-readable size, decimal or binary suffix alike
+fn parse_size(size: &str) -> Option<f32> {
+    let size = size.trim().trim_end_matches('B').trim_end_matches('i');
+    let split = size.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let scale = match unit {
+        "" => 1.0,
+        "K" | "k" => 1024.0,
+        "M" => 1024.0_f32.powi(2),
+        "G" => 1024.0_f32.powi(3),
+        "T" => 1024.0_f32.powi(4),
+        _ => return None,
+    };
+    Some(number.parse::<f32>().ok()? * scale)
+}
+
+/// Pick the info fields out of a run's output; `None` unless every one is there,
+/// so ordinary output that happens to contain `os=` isn't mistaken for it
+pub fn parse_info(output: &str) -> Option<ServerInfo> {
+    let mut info = ServerInfo::default();
+    let mut found = 0;
+    for line in output.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let field = match key {
+            "os" => &mut info.os,
+            "kernel" => &mut info.kernel,
+            "uptime" => &mut info.uptime,
+            "cpu" => &mut info.cpu,
+            "memory" => &mut info.memory,
+            "disk" => &mut info.disk,
+            _ => continue,
+        };
+        if field.is_empty() {
+            found += 1;
+        }
+        *field = match value.trim() {
+            "" => "Unknown".to_string(),
+            value => value.to_string(),
+        };
+    }
+    (found == 6).then_some(info)
+}
+
+/// Quick health check for Linux - uptime and load averages
+pub fn uptime_cmd_linux() -> &'static str {
+    "uptime"
+}
+
+/// Quick health check for Windows - last boot, uptime and CPU load
+pub fn uptime_cmd_windows() -> &'static str {
+    r#"powershell -Command "$os = Get-CimInstance Win32_OperatingSystem; $cpu = (Get-CimInstance Win32_Processor | Measure-Object -Property LoadPercentage -Average).Average; Write-Host ('Last boot: {0}' -f $os.LastBootUpTime); Write-Host ('Uptime: {0}' -f ((Get-Date) - $os.LastBootUpTime)); Write-Host ('CPU load: {0}%' -f $cpu)""#
+}
+
+/// Disk usage of every mounted filesystem on Linux
+pub fn disk_cmd_linux() -> &'static str {
+    "df -h"
+}
+
+/// Disk usage of the local volumes on macOS
+pub fn disk_cmd_macos() -> &'static str {
+    "df -hl"
+}
+
+/// Free and total space of every lettered volume on Windows
+pub fn disk_cmd_windows() -> &'static str {
+    r#"powershell -Command "Get-Volume | Where-Object DriveLetter | Sort-Object DriveLetter | ForEach-Object { Write-Host ('{0}: {1,8:N1} GB free of {2,8:N1} GB ({3:N0}% used) {4}' -f $_.DriveLetter, ($_.SizeRemaining / 1GB), ($_.Size / 1GB), $(if ($_.Size) { 100 - $_.SizeRemaining * 100 / $_.Size } else { 0 }), $_.FileSystemLabel) }""#
+}
+
+/// Service names are pasted into the command line, so only allow plain ones,
+/// and none the tool would read as an option
+pub fn is_valid_service_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('-') && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_.@:".contains(c))
+}
+
+/// State and recent log of a systemd unit
+pub fn service_status_linux_cmd(name: &str) -> String {
+    format!("systemctl status --no-pager {}", name)
+}
+
+/// State of a launchd service, e.g. com.openssh.sshd
+pub fn service_status_macos_cmd(name: &str) -> String {
+    format!("launchctl print system/{}", name)
+}
+
+/// State and start type of a Windows service
+pub fn service_status_windows_cmd(name: &str) -> String {
+    format!(
+        r#"powershell -Command "Get-Service -Name {} | Format-List Name,DisplayName,Status,StartType""#,
+        name
+    )
+}
+
+/// The ten processes using the most CPU on Linux, for `parse_processes`
+pub fn top_processes_cmd_linux() -> &'static str {
+    "ps -eo pid,user,%cpu,%mem,comm --sort=-%cpu | head -n 11"
+}
+
+/// BSD ps sorts by CPU with -r
+pub fn top_processes_cmd_macos() -> &'static str {
+    "ps -Ar -o pid,user,%cpu,%mem,comm | head -n 11"
+}
+
+/// Same columns as ps, with CPU seconds and working set instead of percentages and no user
+pub fn top_processes_cmd_windows() -> &'static str {
+    r#"powershell -NoProfile -Command "Get-Process | Sort-Object CPU -Descending | Select-Object -First 10 | ForEach-Object { '{0} - {1:N1}s {2:N0}MB {3}' -f $_.Id, $_.CPU, ($_.WorkingSet64 / 1MB), $_.ProcessName }""#
+}
+
+/// Whether `command` is one of the top processes presets, whose output `parse_processes` reads
+pub fn is_top_processes(command: &str) -> bool {
+    [top_processes_cmd_linux(), top_processes_cmd_macos(), top_processes_cmd_windows()].contains(&command)
+}
+
+/// One row of the top processes table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Process {
+    pub pid: u32,
+    pub user: String,
+    pub cpu: String,
+    pub memory: String,
+    pub command: String,
+}
+
+/// Rows of the top processes output; the header and anything else without a PID are skipped
+pub fn parse_processes(output: &str, os: &OsType) -> Vec<Process> {
+    output.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let pid = fields.next()?.parse().ok()?;
+            let (user, cpu, memory) = (fields.next()?, fields.next()?, fields.next()?);
+            let command = fields.collect::<Vec<_>>().join(" ");
+            let percent = |value: &str| match os {
+                OsType::Windows => value.to_string(),
+                OsType::Linux | OsType::Macos => format!("{}%", value),
+            };
+            Some(Process {
+                pid,
+                user: if user == "-" { String::new() } else { user.to_string() },
+                cpu: percent(cpu),
+                memory: percent(memory),
+                command,
+            })
+        })
+        .collect()
+}
+
+/// End a process on Linux or macOS; sudo so other users' runaway processes can be stopped too
+pub fn kill_process_linux_cmd(pid: u32) -> String {
+    format!("sudo kill {}", pid)
+}
+
+pub fn kill_process_windows_cmd(pid: u32) -> String {
+    format!(r#"powershell -NoProfile -Command "Stop-Process -Id {} -Force""#, pid)
+}
+
+/// Runs the script piped to stdin from a temp file, so it can't read its own
+/// text as input, and removes the file again keeping the script's exit code.
+/// With a sudo password it comes first on stdin and is handed on to the script,
+/// whose `script_sudo_preamble` reads it.
+pub fn run_script_linux_cmd(has_password: bool) -> &'static str {
+    if has_password {
+        r#"IFS= read -r SM_SUDO_PW; f=$(mktemp) && cat > "$f" && printf '%s\n' "$SM_SUDO_PW" | bash "$f"; code=$?; rm -f "$f"; exit $code"#
+    } else {
+        r#"f=$(mktemp) && cat > "$f" && bash "$f"; code=$?; rm -f "$f"; exit $code"#
+    }
+}
+
+/// One line put before a piped script, making its `sudo` non-interactive the
+/// way `with_sudo` does for a command
+pub fn script_sudo_preamble(has_password: bool) -> &'static str {
+    if has_password {
+        "IFS= read -r SM_SUDO_PW; sudo() { printf '%s\\n' \"$SM_SUDO_PW\" | command sudo -S -p '' \"$@\"; }\n"
+    } else {
+        "sudo() { command sudo -n \"$@\"; }\n"
+    }
+}
+
+/// PowerShell's `-Command -` reads stdin a line at a time and trips on
+/// multi-line blocks, so the piped script is saved as a .ps1 and run with -File
+pub fn run_script_windows_cmd() -> &'static str {
+    r#"powershell -NoProfile -Command "$f = Join-Path $env:TEMP ('server-manager-' + [guid]::NewGuid() + '.ps1'); [Console]::In.ReadToEnd() | Set-Content -Path $f -Encoding UTF8; powershell -NoProfile -ExecutionPolicy Bypass -File $f; $code = $LASTEXITCODE; Remove-Item $f; exit $code""#
+}
+
+/// Linux update command - apt update && upgrade, with apt's progress lines on stdout
 pub fn update_linux_cmd() -> &'static str {
     "echo '>>> Running: sudo apt update' && \
-     sudo apt update && \
+     sudo apt -o APT::Status-Fd=1 update && \
      echo '' && \
      echo '>>> Running: sudo apt upgrade -y' && \
-     sudo DEBIAN_FRONTEND=noninteractive apt upgrade -y && \
+     sudo DEBIAN_FRONTEND=noninteractive apt -o APT::Status-Fd=1 upgrade -y && \
      echo '' && \
      echo '>>> Checking reboot status' && \
      if [ -f /var/run/reboot-required ]; then \
@@ -41,14 +275,306 @@ pub fn update_linux_cmd() -> &'static str {
      fi"
 }
 
+/// PowerShell for `update_windows_cmd`: check for updates, install them via a
+/// scheduled task as SYSTEM and monitor progress, announcing the task with
+/// UPDATE_TASK_MARKER so a dropped session can be resumed
+const UPDATE_WINDOWS_SCRIPT: &str = r#"$ErrorActionPreference='Continue'
+Write-Host '=== WINDOWS UPDATE ==='
+Write-Host ''
+Write-Host '>>> System Info'
+$os=Get-CimInstance Win32_OperatingSystem
+Write-Host "OS: $($os.Caption) Build: $($os.BuildNumber)"
+Write-Host ''
+
+$module=Get-Module -ListAvailable -Name PSWindowsUpdate
+if(-not $module){
+    Write-Host '>>> Installing PSWindowsUpdate...'
+    try{
+        Install-PackageProvider -Name NuGet -Force -EA SilentlyContinue|Out-Null
+        Install-Module -Name PSWindowsUpdate -Force -AllowClobber -Scope AllUsers
+        Write-Host '>>> Installed'
+    }catch{Write-Host "ERROR: $($_.Exception.Message)";exit 1}
+}
+Import-Module PSWindowsUpdate -Force
+
+Write-Host '>>> Checking for updates...'
+$updates=Get-WindowsUpdate -AcceptAll
+if($updates.Count -eq 0){Write-Host '>>> Up to date!';exit 0}
+
+Write-Host "Found $($updates.Count) update(s):"
+$updates|ForEach-Object{Write-Host "  - $($_.Title)"}
+Write-Host ''
+
+$histBefore=(Get-WUHistory|Measure-Object).Count
+$task="SMUpdate_$(Get-Random)"
+$script='Import-Module PSWindowsUpdate -Force;Install-WindowsUpdate -AcceptAll -IgnoreReboot -Confirm:$false'
+$enc=[Convert]::ToBase64String([Text.Encoding]::Unicode.GetBytes($script))
+
+Write-Host '>>> Installing as SYSTEM...'
+$action=New-ScheduledTaskAction -Execute 'powershell.exe' -Argument "-EncodedCommand $enc"
+$principal=New-ScheduledTaskPrincipal -UserId 'SYSTEM' -LogonType ServiceAccount -RunLevel Highest
+Register-ScheduledTask -TaskName $task -Action $action -Principal $principal -Force|Out-Null
+Start-ScheduledTask -TaskName $task
+Write-Host ">>> Update task: $task"
+
+$elapsed=0
+while($elapsed -lt 1800){
+    Start-Sleep -Seconds 15
+    $elapsed+=15
+    $t=Get-ScheduledTask -TaskName $task -EA SilentlyContinue
+    $hist=Get-WUHistory|Select-Object -First 10
+    $new=($hist|Measure-Object).Count
+    if($new -gt $histBefore){
+        $hist|Select-Object -First ($new-$histBefore)|ForEach-Object{
+            $r=if($_.Result -eq 'Succeeded'){'[OK]'}elseif($_.Result -eq 'Failed'){'[FAIL]'}else{"[$($_.Result)]"}
+            Write-Host "  $r $($_.Title)"
+        }
+        $histBefore=$new
+    }
+    if($t.State -eq 'Ready'){Write-Host ">>> Done (${elapsed}s)";break}
+    if($elapsed % 60 -eq 0){Write-Host ">>> Working... (${elapsed}s)"}
+}
+Unregister-ScheduledTask -TaskName $task -Confirm:$false -EA SilentlyContinue
+
+Write-Host ''
+Write-Host '>>> Recent History:'
+Get-WUHistory|Select-Object -First 5|ForEach-Object{
+    $r=if($_.Result -eq 'Succeeded'){'[OK]'}else{"[$($_.Result)]"}
+    Write-Host "  $r $($_.Title)"
+}
+
+$reboot=Test-Path 'HKLM:\SOFTWARE\Microsoft\Windows\CurrentVersion\WindowsUpdate\Auto Update\RebootRequired'
+Write-Host ''
+if($reboot){Write-Host '*** REBOOT REQUIRED ***'}else{Write-Host 'No reboot needed'}
+Write-Host '=== COMPLETE ==='"#;
+
+/// PowerShell for `resume_update_windows_cmd`
+const RESUME_UPDATE_WINDOWS_SCRIPT: &str = r#"$ErrorActionPreference='Continue'
+Write-Host '=== RESUME WINDOWS UPDATE ==='
+Write-Host ''
+Import-Module PSWindowsUpdate -Force -EA SilentlyContinue
+
+$t=Get-ScheduledTask -TaskName 'SMUpdate_*' -EA SilentlyContinue|Select-Object -First 1
+if(-not $t){
+    Write-Host '>>> No update in progress'
+}else{
+    $task=$t.TaskName
+    Write-Host ">>> Update task: $task ($($t.State))"
+    $since=Get-Date
+    $seen=@{}
+    $elapsed=0
+    while($elapsed -lt 1800){
+        $t=Get-ScheduledTask -TaskName $task -EA SilentlyContinue
+        Get-WUHistory|Where-Object{$_.Date -ge $since.AddMinutes(-60)}|ForEach-Object{
+            if(-not $seen.ContainsKey($_.Title)){
+                $seen[$_.Title]=1
+                $r=if($_.Result -eq 'Succeeded'){'[OK]'}elseif($_.Result -eq 'Failed'){'[FAIL]'}else{"[$($_.Result)]"}
+                Write-Host "  $r $($_.Title)"
+            }
+        }
+        if(-not $t -or $t.State -eq 'Ready'){Write-Host ">>> Done (${elapsed}s after reattaching)";break}
+        if($elapsed % 60 -eq 0){Write-Host ">>> Working... (${elapsed}s)"}
+        Start-Sleep -Seconds 15
+        $elapsed+=15
+    }
+    if($t -and $t.State -eq 'Ready'){Unregister-ScheduledTask -TaskName $task -Confirm:$false -EA SilentlyContinue}
+}
+
+Write-Host ''
+Write-Host '>>> Recent History:'
+Get-WUHistory|Select-Object -First 5|ForEach-Object{
+    $r=if($_.Result -eq 'Succeeded'){'[OK]'}else{"[$($_.Result)]"}
+    Write-Host "  $r $($_.Title)"
+}
+
+$reboot=Test-Path 'HKLM:\SOFTWARE\Microsoft\Windows\CurrentVersion\WindowsUpdate\Auto Update\RebootRequired'
+Write-Host ''
+if($reboot){Write-Host '*** REBOOT REQUIRED ***'}else{Write-Host 'No reboot needed'}
+Write-Host '=== COMPLETE ==='"#;
+
+/// Wrap a PowerShell script in a one-line command. The script travels base64
+/// encoded to avoid all quote/newline escaping issues over SSH.
+fn encoded_powershell(script: &str) -> String {
+    format!(
+        r#"powershell -ExecutionPolicy Bypass -Command "[Text.Encoding]::UTF8.GetString([Convert]::FromBase64String('{}'))|Invoke-Expression""#,
+        base64::engine::general_purpose::STANDARD.encode(script)
+    )
+}
+
+/// macOS update command - Homebrew packages when brew is installed, then softwareupdate.
+/// pipefail so a failed softwareupdate isn't hidden behind tee's exit status.
+pub fn update_macos_cmd() -> &'static str {
+    "set -o pipefail; \
+     if command -v brew >/dev/null 2>&1; then \
+         echo '>>> Running: brew update && brew upgrade' && \
+         brew update && brew upgrade && \
+         echo ''; \
+     fi; \
+     log=$(mktemp) && \
+     echo '>>> Running: sudo softwareupdate --install --all' && \
+     sudo softwareupdate --install --all 2>&1 | tee \"$log\" && \
+     echo '' && \
+     echo '>>> Checking reboot status' && \
+     if grep -qi 'restart' \"$log\"; then \
+         echo 'REBOOT REQUIRED'; \
+     else \
+         echo 'No reboot needed'; \
+     fi; \
+     code=$?; rm -f \"$log\"; exit $code"
+}
+
 /// Windows update command using PSWindowsUpdate module via scheduled task
-/// Uses base64 encoded script to avoid quote/newline issues over SSH
 pub fn update_windows_cmd() -> &'static str {
-    // The script is base64 encoded to avoid all escaping issues
-    // Decoded script does: check for updates, install via scheduled task as SYSTEM, monitor progress
-    r#"powershell -ExecutionPolicy Bypass -Command "[Text.Encoding]::UTF8.GetString([Convert]::FromBase64String('JEVycm9yQWN0aW9uUHJlZmVyZW5jZT0nQ29udGludWUnCldyaXRlLUhvc3QgJz09PSBXSU5ET1dTIFVQREFURSA9PT0nCldyaXRlLUhvc3QgJycKV3JpdGUtSG9zdCAnPj4+IFN5c3RlbSBJbmZvJwokb3M9R2V0LUNpbUluc3RhbmNlIFdpbjMyX09wZXJhdGluZ1N5c3RlbQpXcml0ZS1Ib3N0ICJPUzogJCgkb3MuQ2FwdGlvbikgQnVpbGQ6ICQoJG9zLkJ1aWxkTnVtYmVyKSIKV3JpdGUtSG9zdCAnJwoKJG1vZHVsZT1HZXQtTW9kdWxlIC1MaXN0QXZhaWxhYmxlIC1OYW1lIFBTV2luZG93c1VwZGF0ZQppZigtbm90ICRtb2R1bGUpewogICAgV3JpdGUtSG9zdCAnPj4+IEluc3RhbGxpbmcgUFNXaW5kb3dzVXBkYXRlLi4uJwogICAgdHJ5ewogICAgICAgIEluc3RhbGwtUGFja2FnZVByb3ZpZGVyIC1OYW1lIE51R2V0IC1Gb3JjZSAtRUEgU2lsZW50bHlDb250aW51ZXxPdXQtTnVsbAogICAgICAgIEluc3RhbGwtTW9kdWxlIC1OYW1lIFBTV2luZG93c1VwZGF0ZSAtRm9yY2UgLUFsbG93Q2xvYmJlciAtU2NvcGUgQWxsVXNlcnMKICAgICAgICBXcml0ZS1Ib3N0ICc+Pj4gSW5zdGFsbGVkJwogICAgfWNhdGNoe1dyaXRlLUhvc3QgIkVSUk9SOiAkKCRfLkV4Y2VwdGlvbi5NZXNzYWdlKSI7ZXhpdCAxfQp9CkltcG9ydC1Nb2R1bGUgUFNXaW5kb3dzVXBkYXRlIC1Gb3JjZQoKV3JpdGUtSG9zdCAnPj4+IENoZWNraW5nIGZvciB1cGRhdGVzLi4uJwokdXBkYXRlcz1HZXQtV2luZG93c1VwZGF0ZSAtQWNjZXB0QWxsCmlmKCR1cGRhdGVzLkNvdW50IC1lcSAwKXtXcml0ZS1Ib3N0ICc+Pj4gVXAgdG8gZGF0ZSEnO2V4aXQgMH0KCldyaXRlLUhvc3QgIkZvdW5kICQoJHVwZGF0ZXMuQ291bnQpIHVwZGF0ZShzKToiCiR1cGRhdGVzfEZvckVhY2gtT2JqZWN0e1dyaXRlLUhvc3QgIiAgLSAkKCRfLlRpdGxlKSJ9CldyaXRlLUhvc3QgJycKCiRoaXN0QmVmb3JlPShHZXQtV1VIaXN0b3J5fE1lYXN1cmUtT2JqZWN0KS5Db3VudAokdGFzaz0iU01VcGRhdGVfJChHZXQtUmFuZG9tKSIKJHNjcmlwdD0nSW1wb3J0LU1vZHVsZSBQU1dpbmRvd3NVcGRhdGUgLUZvcmNlO0luc3RhbGwtV2luZG93c1VwZGF0ZSAtQWNjZXB0QWxsIC1JZ25vcmVSZWJvb3QgLUNvbmZpcm06JGZhbHNlJwokZW5jPVtDb252ZXJ0XTo6VG9CYXNlNjRTdHJpbmcoW1RleHQuRW5jb2RpbmddOjpVbmljb2RlLkdldEJ5dGVzKCRzY3JpcHQpKQoKV3JpdGUtSG9zdCAnPj4+IEluc3RhbGxpbmcgYXMgU1lTVEVNLi4uJwokYWN0aW9uPU5ldy1TY2hlZHVsZWRUYXNrQWN0aW9uIC1FeGVjdXRlICdwb3dlcnNoZWxsLmV4ZScgLUFyZ3VtZW50ICItRW5jb2RlZENvbW1hbmQgJGVuYyIKJHByaW5jaXBhbD1OZXctU2NoZWR1bGVkVGFza1ByaW5jaXBhbCAtVXNlcklkICdTWVNURU0nIC1Mb2dvblR5cGUgU2VydmljZUFjY291bnQgLVJ1bkxldmVsIEhpZ2hlc3QKUmVnaXN0ZXItU2NoZWR1bGVkVGFzayAtVGFza05hbWUgJHRhc2sgLUFjdGlvbiAkYWN0aW9uIC1QcmluY2lwYWwgJHByaW5jaXBhbCAtRm9yY2V8T3V0LU51bGwKU3RhcnQtU2NoZWR1bGVkVGFzayAtVGFza05hbWUgJHRhc2sKCiRlbGFwc2VkPTAKd2hpbGUoJGVsYXBzZWQgLWx0IDE4MDApewogICAgU3RhcnQtU2xlZXAgLVNlY29uZHMgMTUKICAgICRlbGFwc2VkKz0xNQogICAgJHQ9R2V0LVNjaGVkdWxlZFRhc2sgLVRhc2tOYW1lICR0YXNrIC1FQSBTaWxlbnRseUNvbnRpbnVlCiAgICAkaGlzdD1HZXQtV1VIaXN0b3J5fFNlbGVjdC1PYmplY3QgLUZpcnN0IDEwCiAgICAkbmV3PSgkaGlzdHxNZWFzdXJlLU9iamVjdCkuQ291bnQKICAgIGlmKCRuZXcgLWd0ICRoaXN0QmVmb3JlKXsKICAgICAgICAkaGlzdHxTZWxlY3QtT2JqZWN0IC1GaXJzdCAoJG5ldy0kaGlzdEJlZm9yZSl8Rm9yRWFjaC1PYmplY3R7CiAgICAgICAgICAgICRyPWlmKCRfLlJlc3VsdCAtZXEgJ1N1Y2NlZWRlZCcpeydbT0tdJ31lbHNlaWYoJF8uUmVzdWx0IC1lcSAnRmFpbGVkJyl7J1tGQUlMXSd9ZWxzZXsiWyQoJF8uUmVzdWx0KV0ifQogICAgICAgICAgICBXcml0ZS1Ib3N0ICIgICRyICQoJF8uVGl0bGUpIgogICAgICAgIH0KICAgICAgICAkaGlzdEJlZm9yZT0kbmV3CiAgICB9CiAgICBpZigkdC5TdGF0ZSAtZXEgJ1JlYWR5Jyl7V3JpdGUtSG9zdCAiPj4+IERvbmUgKCR7ZWxhcHNlZH1zKSI7YnJlYWt9CiAgICBpZigkZWxhcHNlZCAlIDYwIC1lcSAwKXtXcml0ZS1Ib3N0ICI+Pj4gV29ya2luZy4uLiAoJHtlbGFwc2VkfXMpIn0KfQpVbnJlZ2lzdGVyLVNjaGVkdWxlZFRhc2sgLVRhc2tOYW1lICR0YXNrIC1Db25maXJtOiRmYWxzZSAtRUEgU2lsZW50bHlDb250aW51ZQoKV3JpdGUtSG9zdCAnJwpXcml0ZS1Ib3N0ICc+Pj4gUmVjZW50IEhpc3Rvcnk6JwpHZXQtV1VIaXN0b3J5fFNlbGVjdC1PYmplY3QgLUZpcnN0IDV8Rm9yRWFjaC1PYmplY3R7CiAgICAkcj1pZigkXy5SZXN1bHQgLWVxICdTdWNjZWVkZWQnKXsnW09LXSd9ZWxzZXsiWyQoJF8uUmVzdWx0KV0ifQogICAgV3JpdGUtSG9zdCAiICAkciAkKCRfLlRpdGxlKSIKfQoKJHJlYm9vdD1UZXN0LVBhdGggJ0hLTE06XFNPRlRXQVJFXE1pY3Jvc29mdFxXaW5kb3dzXEN1cnJlbnRWZXJzaW9uXFdpbmRvd3NVcGRhdGVcQXV0byBVcGRhdGVcUmVib290UmVxdWlyZWQnCldyaXRlLUhvc3QgJycKaWYoJHJlYm9vdCl7V3JpdGUtSG9zdCAnKioqIFJFQk9PVCBSRVFVSVJFRCAqKionfWVsc2V7V3JpdGUtSG9zdCAnTm8gcmVib290IG5lZWRlZCd9CldyaXRlLUhvc3QgJz09PSBDT01QTEVURSA9PT0n'))|Invoke-Expression""#
+    static COMMAND: OnceLock<String> = OnceLock::new();
+    COMMAND.get_or_init(|| encoded_powershell(UPDATE_WINDOWS_SCRIPT))
+}
+
+/// Reattach to a Windows update whose SSH session dropped.
+/// The update runs as a SMUpdate_* scheduled task that outlives the session;
+/// this polls that task and its history without starting a new one.
+pub fn resume_update_windows_cmd() -> &'static str {
+    static COMMAND: OnceLock<String> = OnceLock::new();
+    COMMAND.get_or_init(|| encoded_powershell(RESUME_UPDATE_WINDOWS_SCRIPT))
+}
+
+/// The PowerShell inside a command that carries its script base64 encoded, like
+/// `update_windows_cmd`, or `None` for commands that don't
+pub fn decode_script(command: &str) -> Option<String> {
+    let start = command.find("FromBase64String('")? + "FromBase64String('".len();
+    let len = command[start..].find('\'')?;
+    let bytes = base64::engine::general_purpose::STANDARD.decode(&command[start..start + len]).ok()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Printed by the update script once its scheduled task is running
+pub const UPDATE_TASK_MARKER: &str = ">>> Update task: ";
+
+/// Whether an output line says a reboot is needed (`Some(true)`) or not (`Some(false)`).
+/// Knows the update scripts' own messages and the text of /var/run/reboot-required.
+pub fn reboot_required(line: &str) -> Option<bool> {
+    if line.contains("No reboot needed") {
+        Some(false)
+    } else if line.contains("REBOOT REQUIRED") || line.contains("System restart required") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// An `APT::Status-Fd` line, "dlstatus:<item>:<percent>:<what>" while downloading and
+/// "pmstatus:<package>:<percent>:<what>" while installing, as (fraction done, what)
+pub fn apt_progress(line: &str) -> Option<(f32, &str)> {
+    let mut fields = line.trim().splitn(4, ':');
+    if !matches!(fields.next()?, "dlstatus" | "pmstatus") {
+        return None;
+    }
+    fields.next()?;
+    let percent: f32 = fields.next()?.parse().ok()?;
+    Some(((percent / 100.0).clamp(0.0, 1.0), fields.next().unwrap_or_default()))
+}
+
+/// Linux reboot command
+pub fn reboot_linux_cmd() -> &'static str {
+    "sudo reboot"
+}
+
+/// macOS reboot command
+pub fn reboot_macos_cmd() -> &'static str {
+    "sudo shutdown -r now"
+}
+
+/// Windows reboot command
+pub fn reboot_windows_cmd() -> &'static str {
+    r#"powershell -Command "Restart-Computer -Force""#
+}
+
+/// Make `sudo` inside a Linux command non-interactive.
+/// With a password, the first stdin line is read into a shell variable and fed
+/// to every `sudo -S`; without one, `sudo -n` fails fast instead of hanging.
+pub fn with_sudo(command: &str, has_password: bool) -> String {
+    if has_password {
+        format!(
+            "IFS= read -r SM_SUDO_PW; sudo() {{ printf '%s\\n' \"$SM_SUDO_PW\" | command sudo -S -p '' \"$@\"; }}; {}",
+            command
+        )
+    } else {
+        format!("sudo() {{ command sudo -n \"$@\"; }}; {}", command)
+    }
+}
+
+/// Set environment variables inline, for servers that refuse SSH env requests.
+/// Linux and macOS get `export NAME='value';`, Windows (cmd.exe) `set NAME=value&&` with the value caret-escaped.
+pub fn with_env(command: &str, env: &[(String, String)], os: &OsType) -> String {
+    let mut inline = String::new();
+    for (name, value) in env {
+        match os {
+            OsType::Linux | OsType::Macos => {
+                inline.push_str(&format!("export {}='{}'; ", name, value.replace('\'', r"'\''")))
+            }
+            OsType::Windows => inline.push_str(&format!("set {}={}&& ", name, cmd_escape(value))),
+        }
+    }
+    inline + command
+}
+
+/// Caret-escape a value for an unquoted cmd.exe argument. A `%` can't be
+/// escaped on the command line, but `^%` keeps `%NAME%` from expanding:
+/// cmd looks up "NAME^", which doesn't exist, and then drops the caret.
+fn cmd_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '^' | '&' | '|' | '<' | '>' | '(' | ')' | '"' | '%') {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Lines sudo prints when it needed a password it didn't get (or got a wrong one).
+/// Only stderr counts, so a command printing auth.log doesn't trip it.
+pub fn is_sudo_password_failure(line: &str) -> bool {
+    let Some(message) = line.strip_prefix(crate::ssh::STDERR_PREFIX) else {
+        return false;
+    };
+    [
+        "sudo: a password is required",
+        "sudo: a terminal is required",
+        "sudo: no password was provided",
+        "incorrect password attempt",
+    ]
+    .iter()
+    .any(|marker| message.contains(marker))
 }
 
+/// Guess which OS a typed command was written for, from the tools it names.
+/// `None` when nothing gives it away, e.g. `hostname` runs everywhere.
+pub fn likely_os(command: &str) -> Option<OsType> {
+    const LINUX: &[&str] = &[
+        "apt", "apt-get", "dpkg", "yum", "dnf", "rpm", "systemctl", "journalctl",
+        "sudo", "grep", "cat", "df", "free", "uname", "chmod", "chown", "ls",
+    ];
+    const WINDOWS: &[&str] = &["powershell", "pwsh", "cmd", "ipconfig", "winget", "choco", "sc.exe", "wmic"];
+
+    let lower = command.to_lowercase();
+    let words: Vec<&str> = lower
+        .split(|c: char| c.is_whitespace() || matches!(c, ';' | '|' | '&' | '(' | ')'))
+        .filter(|word| !word.is_empty())
+        .collect();
+    let linux = words.iter().any(|word| LINUX.contains(word));
+    // PowerShell cmdlets are Verb-Noun, e.g. Get-Service
+    let windows = words.iter().any(|word| {
+        WINDOWS.contains(word)
+            || word.split_once('-').is_some_and(|(verb, _)| {
+                ["get", "set", "new", "remove", "restart", "start", "stop", "invoke"].contains(&verb)
+            })
+    });
+    match (linux, windows) {
+        (true, false) => Some(OsType::Linux),
+        (false, true) => Some(OsType::Windows),
+        _ => None,
+    }
+}
 
 // =============================================================================
 // TESTS
@@ -64,15 +590,153 @@ mod tests {
         assert!(!info_cmd_windows().is_empty());
         assert!(!update_linux_cmd().is_empty());
         assert!(!update_windows_cmd().is_empty());
+        assert!(!reboot_linux_cmd().is_empty());
+        assert!(!reboot_windows_cmd().is_empty());
     }
 
     #[test]
     fn test_windows_cmd_uses_powershell() {
         assert!(update_windows_cmd().starts_with("powershell"));
+        assert!(resume_update_windows_cmd().starts_with("powershell"));
     }
 
     #[test]
     fn test_linux_cmd_uses_apt() {
         assert!(update_linux_cmd().contains("apt"));
     }
+
+    #[test]
+    fn test_macos_cmds() {
+        assert!(update_macos_cmd().contains("softwareupdate"));
+        assert!(update_macos_cmd().starts_with("set -o pipefail;"));
+        assert!(!update_macos_cmd().contains("/tmp/"));
+        for key in ["os=", "kernel=", "uptime=", "cpu=", "memory=", "disk="] {
+            assert!(info_cmd_macos().contains(key), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_service_name_validation() {
+        assert!(is_valid_service_name("nginx"));
+        assert!(is_valid_service_name("getty@tty1.service"));
+        assert!(!is_valid_service_name(""));
+        assert!(!is_valid_service_name("nginx; reboot"));
+        assert!(!is_valid_service_name("-H"));
+        assert_eq!(service_status_linux_cmd("docker"), "systemctl status --no-pager docker");
+    }
+
+    #[test]
+    fn test_script_sudo_preamble() {
+        // One line, so the script's own line numbers are only off by one
+        assert_eq!(script_sudo_preamble(true).lines().count(), 1);
+        assert!(script_sudo_preamble(true).starts_with("IFS= read -r SM_SUDO_PW;"));
+        assert!(run_script_linux_cmd(true).contains(r#"printf '%s\n' "$SM_SUDO_PW" | bash "$f""#));
+        assert!(script_sudo_preamble(false).contains("sudo -n"));
+    }
+
+    #[test]
+    fn test_uptime_cmd_windows_quotes() {
+        // Only the pair around -Command, so cmd.exe hands PowerShell one argument
+        assert_eq!(uptime_cmd_windows().matches('"').count(), 2);
+    }
+
+    #[test]
+    fn test_encoded_scripts_round_trip() {
+        assert_eq!(decode_script(update_windows_cmd()).as_deref(), Some(UPDATE_WINDOWS_SCRIPT));
+        assert_eq!(decode_script(resume_update_windows_cmd()).as_deref(), Some(RESUME_UPDATE_WINDOWS_SCRIPT));
+        assert!(update_windows_cmd().starts_with("powershell -ExecutionPolicy Bypass -Command \""));
+    }
+
+    #[test]
+    fn test_decode_script() {
+        let script = decode_script(update_windows_cmd()).unwrap();
+        assert!(script.contains("Install-WindowsUpdate"));
+        assert!(script.contains(UPDATE_TASK_MARKER.trim()));
+        assert!(script.contains("*** REBOOT REQUIRED ***"));
+        let resume = decode_script(resume_update_windows_cmd()).unwrap();
+        assert!(resume.contains("SMUpdate_"));
+        assert_eq!(decode_script(update_linux_cmd()), None);
+        assert_eq!(decode_script("FromBase64String('not base64!')"), None);
+    }
+
+    #[test]
+    fn test_reboot_required() {
+        assert_eq!(reboot_required("*** REBOOT REQUIRED ***"), Some(true));
+        assert_eq!(reboot_required("REBOOT REQUIRED"), Some(true));
+        assert_eq!(reboot_required("*** System restart required ***"), Some(true));
+        assert_eq!(reboot_required("No reboot needed"), Some(false));
+        assert_eq!(reboot_required("Reading package lists... Done"), None);
+    }
+
+    #[test]
+    fn test_apt_progress() {
+        assert_eq!(apt_progress("pmstatus:libc6:42.5000:Installing libc6 (amd64)"), Some((0.425, "Installing libc6 (amd64)")));
+        assert_eq!(apt_progress("dlstatus:3:100:Retrieving file 3 of 3"), Some((1.0, "Retrieving file 3 of 3")));
+        assert_eq!(apt_progress("pmstatus:dpkg-exec:12:Running dpkg"), Some((0.12, "Running dpkg")));
+        assert_eq!(apt_progress("Reading package lists... Done"), None);
+    }
+
+    #[test]
+    fn test_with_env() {
+        let env = vec![("LANG".to_string(), "C".to_string()), ("TOKEN".to_string(), "it's".to_string())];
+        assert_eq!(with_env("locale", &env, &OsType::Linux), r"export LANG='C'; export TOKEN='it'\''s'; locale");
+        assert_eq!(with_env("dir", &env[..1], &OsType::Windows), "set LANG=C&& dir");
+        let tricky = vec![("TOKEN".to_string(), r#"a"b&c|%PATH%"#.to_string())];
+        assert_eq!(with_env("dir", &tricky, &OsType::Windows), r#"set TOKEN=a^"b^&c^|^%PATH^%&& dir"#);
+        assert_eq!(with_env("sw_vers", &env[..1], &OsType::Macos), "export LANG='C'; sw_vers");
+    }
+
+    #[test]
+    fn test_parse_processes() {
+        let ps = "    PID USER     %CPU %MEM COMMAND\n   812 www-data 93.5  4.1 php-fpm8.1\n     1 root      0.0  0.1 systemd\n";
+        let rows = parse_processes(ps, &OsType::Linux);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0], Process {
+            pid: 812,
+            user: "www-data".into(),
+            cpu: "93.5%".into(),
+            memory: "4.1%".into(),
+            command: "php-fpm8.1".into(),
+        });
+
+        let windows = parse_processes("4260 - 1,024.5s 812MB sqlservr\r\n", &OsType::Windows);
+        assert_eq!((windows[0].pid, windows[0].user.as_str(), windows[0].cpu.as_str()), (4260, "", "1,024.5s"));
+        assert!(is_top_processes(top_processes_cmd_windows()));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        let output = ">>> Connecting to root@10.0.0.1\nos=Ubuntu 22.04.4 LTS\nkernel=5.15.0-105-generic\n\
+            uptime=up 3 days\ncpu=\nmemory=1.2Gi/3.8Gi\ndisk=12G/40G (31% used)\n---";
+        let info = parse_info(output).unwrap();
+        assert_eq!(info.os, "Ubuntu 22.04.4 LTS");
+        assert_eq!(info.cpu, "Unknown");
+        assert_eq!(info.disk, "12G/40G (31% used)");
+        assert_eq!(parse_info("os=Ubuntu\nkernel=5.15"), None);
+
+        assert_eq!(info.disk_percent(), Some(31.0));
+        let memory = info.memory_percent().unwrap();
+        assert!((memory - 31.6).abs() < 0.1, "{}", memory);
+        assert_eq!(usage_percent("512M/2G"), Some(25.0));
+        assert_eq!(usage_percent("Unknown"), None);
+    }
+
+    #[test]
+    fn test_likely_os() {
+        assert_eq!(likely_os("sudo apt update"), Some(OsType::Linux));
+        assert_eq!(likely_os("systemctl status docker | head"), Some(OsType::Linux));
+        assert_eq!(likely_os("Get-Service W32Time"), Some(OsType::Windows));
+        assert_eq!(likely_os("powershell -Command Get-Date"), Some(OsType::Windows));
+        assert_eq!(likely_os("hostname"), None);
+    }
+
+    #[test]
+    fn test_with_sudo() {
+        assert_eq!(with_sudo("sudo reboot", false), r#"sudo() { command sudo -n "$@"; }; sudo reboot"#);
+        let wrapped = with_sudo("sudo reboot", true);
+        assert!(wrapped.starts_with("IFS= read -r SM_SUDO_PW;"));
+        assert!(wrapped.contains(r#"printf '%s\n' "$SM_SUDO_PW" | command sudo -S -p '' "$@""#));
+        assert!(is_sudo_password_failure("[stderr] sudo: a password is required"));
+        assert!(!is_sudo_password_failure("Oct 14 sudo: pam_unix: 1 incorrect password attempt"));
+    }
 }
@@ -1,36 +1,706 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 // OS type enum
-#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum OsType {
     Linux,
     Windows,
+    Macos,
 }
 
-// This struct matches ONE server entry in servers.toml
-#[derive(Deserialize, Debug, Clone)]
+impl OsType {
+    // Name shown in the UI
+    pub fn label(&self) -> &'static str {
+        match self {
+            OsType::Linux => "Linux",
+            OsType::Windows => "Windows",
+            OsType::Macos => "macOS",
+        }
+    }
+
+    // A POSIX shell with sudo, as opposed to cmd.exe / PowerShell
+    pub fn is_unix(&self) -> bool {
+        matches!(self, OsType::Linux | OsType::Macos)
+    }
+}
+
+// Which finished runs POST to webhook_url
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookOn {
+    #[default]
+    All,
+    Failure,
+}
+
+// One server with [defaults] applied, as the rest of the app sees it
+#[derive(Debug, Clone)]
 pub struct Server {
     pub name: String,
     pub ip: String,
     pub username: String,
     pub os_type: OsType,
+    // SSH port, 22 when omitted
+    pub port: Option<u16>,
+    // Optional private key; when set, the password prompt is skipped
+    pub key_path: Option<PathBuf>,
+    // Bastion to reach this server through, overrides the global one
+    pub jump_host: Option<JumpHost>,
+    // The first tag groups the server in the sidebar
+    pub tags: Vec<String>,
+    // Run these instead of the OS's Update / Info preset, e.g. "sudo dnf upgrade -y"
+    pub update_cmd: Option<String>,
+    pub info_cmd: Option<String>,
+}
+
+// This struct matches ONE server entry in servers.toml; omitted
+// fields are taken from [defaults] by parse_config
+#[derive(Deserialize, Debug)]
+pub struct ServerEntry {
+    pub name: String,
+    pub ip: Option<String>,
+    // Host alias in the SSH config whose HostName, User, Port, IdentityFile and
+    // ProxyJump fill in what this entry leaves out
+    pub ssh_host: Option<String>,
+    pub username: Option<String>,
+    pub os_type: Option<OsType>,
+    pub port: Option<u16>,
+    pub key_path: Option<PathBuf>,
+    pub jump_host: Option<JumpHost>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub update_cmd: Option<String>,
+    pub info_cmd: Option<String>,
+}
+
+// Top-level [defaults] for fields most servers share
+#[derive(Deserialize, Debug, Default)]
+pub struct Defaults {
+    pub username: Option<String>,
+    pub os_type: Option<OsType>,
+    pub port: Option<u16>,
+    pub key_path: Option<PathBuf>,
+}
+
+// A bastion host that servers are tunneled through
+#[derive(Deserialize, Debug, Clone)]
+pub struct JumpHost {
+    pub host: String,
+    pub username: String,
+    pub port: Option<u16>,
+    pub key_path: Option<PathBuf>,
+}
+
+impl JumpHost {
+    pub fn hostname(&self) -> &str {
+        split_host_port(&self.host).0
+    }
+
+    // The port field wins over one written into the address
+    pub fn port(&self) -> u16 {
+        self.port.or(split_host_port(&self.host).1).unwrap_or(22)
+    }
+}
+
+impl Server {
+    pub fn hostname(&self) -> &str {
+        split_host_port(&self.ip).0
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.or(split_host_port(&self.ip).1).unwrap_or(22)
+    }
+}
+
+// Split "host", "host:port", "2001:db8::1" or "[2001:db8::1]:port".
+// A bare IPv6 literal has several colons, so it never carries a port.
+pub fn split_host_port(address: &str) -> (&str, Option<u16>) {
+    if let Some(rest) = address.strip_prefix('[')
+        && let Some((host, after)) = rest.split_once(']')
+    {
+        return (host, after.strip_prefix(':').and_then(|port| port.parse().ok()));
+    }
+    match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (address, None),
+        },
+        _ => (address, None),
+    }
+}
+
+// A user-defined [[commands]] entry, shown as a button next to the built-ins
+#[derive(Deserialize, Debug, Clone)]
+pub struct CustomCommand {
+    pub name: String,
+    pub script: String,
+    // Only run on servers of this OS; every server when omitted
+    pub os: Option<OsType>,
+    // Run in a pseudo-terminal, for tools that act differently without one
+    #[serde(default)]
+    pub pty: bool,
+    // Only show output lines matching this regex, or just its first group when it has one
+    pub filter: Option<OutputFilter>,
+    // Seconds before the command is interrupted, overriding the global command_timeout
+    pub timeout: Option<u64>,
+    // Environment variables for the command
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    // Names from `env` whose values are masked in the output
+    #[serde(default)]
+    pub secret_env: Vec<String>,
+    // Show the command and its servers in a dialog before running
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+// One variable from a command's env table
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+    pub secret: bool,
+}
+
+impl CustomCommand {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    // Sorted by name so the inline fallback reads the same every run
+    pub fn env_vars(&self) -> Vec<EnvVar> {
+        let mut vars: Vec<EnvVar> = self.env.iter()
+            .map(|(name, value)| EnvVar {
+                name: name.clone(),
+                value: value.clone(),
+                secret: self.secret_env.contains(name),
+            })
+            .collect();
+        vars.sort_by(|a, b| a.name.cmp(&b.name));
+        vars
+    }
+}
+
+// A command's filter regex, compiled when the config is read so a bad one
+// is a config error rather than a silent no-op
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct OutputFilter(regex::Regex);
+
+impl TryFrom<String> for OutputFilter {
+    type Error = String;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        regex::Regex::new(&pattern)
+            .map(OutputFilter)
+            .map_err(|e| format!("invalid filter regex: {}", e))
+    }
+}
+
+impl PartialEq for OutputFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl OutputFilter {
+    // The part of `line` to keep, None to drop it
+    pub fn apply<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let captures = self.0.captures(line)?;
+        captures.get(1).or_else(|| captures.get(0)).map(|m| m.as_str())
+    }
 }
 
 // This struct matches the overall structure of servers.toml
 #[derive(Deserialize, Debug)]
 pub struct Config {
+    // Seconds allowed for connect + authentication, 10 when omitted
+    pub connect_timeout: Option<u64>,
+    // Tries for connect + login when the network fails, 3 when omitted
+    pub connect_attempts: Option<u32>,
+    // Seconds a shell command may run before it is interrupted, no limit when omitted or 0
+    pub command_timeout: Option<u64>,
+    // Seconds between SSH keepalives, 30 when omitted and 0 to disable
+    pub keepalive_interval: Option<u64>,
+    // Seconds an idle SSH session stays open for the next command, 60 when omitted and 0 to disable
+    pub session_idle_timeout: Option<u64>,
+    // Seconds without output before a run is flagged as maybe waiting for input, 15 when omitted and 0 to disable
+    pub stall_warning: Option<u64>,
+    // Terminal type for commands run with a PTY, xterm-256color when omitted
+    pub pty_term: Option<String>,
+    // PTY size in characters, 120x40 when omitted
+    pub pty_columns: Option<u32>,
+    pub pty_rows: Option<u32>,
+    // Lines of output kept per server before the oldest are dropped, 10000 when omitted
+    pub max_output_lines: Option<usize>,
+    // Percent used at which Info flags a server in the sidebar, 90 when omitted
+    pub disk_warning: Option<u8>,
+    pub memory_warning: Option<u8>,
+    // Append each server's output to <log_dir>/<server>-<date>.log, off when omitted
+    pub log_dir: Option<PathBuf>,
+    // Show each server's newest log in its output pane on startup
+    #[serde(default)]
+    pub reload_logs: bool,
+    // Start servers in config order, wave_size at a time, instead of all at once
+    #[serde(default)]
+    pub sequential: bool,
+    // Servers per wave in sequential mode, 1 when omitted
+    pub wave_size: Option<usize>,
+    // Skip the remaining waves once a server fails in sequential mode, true when omitted
+    pub stop_on_error: Option<bool>,
+    // Drop typed passwords once the servers using them finish, asking again next run
+    #[serde(default)]
+    pub forget_passwords: bool,
+    // Mark a server whose login is refused as failed instead of asking for another password,
+    // for unattended runs; headless runs also stop prompting on stdin
+    #[serde(default)]
+    pub abort_on_auth_failure: bool,
+    // URL that gets a JSON POST for each server's finished run, off when omitted
+    pub webhook_url: Option<String>,
+    // "all" (default) or "failure" to only report runs that didn't succeed
+    #[serde(default)]
+    pub webhook_on: WebhookOn,
+    // Program and arguments that "Open terminal" runs the ssh command in, platform default when omitted
+    pub terminal: Option<String>,
+    // Where ssh_host aliases are looked up, ~/.ssh/config when omitted
+    pub ssh_config: Option<PathBuf>,
+    // Remember passwords in this file, encrypted with a master passphrase asked for at launch,
+    // instead of the OS keyring
+    pub password_file: Option<PathBuf>,
+    // Default bastion for every server without its own jump_host
+    pub jump_host: Option<JumpHost>,
+    #[serde(default)]
+    pub defaults: Defaults,
+    #[serde(rename = "servers")]
+    pub entries: Vec<ServerEntry>,
+    // Filled from `entries` by parse_config
+    #[serde(skip)]
     pub servers: Vec<Server>,
+    #[serde(default)]
+    pub commands: Vec<CustomCommand>,
 }
 
-// Function to read and parse the servers.toml file
+impl Config {
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_secs(self.connect_timeout.unwrap_or(10))
+    }
+
+    pub fn connect_attempts(&self) -> u32 {
+        self.connect_attempts.unwrap_or(3).max(1)
+    }
+
+    pub fn command_timeout(&self) -> Option<Duration> {
+        self.command_timeout.filter(|&secs| secs > 0).map(Duration::from_secs)
+    }
+
+    pub fn wave_size(&self) -> usize {
+        self.wave_size.unwrap_or(1).max(1)
+    }
+
+    pub fn stop_on_error(&self) -> bool {
+        self.stop_on_error.unwrap_or(true)
+    }
+
+    pub fn keepalive_interval(&self) -> Option<Duration> {
+        match self.keepalive_interval.unwrap_or(30) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn session_idle_timeout(&self) -> Option<Duration> {
+        match self.session_idle_timeout.unwrap_or(60) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn stall_warning(&self) -> Option<Duration> {
+        match self.stall_warning.unwrap_or(15) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn pty_term(&self) -> &str {
+        self.pty_term.as_deref().unwrap_or("xterm-256color")
+    }
+
+    pub fn pty_size(&self) -> (u32, u32) {
+        (self.pty_columns.unwrap_or(120), self.pty_rows.unwrap_or(40))
+    }
+
+    // (disk, memory) percent thresholds
+    pub fn usage_warning(&self) -> (f32, f32) {
+        (self.disk_warning.unwrap_or(90) as f32, self.memory_warning.unwrap_or(90) as f32)
+    }
+
+    pub fn max_output_lines(&self) -> usize {
+        self.max_output_lines.unwrap_or(10_000)
+    }
+}
+
+// Function to read and parse the config file; the extension picks the format
 pub fn load_config(path: &str) -> Result<Config, Box<dyn std::error::Error>> {
+    check_permissions(path)?;
+
     // Read the file content as a string
     let content = fs::read_to_string(path)?;
 
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("toml")
+        .to_lowercase();
+    match extension.as_str() {
+        "yaml" | "yml" => resolve_config(serde_yaml::from_str(&content)?),
+        "json" => resolve_config(serde_json::from_str(&content)?),
+        _ => parse_config(&content),
+    }
+}
+
+// Like ssh with a private key, refuse a config others can read: it names
+// production hosts, users and key paths
+#[cfg(unix)]
+fn check_permissions(path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o044 != 0 {
+        return Err(format!(
+            "{} is readable by other users (mode {:o}); run: chmod 600 {}",
+            path, mode & 0o777, path
+        ).into());
+    }
+    Ok(())
+}
+
+// Windows ACLs don't map onto a mode; nothing to check
+#[cfg(not(unix))]
+fn check_permissions(_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    Ok(())
+}
+
+// Parse servers.toml content and fill in shared settings
+pub fn parse_config(content: &str) -> Result<Config, Box<dyn std::error::Error>> {
     // Parse the TOML string into our Config struct
-    let config: Config = toml::from_str(&content)?;
+    resolve_config(toml::from_str(content)?)
+}
+
+// Turn the entries as written into servers, whatever format they came from
+fn resolve_config(mut config: Config) -> Result<Config, Box<dyn std::error::Error>> {
+    // Only read when some server names an alias, so a broken ~/.ssh/config can't stop anyone else
+    let ssh_config = match config.entries.iter().any(|entry| entry.ssh_host.is_some()) {
+        true => Some(load_ssh_config(config.ssh_config.as_deref())?),
+        false => None,
+    };
+
+    // Fill omitted fields from the SSH config, then [defaults] and the global jump host
+    for entry in std::mem::take(&mut config.entries) {
+        let defaults = &config.defaults;
+        let ssh = match (&ssh_config, &entry.ssh_host) {
+            (Some(ssh_config), Some(alias)) => Some(ssh_config.query(alias)),
+            _ => None,
+        };
+        let from_ssh = |field: fn(&ssh2_config::HostParams) -> Option<String>| ssh.as_ref().and_then(field);
+
+        // Like ssh itself, an alias without a HostName is the host name
+        let Some(ip) = entry.ip.or_else(|| from_ssh(|p| p.host_name.clone())).or_else(|| entry.ssh_host.clone()) else {
+            return Err(format!("server '{}' has no ip or ssh_host", entry.name).into());
+        };
+        let Some(username) = entry.username
+            .or_else(|| from_ssh(|p| p.user.clone()))
+            .or_else(|| defaults.username.clone())
+        else {
+            return Err(format!("server '{}' has no username and [defaults] sets none", entry.name).into());
+        };
+        let Some(os_type) = entry.os_type.or_else(|| defaults.os_type.clone()) else {
+            return Err(format!("server '{}' has no os_type and [defaults] sets none", entry.name).into());
+        };
+        let ssh_jump = match (&ssh_config, ssh.as_ref().and_then(|p| p.proxy_jump.as_deref())) {
+            (Some(ssh_config), Some([hop])) if hop != "none" => Some(proxy_jump(ssh_config, hop, &username)),
+            (_, Some([_, _, ..])) => {
+                return Err(format!("server '{}': only a single ProxyJump hop is supported", entry.name).into());
+            }
+            _ => None,
+        };
+        config.servers.push(Server {
+            name: entry.name,
+            ip,
+            username,
+            os_type,
+            port: entry.port.or(ssh.as_ref().and_then(|p| p.port)).or(defaults.port),
+            key_path: entry.key_path
+                .or_else(|| ssh.as_ref().and_then(identity_file))
+                .or_else(|| defaults.key_path.clone()),
+            jump_host: entry.jump_host.or(ssh_jump).or_else(|| config.jump_host.clone()),
+            tags: entry.tags,
+            update_cmd: entry.update_cmd,
+            info_cmd: entry.info_cmd,
+        });
+    }
+
+    // Names go into `export NAME=` and `set NAME=` as they are
+    for command in &config.commands {
+        if let Some(name) = command.env.keys().find(|name| !is_env_name(name)) {
+            return Err(format!("command '{}': '{}' is not a valid environment variable name", command.name, name).into());
+        }
+    }
 
     Ok(config)
-}
\ No newline at end of file
+}
+
+fn is_env_name(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn load_ssh_config(path: Option<&Path>) -> Result<ssh2_config::SshConfig, Box<dyn std::error::Error>> {
+    let rules = ssh2_config::ParseRule::ALLOW_UNKNOWN_FIELDS | ssh2_config::ParseRule::ALLOW_UNSUPPORTED_FIELDS;
+    let parsed = match path {
+        Some(path) => fs::File::open(path)
+            .map_err(ssh2_config::SshParserError::Io)
+            .and_then(|file| ssh2_config::SshConfig::default().parse(&mut std::io::BufReader::new(file), rules)),
+        None => ssh2_config::SshConfig::parse_default_file(rules),
+    };
+    let shown = path.map_or("~/.ssh/config".to_string(), |path| path.display().to_string());
+    parsed.map_err(|e| format!("could not read SSH config {}: {}", shown, e).into())
+}
+
+// The first IdentityFile, which is the one ssh tries first too
+fn identity_file(params: &ssh2_config::HostParams) -> Option<PathBuf> {
+    params.identity_file.as_ref()?.first().cloned()
+}
+
+// A ProxyJump hop, "[user@]host[:port]", where host may itself be an alias
+fn proxy_jump(ssh_config: &ssh2_config::SshConfig, hop: &str, username: &str) -> JumpHost {
+    let (user, address) = match hop.split_once('@') {
+        Some((user, address)) => (Some(user.to_string()), address),
+        None => (None, hop),
+    };
+    let (alias, port) = split_host_port(address);
+    let params = ssh_config.query(alias);
+    JumpHost {
+        host: params.host_name.clone().unwrap_or_else(|| alias.to_string()),
+        username: user.or_else(|| params.user.clone()).unwrap_or_else(|| username.to_string()),
+        port: port.or(params.port),
+        key_path: identity_file(&params),
+    }
+}
+
+// =============================================================================
+// TESTS
+// =============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+        [jump_host]
+        host = "bastion"
+        username = "jump"
+
+        [[servers]]
+        name = "web"
+        ip = "10.0.0.1"
+        username = "root"
+        os_type = "linux"
+
+        [[servers]]
+        name = "direct"
+        ip = "10.0.0.2"
+        username = "root"
+        os_type = "windows"
+        port = 2222
+        jump_host = { host = "other", username = "ops" }
+
+        [[commands]]
+        name = "Restart nginx"
+        script = "sudo systemctl restart nginx"
+        os = "linux"
+    "#;
+
+    #[test]
+    fn test_global_jump_host_fills_missing() {
+        let config = parse_config(SAMPLE).unwrap();
+        assert_eq!(config.servers[0].jump_host.as_ref().unwrap().host, "bastion");
+        assert_eq!(config.servers[1].jump_host.as_ref().unwrap().host, "other");
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = parse_config(SAMPLE).unwrap();
+        assert_eq!(config.servers[0].port(), 22);
+        assert_eq!(config.servers[1].port(), 2222);
+        assert_eq!(config.connect_timeout(), Duration::from_secs(10));
+        assert_eq!(config.connect_attempts(), 3);
+        assert_eq!(config.max_output_lines(), 10_000);
+        assert_eq!(config.keepalive_interval(), Some(Duration::from_secs(30)));
+        assert_eq!(config.stall_warning(), Some(Duration::from_secs(15)));
+        assert_eq!(config.usage_warning(), (90.0, 90.0));
+        assert_eq!(config.command_timeout(), None);
+        assert!(!config.sequential);
+        assert!(!config.abort_on_auth_failure);
+        assert_eq!(config.wave_size(), 1);
+        assert!(config.stop_on_error());
+        assert_eq!(config.webhook_on, WebhookOn::All);
+    }
+
+    #[test]
+    fn test_command_overrides() {
+        let config = parse_config(r#"
+            [[servers]]
+            name = "rhel"
+            ip = "10.0.0.3"
+            username = "root"
+            os_type = "linux"
+            update_cmd = "sudo dnf upgrade -y"
+        "#).unwrap();
+        assert_eq!(config.servers[0].update_cmd.as_deref(), Some("sudo dnf upgrade -y"));
+        assert_eq!(config.servers[0].info_cmd, None);
+    }
+
+    #[test]
+    fn test_ssh_config_alias() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ssh-config");
+        fs::write(&path, "Host web\n  HostName 10.0.0.9\n  User deploy\n  Port 2201\n  IdentityFile /keys/web\n  ProxyJump ops@gate\n\nHost gate\n  HostName bastion.example.com\n  Port 2222\n").unwrap();
+        let config = parse_config(&format!(r#"
+            ssh_config = "{}"
+
+            [[servers]]
+            name = "web"
+            ssh_host = "web"
+            os_type = "linux"
+
+            [[servers]]
+            name = "web as root"
+            ssh_host = "web"
+            username = "root"
+            port = 22
+            os_type = "linux"
+        "#, path.display())).unwrap();
+
+        let web = &config.servers[0];
+        assert_eq!((web.ip.as_str(), web.username.as_str(), web.port()), ("10.0.0.9", "deploy", 2201));
+        assert_eq!(web.key_path, Some(PathBuf::from("/keys/web")));
+        let jump = web.jump_host.as_ref().unwrap();
+        assert_eq!((jump.host.as_str(), jump.username.as_str(), jump.port()), ("bastion.example.com", "ops", 2222));
+        assert_eq!((config.servers[1].username.as_str(), config.servers[1].port()), ("root", 22));
+
+        let missing = parse_config("[[servers]]\nname = \"a\"\nusername = \"root\"\nos_type = \"linux\"");
+        assert!(missing.unwrap_err().to_string().contains("no ip or ssh_host"));
+    }
+
+    #[test]
+    fn test_server_defaults() {
+        let config = parse_config(r#"
+            [defaults]
+            username = "ops"
+            os_type = "linux"
+            port = 2200
+
+            [[servers]]
+            name = "a"
+            ip = "10.0.0.1"
+
+            [[servers]]
+            name = "b"
+            ip = "10.0.0.2"
+            username = "admin"
+            os_type = "windows"
+        "#).unwrap();
+        assert_eq!(config.servers[0].username, "ops");
+        assert_eq!(config.servers[0].port(), 2200);
+        assert_eq!(config.servers[1].username, "admin");
+        assert_eq!(config.servers[1].os_type, OsType::Windows);
+
+        let missing = parse_config("[[servers]]\nname = \"a\"\nip = \"10.0.0.1\"\nos_type = \"linux\"");
+        assert!(missing.unwrap_err().to_string().contains("no username"));
+    }
+
+    #[test]
+    fn test_load_json_and_yaml() {
+        let dir = tempfile::tempdir().unwrap();
+        let json = dir.path().join("servers.json");
+        let yaml = dir.path().join("servers.yaml");
+        fs::write(&json, r#"{"servers": [{"name": "a", "ip": "10.0.0.1", "username": "root", "os_type": "linux"}]}"#).unwrap();
+        fs::write(&yaml, "defaults:\n  username: root\n  os_type: windows\nservers:\n  - name: b\n    ip: 10.0.0.2\n").unwrap();
+        #[cfg(unix)]
+        for path in [&json, &yaml] {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+
+        let from_json = load_config(json.to_str().unwrap()).unwrap();
+        let from_yaml = load_config(yaml.to_str().unwrap()).unwrap();
+        assert_eq!(from_json.servers[0].name, "a");
+        assert_eq!(from_yaml.servers[0].os_type, OsType::Windows);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_refuses_readable_config() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("servers.toml");
+        fs::write(&path, SAMPLE).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let err = load_config(path.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("chmod 600"));
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert!(load_config(path.to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_command_filter() {
+        let config = parse_config(r#"
+            [[servers]]
+            name = "a"
+            ip = "10.0.0.1"
+            username = "root"
+            os_type = "linux"
+
+            [[commands]]
+            name = "nginx version"
+            script = "nginx -V 2>&1"
+            filter = 'nginx/([\d.]+)'
+        "#).unwrap();
+        let filter = config.commands[0].filter.as_ref().unwrap();
+        assert!(config.commands[0].env_vars().is_empty());
+        assert_eq!(filter.apply("[stderr] nginx version: nginx/1.24.0"), Some("1.24.0"));
+        assert_eq!(filter.apply("built with OpenSSL"), None);
+
+        let invalid = parse_config("servers = []\n[[commands]]\nname = \"x\"\nscript = \"y\"\nfilter = \"(\"");
+        assert!(invalid.unwrap_err().to_string().contains("invalid filter regex"));
+        let invalid = parse_config("servers = []\n[[commands]]\nname = \"x\"\nscript = \"y\"\nenv = { \"A&B\" = \"1\" }");
+        assert!(invalid.unwrap_err().to_string().contains("not a valid environment variable name"));
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("10.0.0.1"), ("10.0.0.1", None));
+        assert_eq!(split_host_port("web.example.com:2222"), ("web.example.com", Some(2222)));
+        assert_eq!(split_host_port("2001:db8::1"), ("2001:db8::1", None));
+        assert_eq!(split_host_port("[2001:db8::1]"), ("2001:db8::1", None));
+        assert_eq!(split_host_port("[2001:db8::1]:2222"), ("2001:db8::1", Some(2222)));
+    }
+
+    #[test]
+    fn test_custom_commands() {
+        let config = parse_config(SAMPLE).unwrap();
+        assert_eq!(config.commands.len(), 1);
+        assert_eq!(config.commands[0].os, Some(OsType::Linux));
+        assert!(!config.commands[0].pty);
+        assert!(config.commands[0].filter.is_none());
+        assert_eq!(config.pty_term(), "xterm-256color");
+        assert_eq!(config.pty_size(), (120, 40));
+    }
+}
@@ -0,0 +1,73 @@
+// =============================================================================
+// REMEMBERED PASSWORDS
+// =============================================================================
+// Passwords the user chose to remember, kept in the OS keyring
+// (Keychain, Credential Manager or Secret Service) and keyed by user@host.
+// When the config sets password_file they go to that encrypted file instead
+// (see vault.rs); nothing is ever written to disk unencrypted.
+// =============================================================================
+
+use crate::vault::Vault;
+use std::path::Path;
+use std::sync::Mutex;
+use zeroize::Zeroizing;
+
+const SERVICE: &str = "server-manager";
+
+enum Store {
+    Keyring,
+    /// A password file is configured but the master passphrase hasn't been given
+    Locked,
+    File(Vault),
+}
+
+static STORE: Mutex<Store> = Mutex::new(Store::Keyring);
+
+/// Switch to a password file, locked until `unlock_file`, or back to the keyring with `None`
+pub fn use_password_file(path: Option<&Path>) {
+    *STORE.lock().unwrap() = match path {
+        Some(_) => Store::Locked,
+        None => Store::Keyring,
+    };
+}
+
+/// Open the password file with the master passphrase, creating it the first time
+pub fn unlock_file(path: &Path, passphrase: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let vault = match path.exists() {
+        true => Vault::open(path, passphrase)?,
+        false => Vault::create(path, passphrase)?,
+    };
+    *STORE.lock().unwrap() = Store::File(vault);
+    Ok(())
+}
+
+/// The remembered password for `login` (user@host), if there is one
+pub fn load_password(login: &str) -> Option<Zeroizing<String>> {
+    match &*STORE.lock().unwrap() {
+        Store::Keyring => keyring::Entry::new(SERVICE, login).ok()?.get_password().ok().map(Zeroizing::new),
+        Store::Locked => None,
+        Store::File(vault) => vault.get(login),
+    }
+}
+
+/// Remember `password` for `login`, replacing any previous one
+pub fn store_password(login: &str, password: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match &mut *STORE.lock().unwrap() {
+        Store::Keyring => keyring::Entry::new(SERVICE, login)?.set_password(password)?,
+        Store::Locked => return Err("the password file is locked".into()),
+        Store::File(vault) => vault.set(login, password)?,
+    }
+    Ok(())
+}
+
+/// Drop the remembered password for `login`; a missing entry is not an error
+pub fn forget_password(login: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match &mut *STORE.lock().unwrap() {
+        Store::Keyring => match keyring::Entry::new(SERVICE, login)?.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(e.into()),
+        },
+        Store::Locked => Ok(()),
+        Store::File(vault) => vault.remove(login),
+    }
+}
@@ -7,24 +7,287 @@
 // - config.rs: Server configuration loading from servers.toml
 // - ssh.rs: SSH connection and command execution
 // - commands.rs: Command scripts (test, info, update)
+// - credentials.rs: Remembered passwords in the OS keyring
+// - metrics.rs: Optional Prometheus endpoint (--metrics-port)
 // =============================================================================
 
 mod config;
 mod ssh;
 mod commands;
+mod credentials;
+mod metrics;
+mod vault;
 
-use config::{OsType, Server};
+use config::{CustomCommand, JumpHost, OsType, Server};
 use eframe::egui;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, mpsc};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use zeroize::{Zeroize, Zeroizing};
+
+// =============================================================================
+// HEADLESS MODE - Run a preset from the command line, no window
+// =============================================================================
+// server-manager --headless --command <test|info|disk|uptime|top|update|resume-update|custom name> [--os linux|windows|macos]
+// Passwords come from SERVER_MANAGER_PASSWORD, the keyring, or a stdin prompt;
+// --no-prompt (or abort_on_auth_failure) skips the prompt.
+
+// Value of `--name <value>` or `--name=<value>`
+fn arg_value(args: &[String], name: &str) -> Option<String> {
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        if arg == name {
+            return iter.next().cloned();
+        }
+        if let Some(value) = arg.strip_prefix(name).and_then(|rest| rest.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+// The script a preset name stands for on a given OS, None if it doesn't apply
+fn preset_command(name: &str, os: &OsType, custom: &[CustomCommand]) -> Option<String> {
+    let script = match (name.to_lowercase().as_str(), os) {
+        ("test", _) => commands::test_cmd(),
+        ("info", OsType::Linux) => commands::info_cmd_linux(),
+        ("info", OsType::Windows) => commands::info_cmd_windows(),
+        ("info", OsType::Macos) => commands::info_cmd_macos(),
+        ("disk", OsType::Linux) => commands::disk_cmd_linux(),
+        ("uptime", OsType::Linux | OsType::Macos) => commands::uptime_cmd_linux(),
+        ("uptime", OsType::Windows) => commands::uptime_cmd_windows(),
+        ("disk", OsType::Windows) => commands::disk_cmd_windows(),
+        ("disk", OsType::Macos) => commands::disk_cmd_macos(),
+        ("top", OsType::Linux) => commands::top_processes_cmd_linux(),
+        ("top", OsType::Windows) => commands::top_processes_cmd_windows(),
+        ("top", OsType::Macos) => commands::top_processes_cmd_macos(),
+        ("update", OsType::Linux) => commands::update_linux_cmd(),
+        ("update", OsType::Windows) => commands::update_windows_cmd(),
+        ("update", OsType::Macos) => commands::update_macos_cmd(),
+        ("resume-update", OsType::Windows) => commands::resume_update_windows_cmd(),
+        _ => {
+            let custom = custom.iter().find(|c| c.name.eq_ignore_ascii_case(name))?;
+            if custom.os.as_ref().is_some_and(|only| only != os) {
+                return None;
+            }
+            return Some(custom.script.clone());
+        }
+    };
+    Some(script.to_string())
+}
+
+// Keep roughly the newest `limit` bytes, cut at a line break
+fn trim_raw_output(raw: &mut String, limit: usize) {
+    if raw.len() > limit + limit / 10 {
+        let from = raw.len() - limit;
+        // Searched as bytes: `from` may land inside a multi-byte character
+        let cut = raw.as_bytes()[from..].iter()
+            .position(|&b| b == b'\n')
+            .map_or(raw.len(), |pos| from + pos + 1);
+        raw.replace_range(..cut, "");
+    }
+}
+
+// Output text without ANSI escapes
+fn plain_text(text: &str) -> String {
+    parse_ansi(text, &mut AnsiStyle::default())
+        .into_iter()
+        .map(|(segment, _)| segment)
+        .collect()
+}
+
+// Typed at a terminal without echo; piped stdin is read as a plain line
+fn read_secret(prompt: &str) -> std::io::Result<Zeroizing<String>> {
+    use std::io::IsTerminal;
+
+    if std::io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt).map(Zeroizing::new);
+    }
+    eprint!("{}", prompt);
+    let mut line = Zeroizing::new(String::new());
+    std::io::stdin().read_line(&mut line)?;
+    Ok(Zeroizing::new(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+// Returns the process exit code: 0 all good, 1 some server failed, 2 usage or config error
+fn run_headless(config_path: String, args: &[String]) -> i32 {
+    let Some(preset) = arg_value(args, "--command") else {
+        eprintln!("Usage: server-manager --headless --command <test|info|disk|uptime|top|update|resume-update|name> [--os linux|windows|macos] [--sequential] [--no-prompt] [--config <path>]");
+        return 2;
+    };
+    let os_filter = match arg_value(args, "--os").as_deref() {
+        None => None,
+        Some("linux") => Some(OsType::Linux),
+        Some("windows") => Some(OsType::Windows),
+        Some("macos") => Some(OsType::Macos),
+        Some(other) => {
+            eprintln!("Unknown --os '{}', expected linux, windows or macos", other);
+            return 2;
+        }
+    };
+
+    let mut app = ServerManagerApp::new(config_path);
+    if let Some(ref err) = app.config_error {
+        eprintln!("Config error: {}", err);
+        return 2;
+    }
+
+    // Scripted runs can't answer prompts; what they would have asked for is left out
+    let no_prompt = app.abort_on_auth_failure || args.iter().any(|arg| arg == "--no-prompt");
+
+    // Without the passphrase remembered passwords can't be read, so ask before anything else
+    if let Some(dialog) = app.unlock.take() {
+        let passphrase = match std::env::var("SERVER_MANAGER_PASSPHRASE") {
+            Ok(passphrase) => Zeroizing::new(passphrase),
+            Err(_) if no_prompt => Zeroizing::new(String::new()),
+            Err(_) => {
+                let Ok(passphrase) = read_secret(&format!("Master passphrase for {}: ", dialog.path.display())) else {
+                    return 2;
+                };
+                // A new file's passphrase is typed twice, as in the window
+                if dialog.creating && !passphrase.is_empty() {
+                    let Ok(confirm) = read_secret("Repeat the passphrase: ") else {
+                        return 2;
+                    };
+                    if *confirm != *passphrase {
+                        eprintln!("Password file: the passphrases don't match");
+                        return 2;
+                    }
+                }
+                passphrase
+            }
+        };
+        if !passphrase.is_empty()
+            && let Err(e) = credentials::unlock_file(&dialog.path, &passphrase)
+        {
+            eprintln!("Password file: {}", e);
+            return 2;
+        }
+    }
+
+    let custom = app.custom_commands.iter().find(|c| c.name.eq_ignore_ascii_case(&preset));
+    let scope = PendingCommand {
+        task: Task::Exec(preset.clone()),
+        os_filter: os_filter.clone(),
+        server: None,
+        tag: None,
+        second_factor: None,
+        selection: None,
+        pty: custom.is_some_and(|c| c.pty),
+        filter: custom.and_then(|c| c.filter.clone()),
+        timeout: custom.and_then(|c| c.timeout()),
+        env: custom.map(|c| c.env_vars()).unwrap_or_default(),
+    };
+    let runs: Vec<(usize, String)> = app.servers.iter().enumerate()
+        .filter(|(i, server)| scope.targets(*i, server))
+        .filter_map(|(i, server)| {
+            let command = preset_command(&preset, &server.os_type, &app.custom_commands)?;
+            Some((i, server.override_for(&command).map(str::to_string).unwrap_or(command)))
+        })
+        .collect();
+    if runs.is_empty() {
+        eprintln!("No servers matched '{}'", preset);
+        return 2;
+    }
+
+    // Same password lookup as the GUI, with the prompt on stdin instead
+    for login in app.load_remembered_passwords(&scope) {
+        let password = match std::env::var("SERVER_MANAGER_PASSWORD") {
+            Ok(password) => Zeroizing::new(password),
+            // Keys and the agent are still tried; a server that needs more just fails
+            Err(_) if no_prompt => continue,
+            Err(_) => match read_secret(&format!("Password for {}: ", login)) {
+                Ok(password) => password,
+                Err(_) => return 2,
+            },
+        };
+        app.passwords.insert(login, password);
+    }
+
+    let mut jobs = VecDeque::new();
+    for (i, command) in runs {
+        let server = &app.servers[i];
+        let Some(mut target) = app.build_target(server, None) else {
+            continue;
+        };
+        target.pty = scope.pty.then(|| app.pty.clone());
+        target.command_timeout = scope.timeout.or(target.command_timeout);
+        set_command_env(&mut target, &command, &scope.env, &server.os_type);
+        let name = server.name.clone();
+        let cancel = server.cancel.clone();
+        let filter = scope.filter.clone();
+        let env = scope.env.clone();
+
+        let (server, task) = (name.clone(), command.clone());
+        let job: Job = Box::new(move |skip| {
+            if let Some(reason) = skip {
+                eprintln!("[{}] Skipped - {}", name, reason);
+                return false;
+            }
+            let result = ssh::connect_and_execute_with_callback(&target, &command, &cancel, |line| {
+                if commands::apt_progress(line).is_some() {
+                    return;
+                }
+                let line = mask_secrets(line, &env);
+                let Some(line) = filter_line(filter.as_ref(), &line) else {
+                    return;
+                };
+                for line in plain_text(&parse_clixml(line)).lines() {
+                    println!("[{}] {}", name, line);
+                }
+            });
+            match result {
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("[{}] ERROR: {}", name, mask_secrets(&e.to_string(), &env));
+                    false
+                }
+            }
+        });
+        jobs.push_back(QueuedJob { server, task, job });
+    }
+
+    let failed = if app.sequential || args.iter().any(|arg| arg == "--sequential") {
+        run_in_waves(&Mutex::new(jobs), app.wave_size, app.stop_on_error)
+    } else {
+        let wave_size = jobs.len();
+        run_in_waves(&Mutex::new(jobs), wave_size, false)
+    };
+    if failed > 0 {
+        eprintln!("{} server(s) failed", failed);
+        1
+    } else {
+        0
+    }
+}
 
 // =============================================================================
 // MAIN ENTRY POINT
 // =============================================================================
 fn main() -> eframe::Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let config_path = arg_value(&args, "--config").unwrap_or_else(|| "servers.toml".to_string());
+
+    if args.iter().any(|arg| arg == "--headless") {
+        std::process::exit(run_headless(config_path, &args));
+    }
+
+    let metrics_port = match arg_value(&args, "--metrics-port").map(|port| port.parse::<u16>()) {
+        None => None,
+        Some(Ok(port)) => Some(port),
+        Some(Err(_)) => {
+            eprintln!("--metrics-port expects a port number");
+            std::process::exit(2);
+        }
+    };
+
+    // Window geometry and egui memory (panel widths) are restored by eframe
     let options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default()
+            .with_app_id("server-manager")
             .with_inner_size([1200.0, 800.0])
             .with_min_inner_size([800.0, 600.0]),
         ..Default::default()
@@ -46,7 +309,17 @@ fn main() -> eframe::Result<()> {
             visuals.window_fill = egui::Color32::from_rgb(38, 38, 42);
             visuals.panel_fill = egui::Color32::from_rgb(38, 38, 42);
             cc.egui_ctx.set_visuals(visuals);
-            Ok(Box::new(ServerManagerApp::new()))
+            let mut app = ServerManagerApp::new(config_path);
+            app.repaint = Some(cc.egui_ctx.clone());
+            if let Some(storage) = cc.storage {
+                app.restore(storage);
+            }
+            if let Some(port) = metrics_port
+                && let Err(e) = app.start_metrics(port)
+            {
+                app.notify(format!("Metrics endpoint on port {} failed: {}", port, e), true);
+            }
+            Ok(Box::new(app))
         }),
     )
 }
@@ -54,374 +327,3433 @@ fn main() -> eframe::Result<()> {
 // =============================================================================
 // CLIXML PARSER - Convert PowerShell CLIXML to readable text
 // =============================================================================
+// Output often comes as one `<Objs>` document per line; the tree is walked
+// and objects are rendered as indented `key: value` lines
 fn parse_clixml(input: &str) -> String {
     if !input.contains("<Obj") && !input.contains("<S ") {
         return input.to_string();
     }
 
+    let xml = &input[input.find('<').unwrap_or(0)..];
     let mut result = String::new();
-    let mut i = 0;
-    let chars: Vec<char> = input.chars().collect();
-
-    while i < chars.len() {
-        if i + 2 < chars.len() && chars[i] == '<' && chars[i + 1] == 'S' {
-            if let Some(tag_end) = input[i..].find('>') {
-                let tag_start = i;
-                let content_start = i + tag_end + 1;
-
-                if let Some(close_pos) = input[content_start..].find("</S>") {
-                    let content = &input[content_start..content_start + close_pos];
-                    let tag = &input[tag_start..content_start];
-
-                    let clean = content
-                        .replace("_x000D__x000A_", "\n")
-                        .replace("&lt;", "<")
-                        .replace("&gt;", ">")
-                        .replace("&amp;", "&")
-                        .replace("&quot;", "\"");
-
-                    let trimmed = clean.trim();
-                    if !trimmed.is_empty() {
-                        if tag.contains("S=\"Error\"") {
-                            result.push_str("[ERROR] ");
-                        } else if tag.contains("S=\"verbose\"") {
-                            result.push_str("[VERBOSE] ");
-                        } else if tag.contains("S=\"warning\"") {
-                            result.push_str("[WARNING] ");
-                        }
-                        result.push_str(trimmed);
-                        result.push('\n');
-                    }
-
-                    i = content_start + close_pos + 4;
-                    continue;
-                }
-            }
-        }
+    match parse_xml_tree(xml) {
+        Ok(root) => render_clixml(&root, &mut result),
+        Err(_) => return strip_tags(input),
+    }
 
-        if i + 10 < chars.len() && input[i..].starts_with("<ToString>") {
-            let content_start = i + 10;
-            if let Some(close_pos) = input[content_start..].find("</ToString>") {
-                let content = &input[content_start..content_start + close_pos];
-                let trimmed = content.trim();
-                if !trimmed.is_empty() && !trimmed.starts_with('<') {
-                    result.push_str(trimmed);
-                    result.push('\n');
-                }
-                i = content_start + close_pos + 11;
-                continue;
+    if result.trim().is_empty() {
+        return strip_tags(input);
+    }
+
+    result
+}
+
+// One element of a CLIXML document
+#[derive(Debug, Default)]
+struct XmlNode {
+    tag: String,
+    name: Option<String>,    // N="..." - property name
+    stream: Option<String>,  // S="..." - Error, verbose, warning, progress
+    text: String,
+    children: Vec<XmlNode>,
+}
+
+impl XmlNode {
+    fn from_start(start: &quick_xml::events::BytesStart) -> Self {
+        let mut node = XmlNode {
+            tag: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+            ..Default::default()
+        };
+        for attr in start.attributes().flatten() {
+            let value = attr.unescape_value().map(|v| v.into_owned()).ok();
+            match attr.key.as_ref() {
+                b"N" => node.name = value,
+                b"S" => node.stream = value,
+                _ => {}
             }
         }
+        node
+    }
 
-        if i + 4 < chars.len() && input[i..].starts_with("<SD>") {
-            let content_start = i + 4;
-            if let Some(close_pos) = input[content_start..].find("</SD>") {
-                let content = &input[content_start..content_start + close_pos];
-                let trimmed = content.trim();
-                if !trimmed.is_empty() {
-                    result.push_str("[PROGRESS] ");
-                    result.push_str(trimmed);
-                    result.push('\n');
-                }
-                i = content_start + close_pos + 5;
-                continue;
+    fn child(&self, tag: &str) -> Option<&XmlNode> {
+        self.children.iter().find(|c| c.tag == tag)
+    }
+}
+
+fn parse_xml_tree(xml: &str) -> Result<XmlNode, Box<dyn std::error::Error>> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    let mut stack = vec![XmlNode::default()];
+
+    loop {
+        match reader.read_event()? {
+            Event::Start(start) => stack.push(XmlNode::from_start(&start)),
+            Event::Empty(start) => {
+                let node = XmlNode::from_start(&start);
+                stack.last_mut().unwrap().children.push(node);
+            }
+            Event::End(_) if stack.len() > 1 => {
+                let node = stack.pop().unwrap();
+                stack.last_mut().unwrap().children.push(node);
             }
+            Event::Text(text) => stack.last_mut().unwrap().text.push_str(&text.decode()?),
+            Event::CData(data) => stack.last_mut().unwrap().text.push_str(&data.decode()?),
+            Event::GeneralRef(entity) => {
+                let name = entity.decode()?;
+                let resolved = match entity.resolve_char_ref()? {
+                    Some(c) => c.to_string(),
+                    None => quick_xml::escape::resolve_predefined_entity(&name)
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                stack.last_mut().unwrap().text.push_str(&resolved);
+            }
+            Event::Eof => break,
+            _ => {}
         }
+    }
 
-        i += 1;
+    // Close anything left open by a truncated document
+    while stack.len() > 1 {
+        let node = stack.pop().unwrap();
+        stack.last_mut().unwrap().children.push(node);
     }
+    Ok(stack.pop().unwrap())
+}
 
-    if result.trim().is_empty() {
-        let mut clean = String::new();
-        let mut in_tag = false;
-        for c in input.chars() {
-            if c == '<' {
-                in_tag = true;
-            } else if c == '>' {
-                in_tag = false;
-            } else if !in_tag {
-                clean.push(c);
+// PowerShell escapes control characters as _xHHHH_, e.g. _x000D__x000A_ for CRLF
+fn decode_clixml_string(raw: &str) -> String {
+    let mut result = String::new();
+    let mut rest = raw;
+    while let Some(pos) = rest.find("_x") {
+        result.push_str(&rest[..pos]);
+        let code = rest.get(pos + 2..pos + 6)
+            .filter(|_| rest.get(pos + 6..pos + 7) == Some("_"))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .and_then(char::from_u32);
+        match code {
+            Some(c) => {
+                if c != '\r' {
+                    result.push(c);
+                }
+                rest = &rest[pos + 7..];
+            }
+            None => {
+                result.push_str("_x");
+                rest = &rest[pos + 2..];
             }
         }
-        return clean;
     }
-
+    result.push_str(rest);
     result
 }
 
-// =============================================================================
-// SERVER STATE
-// =============================================================================
-#[derive(Clone)]
-struct ServerState {
-    name: String,
-    ip: String,
-    username: String,
-    os_type: OsType,
-    output: Arc<Mutex<String>>,
-    is_running: Arc<Mutex<bool>>,
-    status: Arc<Mutex<String>>,
-    auth_failed: Arc<Mutex<bool>>,
+fn stream_prefix(stream: Option<&str>) -> &'static str {
+    match stream {
+        Some("Error") => "[ERROR] ",
+        Some("verbose") => "[VERBOSE] ",
+        Some("warning") => "[WARNING] ",
+        _ => "",
+    }
 }
 
-impl ServerState {
-    fn new(server: &Server) -> Self {
-        Self {
-            name: server.name.clone(),
-            ip: server.ip.clone(),
-            username: server.username.clone(),
-            os_type: server.os_type.clone(),
-            output: Arc::new(Mutex::new(String::new())),
-            is_running: Arc::new(Mutex::new(false)),
-            status: Arc::new(Mutex::new("Ready".to_string())),
-            auth_failed: Arc::new(Mutex::new(false)),
+fn push_clixml_line(out: &mut String, depth: usize, text: &str) {
+    out.push_str(&"  ".repeat(depth));
+    out.push_str(text);
+    out.push('\n');
+}
+
+// Top level of the document: stream strings and output objects
+fn render_clixml(root: &XmlNode, out: &mut String) {
+    let objs = root.child("Objs").unwrap_or(root);
+    for node in &objs.children {
+        match node.tag.as_str() {
+            "S" => {
+                let text = decode_clixml_string(&node.text);
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    out.push_str(stream_prefix(node.stream.as_deref()));
+                    out.push_str(trimmed);
+                    out.push('\n');
+                }
+            }
+            "Obj" if node.stream.as_deref() == Some("progress") => render_progress(node, out),
+            "Obj" if !stream_prefix(node.stream.as_deref()).is_empty() => {
+                // Error records and the like read best as their display string
+                if let Some(display) = node.child("ToString") {
+                    let text = decode_clixml_string(&display.text);
+                    out.push_str(stream_prefix(node.stream.as_deref()));
+                    out.push_str(text.trim());
+                    out.push('\n');
+                } else {
+                    render_clixml_value(node, 0, out);
+                }
+            }
+            _ => render_clixml_value(node, 0, out),
         }
     }
+}
 
-    fn append_output(&self, text: &str) {
-        let mut output = self.output.lock().unwrap();
-        let clean = parse_clixml(text);
-        output.push_str(&clean);
-        if !clean.ends_with('\n') {
-            output.push('\n');
+// Progress records only carry their status description
+fn render_progress(node: &XmlNode, out: &mut String) {
+    for child in &node.children {
+        if child.tag == "SD" {
+            let text = decode_clixml_string(&child.text);
+            if !text.trim().is_empty() {
+                out.push_str("[PROGRESS] ");
+                out.push_str(text.trim());
+                out.push('\n');
+            }
+        } else {
+            render_progress(child, out);
         }
     }
+}
 
-    fn clear_output(&self) {
-        self.output.lock().unwrap().clear();
+// Numbers, booleans, dates and null as readable text
+fn clixml_primitive(node: &XmlNode) -> Option<String> {
+    let text = node.text.trim();
+    match node.tag.as_str() {
+        "I16" | "I32" | "I64" | "U16" | "U32" | "U64" | "By" | "SB" | "Db" | "Sg" | "D" | "G" | "Version" | "URI" => {
+            Some(text.to_string())
+        }
+        "B" => Some(if text.eq_ignore_ascii_case("true") { "True" } else { "False" }.to_string()),
+        "DT" => Some(format_clixml_date(text)),
+        "Nil" => Some("null".to_string()),
+        _ => None,
     }
+}
 
-    fn set_status(&self, status: &str) {
-        *self.status.lock().unwrap() = status.to_string();
+// 2024-01-31T14:25:00.1234567+01:00 -> 2024-01-31 14:25:00 +01:00
+fn format_clixml_date(text: &str) -> String {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(text) {
+        return date.format("%Y-%m-%d %H:%M:%S %:z").to_string();
     }
-
-    fn set_running(&self, running: bool) {
-        *self.is_running.lock().unwrap() = running;
+    // Dates with an unspecified kind carry no offset
+    match chrono::NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f") {
+        Ok(date) => date.format("%Y-%m-%d %H:%M:%S").to_string(),
+        Err(_) => text.to_string(),
     }
+}
 
-    fn is_running(&self) -> bool {
-        *self.is_running.lock().unwrap()
-    }
+fn render_clixml_value(node: &XmlNode, depth: usize, out: &mut String) {
+    let label = |value: &str| match &node.name {
+        Some(name) => format!("{}: {}", name, value),
+        None => value.to_string(),
+    };
 
-    fn get_output(&self) -> String {
-        self.output.lock().unwrap().clone()
+    if let Some(value) = clixml_primitive(node) {
+        push_clixml_line(out, depth, &label(&value));
+        return;
     }
 
-    fn get_status(&self) -> String {
-        self.status.lock().unwrap().clone()
-    }
+    match node.tag.as_str() {
+        "S" | "ToString" => {
+            let text = decode_clixml_string(&node.text);
+            for line in text.trim_end().lines() {
+                push_clixml_line(out, depth, &label(line));
+            }
+        }
+        "Obj" => {
+            let members: Vec<&XmlNode> = node.children.iter()
+                .filter(|c| matches!(c.tag.as_str(), "MS" | "Props" | "LST" | "IE" | "STK" | "QUE" | "DCT"))
+                .collect();
 
-    fn set_auth_failed(&self, failed: bool) {
-        *self.auth_failed.lock().unwrap() = failed;
+            if members.is_empty() {
+                if let Some(display) = node.child("ToString") {
+                    let text = decode_clixml_string(&display.text);
+                    push_clixml_line(out, depth, &label(text.trim()));
+                }
+                return;
+            }
+
+            let depth = match &node.name {
+                Some(name) => {
+                    push_clixml_line(out, depth, &format!("{}:", name));
+                    depth + 1
+                }
+                None => depth,
+            };
+            for member in members {
+                render_clixml_value(member, depth, out);
+            }
+        }
+        // Containers: their children are the properties or list items
+        "MS" | "Props" | "LST" | "IE" | "STK" | "QUE" | "DCT" | "En" => {
+            for child in &node.children {
+                render_clixml_value(child, depth, out);
+            }
+        }
+        // Type names, references to earlier objects and unknown tags
+        _ => {}
     }
+}
 
-    fn auth_failed(&self) -> bool {
-        *self.auth_failed.lock().unwrap()
+// Last resort for input that isn't valid XML
+fn strip_tags(input: &str) -> String {
+    let mut clean = String::new();
+    let mut in_tag = false;
+    for c in input.chars() {
+        if c == '<' {
+            in_tag = true;
+        } else if c == '>' {
+            in_tag = false;
+        } else if !in_tag {
+            clean.push(c);
+        }
     }
+    clean
 }
 
 // =============================================================================
-// MAIN APP STATE
+// ANSI PARSER - Turn SGR escape codes into colored segments
 // =============================================================================
-struct ServerManagerApp {
-    servers: Vec<ServerState>,
-    config_error: Option<String>,
-    selected_tab: usize,
-    passwords: HashMap<String, String>,
-    password_input: String,
-    password_needed_for: Option<String>,
-    password_error: Option<String>,
-    pending_command: Option<PendingCommand>,
-    last_command: Option<PendingCommand>,  // Store last command for retry
-}
-
-#[derive(Clone)]
-struct PendingCommand {
-    command: String,
-    os_filter: Option<OsType>,
+// Second stage after parse_clixml. Handles reset, bold and the 16 basic
+// foreground colors; every other escape sequence is stripped.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct AnsiStyle {
+    fg: Option<u8>,  // Palette index 0-15
+    bold: bool,
 }
 
-impl ServerManagerApp {
-    fn new() -> Self {
-        let (config_error, servers) = match config::load_config("servers.toml") {
-            Ok(cfg) => {
-                let servers: Vec<ServerState> = cfg.servers.iter().map(ServerState::new).collect();
-                (None, servers)
+impl AnsiStyle {
+    fn apply_sgr(&mut self, params: &str) {
+        let mut codes = params.split(';').map(|p| p.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = AnsiStyle::default(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                30..=37 => self.fg = Some((code - 30) as u8),
+                39 => self.fg = None,
+                90..=97 => self.fg = Some((code - 90 + 8) as u8),
+                // 256-color and truecolor forms: skip their arguments
+                38 | 48 => match codes.next() {
+                    Some(5) => {
+                        codes.next();
+                    }
+                    Some(2) => {
+                        codes.nth(2);
+                    }
+                    _ => {}
+                },
+                _ => {}
             }
-            Err(e) => (Some(e.to_string()), Vec::new()),
-        };
-
-        Self {
-            servers,
-            config_error,
-            selected_tab: 0,
-            passwords: HashMap::new(),
-            password_input: String::new(),
-            password_needed_for: None,
-            password_error: None,
-            pending_command: None,
-            last_command: None,
         }
     }
 
-    fn get_missing_passwords(&self, os_filter: Option<&OsType>) -> Vec<String> {
-        let mut missing = Vec::new();
-        for server in &self.servers {
-            if let Some(os) = os_filter {
-                if &server.os_type != os {
-                    continue;
-                }
-            }
-            if !self.passwords.contains_key(&server.username) && !missing.contains(&server.username) {
-                missing.push(server.username.clone());
+    fn color(&self, default: egui::Color32) -> egui::Color32 {
+        const PALETTE: [(u8, u8, u8); 16] = [
+            (90, 90, 95), (205, 95, 95), (110, 180, 110), (205, 175, 85),
+            (95, 140, 210), (180, 110, 190), (90, 175, 185), (190, 190, 195),
+            (130, 130, 140), (235, 120, 120), (140, 215, 140), (235, 210, 120),
+            (130, 170, 240), (215, 145, 225), (125, 210, 220), (235, 235, 240),
+        ];
+        // Bold brightens the basic colors, like most terminals
+        let index = match (self.fg, self.bold) {
+            (Some(i), true) if i < 8 => Some(i + 8),
+            (None, true) => Some(15),
+            (fg, _) => fg,
+        };
+        match index {
+            Some(i) => {
+                let (r, g, b) = PALETTE[i as usize];
+                egui::Color32::from_rgb(r, g, b)
             }
+            None => default,
         }
-        missing
     }
+}
 
-    fn check_auth_failures(&mut self) {
-        for server in &self.servers {
-            if server.auth_failed() {
-                server.set_auth_failed(false);
-                let username = server.username.clone();
-                self.passwords.remove(&username);
-                self.password_error = Some(format!("Wrong password for '{}'. Please try again.", username));
-                self.password_needed_for = Some(username);
-                self.password_input.clear();
+// Split `input` into (text, style) runs; `style` carries over between calls
+fn parse_ansi(input: &str, style: &mut AnsiStyle) -> Vec<(String, AnsiStyle)> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = input.chars().peekable();
 
-                // Set up pending command to retry the last command
-                if let Some(ref last) = self.last_command {
-                    self.pending_command = Some(last.clone());
-                }
-                break;
-            }
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            current.push(c);
+            continue;
         }
-    }
-
-    fn run_command(&mut self, command: &str, os_filter: Option<OsType>) {
-        // Store as last command for potential retry
-        self.last_command = Some(PendingCommand {
-            command: command.to_string(),
-            os_filter: os_filter.clone(),
-        });
 
-        for server in &self.servers {
-            if let Some(ref os) = os_filter {
-                if &server.os_type != os {
-                    continue;
+        let mut new_style = *style;
+        match chars.next() {
+            // CSI: ESC [ params final-byte
+            Some('[') => {
+                let mut params = String::new();
+                let mut final_byte = None;
+                for c in chars.by_ref() {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        final_byte = Some(c);
+                        break;
+                    }
+                    params.push(c);
+                }
+                if final_byte == Some('m') {
+                    new_style.apply_sgr(&params);
                 }
             }
+            // OSC: ESC ] ... terminated by BEL or ESC \
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\x07' {
+                        break;
+                    }
+                    if c == '\x1b' {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Two-byte sequences like ESC ( B
+            Some('(') | Some(')') => {
+                chars.next();
+            }
+            _ => {}
+        }
 
-            if server.is_running() {
-                continue;
+        if new_style != *style {
+            if !current.is_empty() {
+                segments.push((std::mem::take(&mut current), *style));
             }
+            *style = new_style;
+        }
+    }
 
-            let password = match self.passwords.get(&server.username) {
-                Some(pw) => pw.clone(),
-                None => continue,
-            };
+    if !current.is_empty() {
+        segments.push((current, *style));
+    }
+    segments
+}
 
-            let server_state = server.clone();
-            let ip = server.ip.clone();
-            let username = server.username.clone();
-            let cmd = command.to_string();
+// =============================================================================
+// OUTPUT BUFFER
+// =============================================================================
+// Plain text as shown on screen, plus the colored ranges within it
+const TRUNCATED_MARKER: &str = "[output truncated]\n";
+const RAW_OUTPUT_LIMIT: usize = 4 * 1024 * 1024;  // Bytes of unparsed output kept per server
+const STDERR_COLOR: u8 = 1;  // Palette red, for stderr text without its own color
 
-            server_state.clear_output();
-            server_state.set_running(true);
-            server_state.set_status("Connecting...");
-            server_state.append_output(&format!(">>> Connecting to {}@{}", username, ip));
+// Which stream's lines the output pane shows; capture is unaffected
+#[derive(Clone, Copy, PartialEq)]
+enum StreamFilter {
+    All,
+    Stdout,
+    Stderr,
+}
 
-            thread::spawn(move || {
-                let output_clone = server_state.output.clone();
+// Where the newest line starts in OutputBuffer::text and how it is shown
+#[derive(Clone)]
+struct LastLine {
+    line: String,  // As pushed, before parsing
+    count: usize,
+    start: usize,
+    style: AnsiStyle,  // ANSI state before it, to render it again from
+}
 
-                server_state.set_status("Running...");
+#[derive(Clone)]
+struct OutputBuffer {
+    text: String,
+    spans: Vec<(std::ops::Range<usize>, AnsiStyle)>,
+    style: AnsiStyle,  // ANSI state carried over between lines
+    lines: usize,      // Lines kept, not counting the marker
+    max_lines: usize,
+    truncated: bool,
+    timestamps: bool,  // Prefix new lines with the time they arrived
+    collapse_repeats: bool,  // Show a run of identical lines once, as "line (xN)"
+    last: Option<LastLine>,  // The newest line, for collapse_repeats
+    stderr_lines: usize,  // Lines that came from stderr since the last clear
+    stderr_ranges: Vec<std::ops::Range<usize>>,  // Where those lines are in `text`, in order
+}
 
-                let result = ssh::connect_and_execute_with_callback(
-                    &ip,
-                    &username,
-                    &password,
-                    &cmd,
-                    move |line| {
-                        let mut output = output_clone.lock().unwrap();
-                        let clean = parse_clixml(line);
-                        output.push_str(&clean);
-                        if !clean.ends_with('\n') {
-                            output.push('\n');
-                        }
-                    },
-                );
+impl OutputBuffer {
+    fn new(max_lines: usize) -> Self {
+        Self {
+            text: String::new(),
+            spans: Vec::new(),
+            style: AnsiStyle::default(),
+            lines: 0,
+            max_lines: max_lines.max(1),
+            truncated: false,
+            timestamps: false,
+            collapse_repeats: false,
+            last: None,
+            stderr_lines: 0,
+            stderr_ranges: Vec::new(),
+        }
+    }
 
-                match result {
-                    Ok(_) => {
-                        server_state.append_output("---");
-                        server_state.append_output(">>> Done");
-                        server_state.set_status("Done");
-                    }
+    fn push_line(&mut self, line: &str) {
+        if line.starts_with(ssh::STDERR_PREFIX) {
+            self.stderr_lines += 1;
+        }
+        // A repeat replaces the line it repeats, rendered again with the count
+        let repeats = match self.last {
+            Some(ref mut last) if self.collapse_repeats && last.line == line => {
+                last.count += 1;
+                Some((last.start, last.style, last.count))
+            }
+            _ => None,
+        };
+        match repeats {
+            Some((start, style, count)) => {
+                self.lines -= self.text[start..].matches('\n').count();
+                self.text.truncate(start);
+                self.spans.retain(|(range, _)| range.start < start);
+                self.stderr_ranges.retain(|range| range.start < start);
+                self.style = style;
+                self.render_line(line, count);
+            }
+            None => {
+                self.last = Some(LastLine { line: line.to_string(), count: 1, start: self.text.len(), style: self.style });
+                self.render_line(line, 1);
+            }
+        }
+
+        // Trim in batches so a flood of output doesn't copy the buffer per line
+        if self.lines > self.max_lines + self.max_lines / 10 {
+            self.drop_oldest(self.lines - self.max_lines);
+        }
+    }
+
+    fn render_line(&mut self, line: &str, count: usize) {
+        let clean = parse_clixml(line);
+        let stderr = line.starts_with(ssh::STDERR_PREFIX);
+        let start = self.text.len();
+        if self.timestamps {
+            self.text.push_str(&chrono::Local::now().format("[%H:%M:%S] ").to_string());
+        }
+        for (segment, style) in parse_ansi(&clean, &mut self.style) {
+            let style = match style.fg {
+                None if stderr => AnsiStyle { fg: Some(STDERR_COLOR), ..style },
+                _ => style,
+            };
+            let from = self.text.len();
+            self.text.push_str(&segment);
+            if style != AnsiStyle::default() {
+                self.spans.push((from..self.text.len(), style));
+            }
+        }
+        if count > 1 {
+            if self.text[start..].ends_with('\n') {
+                self.text.pop();
+            }
+            self.text.push_str(&format!(" (x{})", count));
+        }
+        if !self.text[start..].ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.lines += self.text[start..].matches('\n').count();
+        if stderr {
+            self.stderr_ranges.push(start..self.text.len());
+        }
+    }
+
+    // Remove the first `count` lines and put the marker in their place
+    fn drop_oldest(&mut self, count: usize) {
+        let body_start = if self.truncated { TRUNCATED_MARKER.len() } else { 0 };
+        let cut = match self.text[body_start..].match_indices('\n').nth(count - 1) {
+            Some((pos, _)) => body_start + pos + 1,
+            None => self.text.len(),
+        };
+
+        self.text.replace_range(..cut, TRUNCATED_MARKER);
+        self.spans.retain(|(range, _)| range.end > cut);
+        for (range, _) in &mut self.spans {
+            range.start = range.start.max(cut) + TRUNCATED_MARKER.len() - cut;
+            range.end = range.end + TRUNCATED_MARKER.len() - cut;
+        }
+        self.stderr_ranges.retain(|range| range.start >= cut);
+        for range in &mut self.stderr_ranges {
+            *range = range.start + TRUNCATED_MARKER.len() - cut..range.end + TRUNCATED_MARKER.len() - cut;
+        }
+        self.last = self.last.take()
+            .filter(|last| last.start >= cut)
+            .map(|last| LastLine { start: last.start + TRUNCATED_MARKER.len() - cut, ..last });
+        self.lines -= count;
+        self.truncated = true;
+    }
+
+    fn clear(&mut self) {
+        let (timestamps, collapse_repeats) = (self.timestamps, self.collapse_repeats);
+        *self = Self::new(self.max_lines);
+        self.timestamps = timestamps;
+        self.collapse_repeats = collapse_repeats;
+    }
+
+    // A copy holding only the lines from one stream, colors kept
+    fn filtered(&self, filter: StreamFilter) -> OutputBuffer {
+        let kept: Vec<std::ops::Range<usize>> = match filter {
+            StreamFilter::All => return self.clone(),
+            StreamFilter::Stderr => {
+                let marker = self.truncated.then_some(0..TRUNCATED_MARKER.len());
+                marker.into_iter().chain(self.stderr_ranges.iter().cloned()).collect()
+            }
+            StreamFilter::Stdout => {
+                let mut gaps = Vec::new();
+                let mut from = 0;
+                for range in &self.stderr_ranges {
+                    gaps.push(from..range.start);
+                    from = range.end;
+                }
+                gaps.push(from..self.text.len());
+                gaps.retain(|range| !range.is_empty());
+                gaps
+            }
+        };
+
+        let mut out = OutputBuffer { text: String::new(), spans: Vec::new(), stderr_ranges: Vec::new(), ..self.clone() };
+        let mut first_span = 0;
+        for range in kept {
+            let offset = out.text.len();
+            out.text.push_str(&self.text[range.clone()]);
+            while self.spans.get(first_span).is_some_and(|(span, _)| span.end <= range.start) {
+                first_span += 1;
+            }
+            for (span, style) in self.spans[first_span..].iter().take_while(|(span, _)| span.start < range.end) {
+                let start = span.start.max(range.start) - range.start + offset;
+                let end = span.end.min(range.end) - range.start + offset;
+                out.spans.push((start..end, *style));
+            }
+        }
+        out.lines = out.text.matches('\n').count();
+        out
+    }
+
+    // Layout with ANSI colors applied over `default` text color, and
+    // `highlights` (sorted, non-overlapping byte ranges) given a background
+    fn layout_job(
+        &self,
+        font: egui::FontId,
+        default: egui::Color32,
+        highlights: &[(std::ops::Range<usize>, egui::Color32)],
+    ) -> egui::text::LayoutJob {
+        let mut bounds: Vec<usize> = vec![0, self.text.len()];
+        for (range, _) in &self.spans {
+            bounds.extend([range.start, range.end]);
+        }
+        for (range, _) in highlights {
+            bounds.extend([range.start, range.end]);
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut job = egui::text::LayoutJob::default();
+        let (mut spans, mut marks) = (self.spans.iter().peekable(), highlights.iter().peekable());
+        for pair in bounds.windows(2) {
+            let (from, to) = (pair[0], pair[1]);
+            while spans.peek().is_some_and(|(range, _)| range.end <= from) {
+                spans.next();
+            }
+            while marks.peek().is_some_and(|(range, _)| range.end <= from) {
+                marks.next();
+            }
+            let color = match spans.peek() {
+                Some((range, style)) if range.start <= from => style.color(default),
+                _ => default,
+            };
+            let background = match marks.peek() {
+                Some((range, color)) if range.start <= from => *color,
+                _ => egui::Color32::TRANSPARENT,
+            };
+            job.append(&self.text[from..to], 0.0, egui::TextFormat {
+                font_id: font.clone(),
+                color,
+                background,
+                ..Default::default()
+            });
+        }
+        job
+    }
+}
+
+// =============================================================================
+// SERVER STATE
+// =============================================================================
+// Passwords are kept per account on a host, since the same user name on
+// two servers rarely shares a password
+fn login_key(username: &str, host: &str) -> String {
+    format!("{}@{}", username, host)
+}
+
+fn login_user(login: &str) -> &str {
+    login.rsplit_once('@').map_or(login, |(user, _)| user)
+}
+
+#[derive(Clone)]
+struct ServerState {
+    name: String,
+    ip: String,
+    host: String,  // `ip` without brackets or port
+    port: u16,
+    username: String,
+    os_type: OsType,
+    key_path: Option<PathBuf>,
+    jump_host: Option<JumpHost>,
+    tags: Vec<String>,
+    update_cmd: Option<String>,  // Replaces the OS's update preset on this server
+    info_cmd: Option<String>,  // Replaces the OS's info preset on this server
+    output: Arc<Mutex<OutputBuffer>>,
+    raw_output: Arc<Mutex<String>>,  // Lines as received, before CLIXML and ANSI parsing
+    is_running: Arc<Mutex<bool>>,
+    last_duration: Arc<Mutex<Option<Duration>>>,  // How long the last run took
+    last_exit_code: Arc<Mutex<Option<u32>>>,
+    last_run: Arc<Mutex<Option<String>>>,  // Shell command of the latest run or dry run, for "Copy command"
+    cancel: Arc<AtomicBool>,
+    status: Arc<Mutex<String>>,
+    auth_failed: Arc<Mutex<bool>>,
+    host_key_prompt: Arc<Mutex<Option<ssh::HostKeyError>>>,
+    second_factor_prompt: Arc<Mutex<Option<String>>>,
+    sudo_prompt: Arc<Mutex<bool>>,  // sudo wanted a password during the last run
+    input: Arc<Mutex<Vec<String>>>,  // Lines waiting to be sent to the running command
+    info: Arc<Mutex<Option<commands::ServerInfo>>>,  // Set when the last run was an info command
+    log: Arc<Mutex<Option<OutputLog>>>,  // None unless the config sets log_dir
+    stream_filter: StreamFilter,  // What the output pane shows for this server
+    health: Arc<Mutex<Option<String>>>,  // e.g. "Disk 93% used", from the last info run
+    update_interrupted: Arc<Mutex<bool>>,  // A Windows update task outlived its dropped session
+    reboot_pending: Arc<Mutex<bool>>,  // Output said a reboot is required, cleared by a reboot
+    last_success: Arc<Mutex<Option<SystemTime>>>,  // Last login that worked, kept across launches
+    logged_in: Arc<AtomicBool>,  // The latest run got past the login, this session only
+    failures: Arc<AtomicU64>,  // Runs that ended in an error, for the metrics endpoint
+    last_error: Arc<Mutex<Option<String>>>,  // Why the last run failed, shown when hovering the sidebar entry
+    progress: Arc<Mutex<Option<(f32, String)>>>,  // Fraction done and step of a running apt update
+    processes: Arc<Mutex<Option<Vec<commands::Process>>>>,  // Set when the last run was Top processes
+    ping_generation: Arc<AtomicU64>,  // Bumped by each ping and run; older ping results are dropped
+}
+
+impl ServerState {
+    fn new(server: &Server, max_output_lines: usize) -> Self {
+        Self {
+            name: server.name.clone(),
+            ip: server.ip.clone(),
+            host: server.hostname().to_string(),
+            port: server.port(),
+            username: server.username.clone(),
+            os_type: server.os_type.clone(),
+            key_path: server.key_path.clone(),
+            jump_host: server.jump_host.clone(),
+            tags: server.tags.clone(),
+            update_cmd: server.update_cmd.clone(),
+            info_cmd: server.info_cmd.clone(),
+            output: Arc::new(Mutex::new(OutputBuffer::new(max_output_lines))),
+            raw_output: Arc::new(Mutex::new(String::new())),
+            is_running: Arc::new(Mutex::new(false)),
+            last_duration: Arc::new(Mutex::new(None)),
+            last_exit_code: Arc::new(Mutex::new(None)),
+            last_run: Arc::new(Mutex::new(None)),
+            cancel: Arc::new(AtomicBool::new(false)),
+            status: Arc::new(Mutex::new("Ready".to_string())),
+            auth_failed: Arc::new(Mutex::new(false)),
+            host_key_prompt: Arc::new(Mutex::new(None)),
+            second_factor_prompt: Arc::new(Mutex::new(None)),
+            sudo_prompt: Arc::new(Mutex::new(false)),
+            input: Arc::new(Mutex::new(Vec::new())),
+            info: Arc::new(Mutex::new(None)),
+            log: Arc::new(Mutex::new(None)),
+            stream_filter: StreamFilter::All,
+            health: Arc::new(Mutex::new(None)),
+            update_interrupted: Arc::new(Mutex::new(false)),
+            reboot_pending: Arc::new(Mutex::new(false)),
+            last_success: Arc::new(Mutex::new(None)),
+            logged_in: Arc::new(AtomicBool::new(false)),
+            failures: Arc::new(AtomicU64::new(0)),
+            last_error: Arc::new(Mutex::new(None)),
+            progress: Arc::new(Mutex::new(None)),
+            processes: Arc::new(Mutex::new(None)),
+            ping_generation: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    fn append_output(&self, text: &str) {
+        self.show_line(text);
+        self.write_log(text);
+    }
+
+    // Add a line to the output pane only
+    fn show_line(&self, text: &str) {
+        let mut raw = self.raw_output.lock().unwrap();
+        raw.push_str(text);
+        raw.push('\n');
+        trim_raw_output(&mut raw, RAW_OUTPUT_LIMIT);
+        drop(raw);
+        self.output.lock().unwrap().push_line(text);
+    }
+
+    fn clear_output(&self) {
+        self.output.lock().unwrap().clear();
+        self.raw_output.lock().unwrap().clear();
+        *self.info.lock().unwrap() = None;
+        *self.processes.lock().unwrap() = None;
+    }
+
+    fn set_log_dir(&self, dir: Option<&PathBuf>) {
+        let mut log = self.log.lock().unwrap();
+        if log.as_ref().map(|log| &log.dir) != dir {
+            *log = dir.map(|dir| OutputLog { dir: dir.clone(), file: None });
+        }
+    }
+
+    fn write_log(&self, text: &str) {
+        use std::io::Write;
+
+        let mut guard = self.log.lock().unwrap();
+        let Some(log) = guard.as_mut() else {
+            return;
+        };
+        let today = chrono::Local::now().date_naive();
+        if log.file.as_ref().is_none_or(|(date, _)| *date != today) {
+            let path = log.dir.join(log_file_name(&self.name, today));
+            // Owner only: the output can hold anything a command printed
+            let mut options = std::fs::OpenOptions::new();
+            options.create(true).append(true);
+            #[cfg(unix)]
+            std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+            let opened = std::fs::create_dir_all(&log.dir).and_then(|_| options.open(&path));
+            match opened {
+                Ok(file) => log.file = Some((today, file)),
+                Err(e) => {
+                    // Stop trying rather than failing on every line
+                    *guard = None;
+                    drop(guard);
+                    self.show_line(&format!(">>> Logging stopped, could not open {}: {}", path.display(), e));
+                    return;
+                }
+            }
+        }
+        if let Some((_, ref mut file)) = log.file {
+            let _ = writeln!(file, "{}", text);
+        }
+    }
+
+    // Marks where each run starts in the log, which otherwise has no times
+    fn log_run_start(&self, description: &str) {
+        let now = chrono::Local::now().format("%Y-%m-%d %H:%M:%S");
+        self.write_log(&format!("=== {} {} ===", now, description));
+    }
+
+    // Fill the output pane from the newest log, keeping only what fits
+    fn load_latest_log(&self, max_lines: usize) {
+        let Some(dir) = self.log.lock().unwrap().as_ref().map(|log| log.dir.clone()) else {
+            return;
+        };
+        let Some(content) = latest_log(&dir, &self.name).and_then(|path| std::fs::read_to_string(path).ok()) else {
+            return;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        for line in &lines[lines.len().saturating_sub(max_lines)..] {
+            self.show_line(line);
+        }
+    }
+
+    fn info(&self) -> Option<commands::ServerInfo> {
+        self.info.lock().unwrap().clone()
+    }
+
+    fn processes(&self) -> Option<Vec<commands::Process>> {
+        self.processes.lock().unwrap().clone()
+    }
+
+    fn last_success(&self) -> Option<SystemTime> {
+        *self.last_success.lock().unwrap()
+    }
+
+    fn metrics(&self) -> metrics::ServerMetrics {
+        let status = self.get_status();
+        // Not last_success: that one survives restarts and would keep a dead host up
+        let up = match status.as_str() {
+            status if status.starts_with("Online") => true,
+            "Offline" => false,
+            _ => self.logged_in.load(Ordering::Relaxed),
+        };
+        metrics::ServerMetrics {
+            name: self.name.clone(),
+            up,
+            running: self.is_running(),
+            last_duration: self.last_duration(),
+            failures: self.failures.load(Ordering::Relaxed),
+            last_seen: self.last_success(),
+        }
+    }
+
+    fn update_interrupted(&self) -> bool {
+        *self.update_interrupted.lock().unwrap()
+    }
+
+    fn reboot_pending(&self) -> bool {
+        *self.reboot_pending.lock().unwrap()
+    }
+
+    fn health(&self) -> Option<String> {
+        self.health.lock().unwrap().clone()
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    fn progress(&self) -> Option<(f32, String)> {
+        self.progress.lock().unwrap().clone()
+    }
+
+    // Only info runs change this, so the flag stays through other commands
+    fn set_info(&self, info: Option<commands::ServerInfo>, (disk_limit, memory_limit): (f32, f32)) {
+        if let Some(ref info) = info {
+            let mut warnings = Vec::new();
+            if let Some(disk) = info.disk_percent().filter(|&disk| disk >= disk_limit) {
+                warnings.push(format!("Disk {:.0}% used", disk));
+            }
+            if let Some(memory) = info.memory_percent().filter(|&memory| memory >= memory_limit) {
+                warnings.push(format!("Memory {:.0}% used", memory));
+            }
+            *self.health.lock().unwrap() = (!warnings.is_empty()).then(|| warnings.join(", "));
+        }
+        *self.info.lock().unwrap() = info;
+    }
+
+    fn login_key(&self) -> String {
+        login_key(&self.username, &self.host)
+    }
+
+    // The server's own update_cmd / info_cmd when `command` is the preset it replaces
+    fn override_for(&self, command: &str) -> Option<&str> {
+        if [commands::update_linux_cmd(), commands::update_windows_cmd(), commands::update_macos_cmd()].contains(&command) {
+            self.update_cmd.as_deref()
+        } else if [commands::info_cmd_linux(), commands::info_cmd_windows(), commands::info_cmd_macos()].contains(&command) {
+            self.info_cmd.as_deref()
+        } else {
+            None
+        }
+    }
+
+    // This server's login, then its jump host's
+    fn logins(&self) -> Vec<String> {
+        let mut logins = vec![self.login_key()];
+        if let Some(ref jump) = self.jump_host {
+            logins.push(login_key(&jump.username, jump.hostname()));
+        }
+        logins
+    }
+
+    fn set_sudo_prompt(&self, needed: bool) {
+        *self.sudo_prompt.lock().unwrap() = needed;
+    }
+
+    fn take_sudo_prompt(&self) -> bool {
+        std::mem::take(&mut *self.sudo_prompt.lock().unwrap())
+    }
+
+    fn send_input(&self, line: &str) {
+        self.input.lock().unwrap().push(format!("{}\n", line));
+    }
+
+    fn set_timestamps(&self, enabled: bool) {
+        self.output.lock().unwrap().timestamps = enabled;
+    }
+
+    fn set_collapse_repeats(&self, enabled: bool) {
+        self.output.lock().unwrap().collapse_repeats = enabled;
+    }
+
+    // Take new connection settings after a config reload, keeping output and status
+    fn update_from(&mut self, server: &Server, max_output_lines: usize) {
+        self.ip = server.ip.clone();
+        self.host = server.hostname().to_string();
+        self.port = server.port();
+        self.username = server.username.clone();
+        self.os_type = server.os_type.clone();
+        self.key_path = server.key_path.clone();
+        self.jump_host = server.jump_host.clone();
+        self.tags = server.tags.clone();
+        self.output.lock().unwrap().max_lines = max_output_lines.max(1);
+    }
+
+    fn set_status(&self, status: &str) {
+        *self.status.lock().unwrap() = status.to_string();
+    }
+
+    fn set_running(&self, running: bool) {
+        if running {
+            self.ping_generation.fetch_add(1, Ordering::Relaxed);
+        }
+        *self.is_running.lock().unwrap() = running;
+    }
+
+    // Status from a ping, unless a newer ping or a run has started since
+    fn set_ping_status(&self, generation: u64, status: &str) -> bool {
+        let mut current = self.status.lock().unwrap();
+        if self.ping_generation.load(Ordering::Relaxed) != generation || self.is_running() {
+            return false;
+        }
+        *current = status.to_string();
+        true
+    }
+
+    fn is_running(&self) -> bool {
+        *self.is_running.lock().unwrap()
+    }
+
+    fn request_cancel(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    fn get_output(&self) -> String {
+        self.output.lock().unwrap().text.clone()
+    }
+
+    fn get_status(&self) -> String {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn set_last_duration(&self, duration: Option<Duration>) {
+        *self.last_duration.lock().unwrap() = duration;
+    }
+
+    fn last_duration(&self) -> Option<Duration> {
+        *self.last_duration.lock().unwrap()
+    }
+
+    fn set_last_exit_code(&self, code: Option<u32>) {
+        *self.last_exit_code.lock().unwrap() = code;
+    }
+
+    fn last_exit_code(&self) -> Option<u32> {
+        *self.last_exit_code.lock().unwrap()
+    }
+
+    // One line for the grid view: the parsed info, or the last line worth reading
+    fn summary(&self) -> String {
+        if let Some(info) = self.info() {
+            return format!("{} | {} | mem {} | disk {}", info.os, info.uptime, info.memory, info.disk);
+        }
+        let output = self.output.lock().unwrap();
+        output.text.lines()
+            .rev()
+            .map(str::trim)
+            .find(|line| {
+                !line.is_empty()
+                    && *line != "---"
+                    && !line.starts_with(">>> Done")
+                    && !line.starts_with(">>> Exit code")
+            })
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    // Status with the run time once finished, e.g. "Done (2m14s)"
+    fn status_label(&self) -> String {
+        let status = self.get_status();
+        match self.last_duration() {
+            Some(duration) if !self.is_running() => format!("{} ({})", status, format_duration(duration)),
+            _ => status,
+        }
+    }
+
+    fn set_auth_failed(&self, failed: bool) {
+        *self.auth_failed.lock().unwrap() = failed;
+    }
+
+    fn auth_failed(&self) -> bool {
+        *self.auth_failed.lock().unwrap()
+    }
+
+    fn set_host_key_prompt(&self, prompt: Option<ssh::HostKeyError>) {
+        *self.host_key_prompt.lock().unwrap() = prompt;
+    }
+
+    fn host_key_prompt(&self) -> Option<ssh::HostKeyError> {
+        self.host_key_prompt.lock().unwrap().clone()
+    }
+
+    fn set_second_factor_prompt(&self, prompt: Option<String>) {
+        *self.second_factor_prompt.lock().unwrap() = prompt;
+    }
+
+    fn take_second_factor_prompt(&self) -> Option<String> {
+        self.second_factor_prompt.lock().unwrap().take()
+    }
+}
+
+// =============================================================================
+// MAIN APP STATE
+// =============================================================================
+struct ServerManagerApp {
+    servers: Vec<ServerState>,
+    config_path: String,
+    config_error: Option<String>,
+    connect_timeout: Duration,
+    keepalive_interval: Option<Duration>,
+    session_idle_timeout: Option<Duration>,
+    stall_warning: Option<Duration>,
+    connect_attempts: u32,
+    command_timeout: Option<Duration>,  // Default limit for shell commands, None = no limit
+    sequential: bool,  // "One at a time" checkbox: runs go through the servers in waves
+    wave_size: usize,  // Servers started together in sequential mode
+    stop_on_error: bool,  // Skip the remaining waves after a failure in sequential mode
+    custom_commands: Vec<CustomCommand>,
+    selected_tab: usize,
+    passwords: HashMap<String, Zeroizing<String>>,  // By login_key, wiped when replaced or dropped
+    password_input: Zeroizing<String>,
+    password_needed_for: Option<String>,  // login_key the dialog is asking about
+    password_error: Option<String>,
+    remember_password: bool,  // "Remember password" checkbox in the dialog
+    remember: HashSet<String>,  // Logins to save to the keyring once they work
+    same_for_user: bool,  // "Use for every server with this user" checkbox in the dialog
+    webhook: Option<(String, config::WebhookOn)>,  // URL each finished run is POSTed to, and for which runs
+    terminal: Option<String>,  // "Open terminal" program from the config, None = platform default
+    forget_after_use: bool,  // "Forget after use" checkbox: passwords are dropped once their runs finish
+    abort_on_auth_failure: bool,  // A refused login fails the server instead of reopening the password dialog
+    second_factor_for: Option<(usize, String)>,  // Server index and its prompt
+    sudo_passwords: HashMap<String, Zeroizing<String>>,  // By login_key, may equal the login password
+    sudo_for: Option<usize>,  // The dialog is asking for this server's sudo password
+    use_for_sudo: bool,  // "Also use for sudo" checkbox in the dialog
+    pending_command: Option<PendingCommand>,
+    last_command: Option<PendingCommand>,  // Store last command for retry
+    history: Vec<HistoryEntry>,  // Oldest first, capped at HISTORY_LIMIT
+    confirm_reboot: Option<PendingCommand>,
+    confirm_mismatch: Option<(PendingCommand, OsType)>,  // Typed command that looks like it's for one OS only
+    confirm_run: Option<PendingCommand>,  // Update or confirm = true command waiting for the go-ahead
+    view_script: Option<String>,  // Decoded PowerShell shown in the "View script" window
+    compare: Option<(usize, usize)>,  // Servers whose outputs the "Compare" window diffs
+    compare_diff: Option<(u64, Vec<(similar::ChangeTag, String)>)>,  // Its diff, keyed on a hash of both outputs
+    confirm_kill: Option<(usize, commands::Process)>,  // Kill picked in the processes table, waiting for the go-ahead
+    dry_run: bool,  // "Dry run" checkbox: show what each server would run instead of running it
+    server_search: String,
+    sidebar_os: Option<OsType>,  // Chips above the server list, a view filter like server_search
+    selection: HashSet<usize>,  // Ctrl/Shift-clicked servers for "Run on selected"
+    sidebar_order: Vec<usize>,  // Server indices in the order the sidebar last showed them, for Shift-click
+    upload_remote_path: String,
+    upload_os_filter: Option<OsType>,
+    download_remote_path: String,  // Empty = OS default log
+    command_input: String,  // Free-form command, kept after running
+    stdin_input: String,  // Line to send to a command that seems to be waiting
+    command_pty: bool,  // "PTY" checkbox next to the free-form command
+    service_name: String,  // For "Check Service"
+    pty: ssh::PtyRequest,  // Terminal type and size from the config
+    command_os_filter: Option<OsType>,
+    notice: Option<(String, bool, Instant)>,  // Message, is error, when shown
+    repaint: Option<egui::Context>,  // Lets workers wake the UI, None when headless
+    show_timestamps: bool,
+    collapse_repeats: bool,  // "Collapse repeats" checkbox, applied to every server's output pane
+    grid_view: bool,  // Central panel lists every server instead of one server's output
+    wrap_output: bool,  // "Wrap" toggle: long output lines fit the panel instead of scrolling sideways
+    show_shortcuts: bool,  // "?" overlay listing the keyboard shortcuts
+    usage_warning: (f32, f32),  // Disk and memory percent that flag a server after Info
+    show_raw: bool,  // Output panel shows the unparsed stream
+    notify_when_done: bool,  // Desktop notification when a run finishes
+    find_open: bool,  // Find bar above the output (Ctrl+F)
+    find_query: String,
+    find_case_sensitive: bool,
+    find_current: usize,  // Index of the focused match
+    find_scroll: bool,  // Bring the focused match into view next frame
+    output_focused: bool,
+    output_selection: String,  // Last text selected in the output, kept for the right-click menu
+    metrics: Option<Arc<Mutex<Vec<ServerState>>>>,  // What the metrics endpoint reports, kept in step on reload
+    queues: Vec<JobQueue>,  // Sequential runs with servers still waiting, oldest first
+    password_file: Option<PathBuf>,  // Encrypted file remembered passwords go to instead of the keyring
+    unlock: Option<UnlockDialog>,  // Asking for the password file's master passphrase
+}
+
+// Master passphrase dialog for password_file, shown at launch
+struct UnlockDialog {
+    path: PathBuf,
+    creating: bool,  // No file yet: the passphrase is being chosen, so it's typed twice
+    passphrase: Zeroizing<String>,
+    confirm: Zeroizing<String>,
+    error: Option<String>,
+}
+
+impl UnlockDialog {
+    fn new(path: PathBuf) -> Self {
+        UnlockDialog {
+            creating: !path.exists(),
+            path,
+            passphrase: Zeroizing::new(String::new()),
+            confirm: Zeroizing::new(String::new()),
+            error: None,
+        }
+    }
+}
+
+// Byte ranges of every occurrence of `query`; ASCII case folding keeps offsets intact
+fn find_matches(text: &str, query: &str, case_sensitive: bool) -> Vec<std::ops::Range<usize>> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let (haystack, needle) = if case_sensitive {
+        (text.to_string(), query.to_string())
+    } else {
+        (text.to_ascii_lowercase(), query.to_ascii_lowercase())
+    };
+    haystack.match_indices(&needle)
+        .map(|(start, found)| start..start + found.len())
+        .collect()
+}
+
+// One server's outcome in an "Export JSON" file
+#[derive(serde::Serialize)]
+struct RunResult {
+    name: String,
+    ip: String,
+    os: OsType,
+    status: String,
+    exit_code: Option<u32>,
+    duration_secs: Option<f64>,
+    output: String,
+}
+
+#[derive(serde::Serialize)]
+struct RunExport {
+    timestamp: String,
+    command: String,
+    results: Vec<RunResult>,
+}
+
+// Body POSTed to webhook_url when a server's run finishes
+#[derive(serde::Serialize)]
+struct WebhookPayload {
+    text: String,  // One-line summary, what Slack and Teams show
+    server: String,
+    ip: String,
+    command: String,
+    status: String,
+    exit_code: Option<u32>,
+    duration_secs: Option<f64>,
+    output_tail: String,
+}
+
+impl WebhookPayload {
+    fn new(server: &ServerState, command: String) -> Self {
+        let status = server.get_status();
+        WebhookPayload {
+            text: format!("{}: {} ({})", server.name, status, command),
+            server: server.name.clone(),
+            ip: server.ip.clone(),
+            command,
+            status,
+            exit_code: server.last_exit_code(),
+            duration_secs: server.last_duration().map(|d| d.as_secs_f64()),
+            output_tail: output_tail(&server.get_output(), WEBHOOK_TAIL_LINES),
+        }
+    }
+}
+
+const WEBHOOK_TAIL_LINES: usize = 20;
+
+// POST in the background so a slow endpoint never holds up the run
+fn send_webhook(url: String, payload: WebhookPayload, server: ServerState) {
+    thread::spawn(move || {
+        let sent = reqwest::blocking::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .and_then(|client| client.post(&url).json(&payload).send())
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = sent {
+            server.append_output(&format!(">>> Webhook failed: {}", e));
+        }
+    });
+}
+
+// The last `lines` lines of an output
+fn output_tail(text: &str, lines: usize) -> String {
+    let all: Vec<&str> = text.lines().collect();
+    all[all.len().saturating_sub(lines)..].join("\n")
+}
+
+// Sidebar "last seen" hint, e.g. "3d ago"
+fn format_ago(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        "just now".to_string()
+    } else if secs < 3600 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 86400 {
+        format!("{}h ago", secs / 3600)
+    } else {
+        format!("{}d ago", secs / 86400)
+    }
+}
+
+// 45s, 2m14s, 1h05m
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+// A command's output filter, letting our own ">>> " notices through
+fn filter_line<'a>(filter: Option<&config::OutputFilter>, line: &'a str) -> Option<&'a str> {
+    match filter {
+        Some(filter) if !line.starts_with(">>> ") => filter.apply(line),
+        _ => Some(line),
+    }
+}
+
+// Ask for a command's variables over SSH, with the inline form ready for servers that refuse
+fn set_command_env(target: &mut ssh::Target, command: &str, env: &[config::EnvVar], os: &OsType) {
+    if env.is_empty() {
+        return;
+    }
+    target.env = env.iter().map(|var| (var.name.clone(), var.value.clone())).collect();
+    target.env_command = Some(commands::with_env(command, &target.env, os));
+}
+
+// Hide secret_env values should the command print them
+fn mask_secrets<'a>(line: &'a str, env: &[config::EnvVar]) -> std::borrow::Cow<'a, str> {
+    let mut line = std::borrow::Cow::Borrowed(line);
+    for var in env.iter().filter(|var| var.secret && !var.value.is_empty()) {
+        if line.contains(&var.value) {
+            line = std::borrow::Cow::Owned(line.replace(&var.value, "****"));
+        }
+    }
+    line
+}
+
+// The shell line a task runs, variables inline, for pasting into a terminal.
+// Secret values are left as **** for whoever pastes it to fill in.
+fn shell_command(task: &Task, env: &[config::EnvVar], os: &OsType) -> Option<String> {
+    match task {
+        Task::Exec(cmd) if !env.is_empty() => {
+            let env: Vec<(String, String)> = env.iter()
+                .map(|var| (var.name.clone(), if var.secret { "****".to_string() } else { var.value.clone() }))
+                .collect();
+            Some(commands::with_env(cmd, &env, os))
+        }
+        Task::Exec(cmd) | Task::Reboot(cmd) => Some(cmd.clone()),
+        _ => None,
+    }
+}
+
+fn is_powershell_script(path: &std::path::Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ps1"))
+}
+
+// `ssh` invocation logging in to a server the way the app does
+fn ssh_args(server: &ServerState) -> Vec<String> {
+    let mut args = vec!["ssh".to_string(), "-p".to_string(), server.port.to_string()];
+    if let Some(ref key) = server.key_path {
+        args.push("-i".to_string());
+        args.push(key.display().to_string());
+    }
+    if let Some(ref jump) = server.jump_host {
+        args.push("-J".to_string());
+        args.push(format!("{}@{}", jump.username, ssh::format_address(jump.hostname(), jump.port())));
+    }
+    args.push(format!("{}@{}", server.username, server.host));
+    args
+}
+
+// The configured terminal with the ssh command appended, or the platform's usual one
+fn terminal_command(configured: Option<&str>, ssh: &[String]) -> Vec<String> {
+    let mut command: Vec<String> = match configured.map(str::trim).filter(|c| !c.is_empty()) {
+        Some(configured) => configured.split_whitespace().map(String::from).collect(),
+        // The empty argument is start's window title
+        None if cfg!(windows) => ["cmd", "/c", "start", ""].map(String::from).to_vec(),
+        None if cfg!(target_os = "macos") => {
+            // Terminal.app only takes a script, so the command goes in as one string
+            let line = ssh.iter().map(|arg| shell_quote(arg)).collect::<Vec<_>>().join(" ");
+            let script = format!("tell application \"Terminal\" to do script \"{}\"", line.replace('\\', "\\\\").replace('"', "\\\""));
+            return vec!["osascript".to_string(), "-e".to_string(), script];
+        }
+        None => ["x-terminal-emulator", "-e"].map(String::from).to_vec(),
+    };
+    command.extend(ssh.iter().cloned());
+    command
+}
+
+// Single-quote an argument for a POSIX shell when it needs it
+fn shell_quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:@=[]".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+// What a dry run prints for one server's task, secret values masked
+fn dry_run_lines(task: &Task, env: &[config::EnvVar]) -> Vec<String> {
+    let mut lines = match task {
+        Task::Exec(cmd) => std::iter::once(">>> Would run:".to_string())
+            .chain(cmd.lines().map(|line| format!("    {}", line)))
+            .collect(),
+        Task::Reboot(cmd) => vec![format!(">>> Would reboot with: {}", cmd)],
+        Task::Script { local, contents } => vec![format!(">>> Would run {} ({} lines) from stdin", local.display(), contents.lines().count())],
+        other => vec![format!(">>> Would do: {}", other.describe())],
+    };
+    if matches!(task, Task::Exec(_)) {
+        for var in env {
+            let value = if var.secret { "****" } else { var.value.as_str() };
+            lines.push(format!(">>> With {}={}", var.name, value));
+        }
+    }
+    lines
+}
+
+const ERROR_SUMMARY_CHARS: usize = 160;
+
+// The sidebar tooltip's one line: the error, then the first thing the command
+// said on stderr, which already went through secret masking on its way in
+fn error_summary(error: &str, raw_output: &str) -> String {
+    let mut summary = error.lines().next().unwrap_or_default().trim().to_string();
+    if let Some(line) = raw_output.lines().find_map(|line| line.strip_prefix(ssh::STDERR_PREFIX)) {
+        summary.push_str(" - ");
+        summary.push_str(plain_text(&parse_clixml(line)).trim());
+    }
+    if summary.chars().count() > ERROR_SUMMARY_CHARS {
+        summary = format!("{}...", summary.chars().take(ERROR_SUMMARY_CHARS).collect::<String>());
+    }
+    summary
+}
+
+// Non-zero codes are colored red through the ANSI stage
+fn exit_code_line(code: u32) -> String {
+    if code == 0 {
+        ">>> Exit code: 0".to_string()
+    } else {
+        format!("\x1b[31m>>> Exit code: {}\x1b[0m", code)
+    }
+}
+
+// A server's output as diffed by "Compare": parsed, without colors or timestamps
+fn comparable_output(raw: &str) -> String {
+    raw.lines().map(|line| plain_text(&parse_clixml(line))).collect::<Vec<_>>().join("\n")
+}
+
+// Line diff of two outputs, in order: Delete is only in `a`, Insert only in `b`.
+// Past the deadline similar settles for a coarser, still correct diff.
+fn diff_lines(a: &str, b: &str) -> Vec<(similar::ChangeTag, String)> {
+    similar::TextDiff::configure()
+        .timeout(Duration::from_millis(200))
+        .diff_lines(a, b)
+        .iter_all_changes()
+        .map(|change| (change.tag(), change.value().trim_end_matches(['\r', '\n']).to_string()))
+        .collect()
+}
+
+// Default file name for a saved output, e.g. web-01-20240131-142500.log
+fn output_file_name(server_name: &str) -> String {
+    format!("{}-{}.log", file_safe(server_name), chrono::Local::now().format("%Y%m%d-%H%M%S"))
+}
+
+// A server name as one path component: separators and anything unusual become _
+fn file_safe(name: &str) -> String {
+    name.chars().map(|c| if c.is_alphanumeric() || "-_.".contains(c) { c } else { '_' }).collect()
+}
+
+// Daily output log, e.g. logs/web-01-2026-10-14.log
+fn log_file_name(server_name: &str, date: chrono::NaiveDate) -> String {
+    format!("{}-{}.log", file_safe(server_name), date.format("%Y-%m-%d"))
+}
+
+// The newest daily log for this server, ignoring servers whose name merely starts the same
+fn latest_log(dir: &std::path::Path, server_name: &str) -> Option<PathBuf> {
+    let prefix = format!("{}-", file_safe(server_name));
+    std::fs::read_dir(dir).ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let date = name.strip_prefix(&prefix)?.strip_suffix(".log")?;
+            Some((chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?, path))
+        })
+        .max_by_key(|(date, _)| *date)
+        .map(|(_, path)| path)
+}
+
+// A server's output log on disk, reopened when the date changes
+struct OutputLog {
+    dir: PathBuf,
+    file: Option<(chrono::NaiveDate, std::fs::File)>,
+}
+
+// Suggested log to pull back when no remote path is typed
+fn default_download_path(os_type: &OsType) -> &'static str {
+    match os_type {
+        OsType::Linux => "/var/log/apt/history.log",
+        OsType::Windows => "C:/Windows/Logs/CBS/CBS.log",
+        OsType::Macos => "/var/log/install.log",
+    }
+}
+
+// What a run does on each server
+#[derive(Clone, PartialEq)]
+enum Task {
+    Exec(String),
+    Upload { local: PathBuf, remote: String },
+    Download { remote: String, local: PathBuf },
+    Script { local: PathBuf, contents: String },  // Piped to bash, or PowerShell for a .ps1
+    Reboot(String),  // Connection drop afterwards is expected
+    Service(String),  // Status of a named service, the command depends on the OS
+    Diagnose,  // Server identification and offered auth methods, no login
+}
+
+impl Task {
+    // Short one-line label for lists
+    fn describe(&self) -> String {
+        match self {
+            Task::Exec(cmd) => {
+                let line = cmd.lines().next().unwrap_or_default().trim();
+                if line.chars().count() > 60 {
+                    format!("{}...", line.chars().take(60).collect::<String>())
+                } else {
+                    line.to_string()
+                }
+            }
+            Task::Upload { local, remote } => format!("Upload {} -> {}", local.display(), remote),
+            Task::Download { remote, local } => format!("Download {} -> {}", remote, local.display()),
+            Task::Script { local, .. } => format!("Script {}", local.file_name().unwrap_or_default().to_string_lossy()),
+            Task::Reboot(_) => "Reboot".to_string(),
+            Task::Service(name) => format!("Service status: {}", name),
+            Task::Diagnose => "Diagnose connection".to_string(),
+        }
+    }
+
+    // Turn OS-dependent tasks into the shell command for this server
+    fn for_os(self, os: &OsType) -> Task {
+        match (self, os) {
+            (Task::Service(name), OsType::Linux) => Task::Exec(commands::service_status_linux_cmd(&name)),
+            (Task::Service(name), OsType::Windows) => Task::Exec(commands::service_status_windows_cmd(&name)),
+            (Task::Service(name), OsType::Macos) => Task::Exec(commands::service_status_macos_cmd(&name)),
+            (other, _) => other,
+        }
+    }
+
+    // for_os, then the server's own update_cmd / info_cmd in place of the presets
+    fn for_server(self, server: &ServerState) -> Task {
+        match self.for_os(&server.os_type) {
+            Task::Exec(cmd) => match server.override_for(&cmd) {
+                Some(own) => Task::Exec(own.to_string()),
+                None => Task::Exec(cmd),
+            },
+            other => other,
+        }
+    }
+
+    // Route any sudo in a shell command through commands::with_sudo
+    fn with_sudo(self, has_password: bool) -> Task {
+        match self {
+            Task::Exec(cmd) if cmd.contains("sudo") => Task::Exec(commands::with_sudo(&cmd, has_password)),
+            Task::Reboot(cmd) if cmd.contains("sudo") => Task::Reboot(commands::with_sudo(&cmd, has_password)),
+            other => other,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+struct PendingCommand {
+    task: Task,
+    os_filter: Option<OsType>,
+    server: Option<usize>,  // Restrict to one server by index
+    tag: Option<String>,  // Restrict to servers whose first tag matches
+    second_factor: Option<Zeroizing<String>>,  // One-time code, never kept in last_command
+    pty: bool,  // Run shell commands in a pseudo-terminal
+    selection: Option<HashSet<usize>>,  // Restrict to these server indices
+    filter: Option<config::OutputFilter>,  // Keep only matching output, from a [[commands]] entry
+    timeout: Option<Duration>,  // Overrides command_timeout, from a [[commands]] entry
+    env: Vec<config::EnvVar>,  // Environment for shell commands, from a [[commands]] entry
+}
+
+const HISTORY_LIMIT: usize = 50;
+const SELECTED_TAB_KEY: &str = "selected_tab";
+
+// Shown in the "?" overlay; handled in handle_shortcuts
+const SHORTCUTS: [(&str, &str); 7] = [
+    ("Ctrl+T", "Test all servers"),
+    ("Ctrl+L", "Info on Linux servers"),
+    ("Ctrl+W", "Info on Windows servers"),
+    ("1 - 9", "Show that server's output"),
+    ("Ctrl+F", "Find in output"),
+    ("Esc", "Cancel the password dialog"),
+    ("? / F1", "Show or hide this list"),
+];
+const GRID_VIEW_KEY: &str = "grid_view";
+const WRAP_OUTPUT_KEY: &str = "wrap_output";
+const LAST_SEEN_KEY: &str = "last_seen";  // Server name -> seconds since the Unix epoch
+
+struct HistoryEntry {
+    at: chrono::DateTime<chrono::Local>,
+    command: PendingCommand,
+}
+
+impl PendingCommand {
+    fn targets(&self, index: usize, server: &ServerState) -> bool {
+        if let Some(only) = self.server
+            && only != index
+        {
+            return false;
+        }
+        if let Some(ref tag) = self.tag
+            && server.tags.first() != Some(tag)
+        {
+            return false;
+        }
+        if let Some(ref selection) = self.selection
+            && !selection.contains(&index)
+        {
+            return false;
+        }
+        // A script only goes to servers with the shell it was written for
+        if let Task::Script { ref local, .. } = self.task
+            && is_powershell_script(local) == server.os_type.is_unix()
+        {
+            return false;
+        }
+        match self.os_filter {
+            Some(ref os) => &server.os_type == os,
+            None => true,
+        }
+    }
+}
+
+impl ServerManagerApp {
+    fn new(config_path: String) -> Self {
+        let mut app = Self {
+            servers: Vec::new(),
+            config_path,
+            config_error: None,
+            connect_timeout: Duration::from_secs(10),
+            keepalive_interval: None,
+            session_idle_timeout: None,
+            stall_warning: None,
+            connect_attempts: 1,
+            command_timeout: None,
+            sequential: false,
+            wave_size: 1,
+            stop_on_error: true,
+            custom_commands: Vec::new(),
+            selected_tab: 0,
+            passwords: HashMap::new(),
+            password_input: Zeroizing::new(String::new()),
+            password_needed_for: None,
+            password_error: None,
+            remember_password: false,
+            remember: HashSet::new(),
+            same_for_user: false,
+            webhook: None,
+            terminal: None,
+            forget_after_use: false,
+            abort_on_auth_failure: false,
+            second_factor_for: None,
+            sudo_passwords: HashMap::new(),
+            sudo_for: None,
+            use_for_sudo: true,
+            pending_command: None,
+            last_command: None,
+            history: Vec::new(),
+            confirm_reboot: None,
+            confirm_mismatch: None,
+            confirm_run: None,
+            view_script: None,
+            compare: None,
+            compare_diff: None,
+            confirm_kill: None,
+            dry_run: false,
+            server_search: String::new(),
+            sidebar_os: None,
+            selection: HashSet::new(),
+            sidebar_order: Vec::new(),
+            upload_remote_path: "/tmp/".to_string(),
+            upload_os_filter: None,
+            download_remote_path: String::new(),
+            command_input: String::new(),
+            stdin_input: String::new(),
+            command_pty: false,
+            service_name: String::new(),
+            pty: ssh::PtyRequest { term: "xterm-256color".to_string(), columns: 120, rows: 40 },
+            command_os_filter: None,
+            notice: None,
+            repaint: None,
+            show_timestamps: false,
+            collapse_repeats: false,
+            grid_view: false,
+            wrap_output: false,
+            show_shortcuts: false,
+            usage_warning: (90.0, 90.0),
+            show_raw: false,
+            notify_when_done: false,
+            find_open: false,
+            find_query: String::new(),
+            find_case_sensitive: false,
+            find_current: 0,
+            find_scroll: false,
+            output_focused: false,
+            output_selection: String::new(),
+            metrics: None,
+            queues: Vec::new(),
+            password_file: None,
+            unlock: None,
+        };
+        app.reload_config();
+        app
+    }
+
+    // Bring back what was saved on the last exit; the config may have fewer servers now
+    fn restore(&mut self, storage: &dyn eframe::Storage) {
+        if let Some(tab) = eframe::get_value::<usize>(storage, SELECTED_TAB_KEY)
+            && tab < self.servers.len()
+        {
+            self.selected_tab = tab;
+        }
+        self.grid_view = eframe::get_value(storage, GRID_VIEW_KEY).unwrap_or(false);
+        self.wrap_output = eframe::get_value(storage, WRAP_OUTPUT_KEY).unwrap_or(false);
+        let last_seen: HashMap<String, u64> = eframe::get_value(storage, LAST_SEEN_KEY).unwrap_or_default();
+        for server in &self.servers {
+            if let Some(&secs) = last_seen.get(&server.name) {
+                *server.last_success.lock().unwrap() = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+            }
+        }
+    }
+
+    // (Re)load the config file. Servers are matched by name so existing ones keep
+    // their output and status, and running commands carry on undisturbed.
+    // On failure the current servers stay and the error is shown.
+    fn reload_config(&mut self) {
+        let cfg = match config::load_config(&self.config_path) {
+            Ok(cfg) => cfg,
+            Err(e) => {
+                self.config_error = Some(if self.servers.is_empty() {
+                    e.to_string()
+                } else {
+                    format!("Reload failed: {}", e)
+                });
+                return;
+            }
+        };
+
+        let old_names: Vec<String> = self.servers.iter().map(|s| s.name.clone()).collect();
+        let mut old = std::mem::take(&mut self.servers);
+        for server in &cfg.servers {
+            let state = match old.iter().position(|s| s.name == server.name) {
+                Some(pos) => {
+                    let mut state = old.remove(pos);
+                    state.update_from(server, cfg.max_output_lines());
+                    state.set_log_dir(cfg.log_dir.as_ref());
+                    state
+                }
+                None => {
+                    let state = ServerState::new(server, cfg.max_output_lines());
+                    state.set_timestamps(self.show_timestamps);
+                    state.set_collapse_repeats(self.collapse_repeats);
+                    state.set_log_dir(cfg.log_dir.as_ref());
+                    if cfg.reload_logs {
+                        state.load_latest_log(cfg.max_output_lines());
+                    }
+                    state
+                }
+            };
+            self.servers.push(state);
+        }
+
+        self.config_error = None;
+        if let Some(ref shared) = self.metrics {
+            *shared.lock().unwrap() = self.servers.clone();
+        }
+        self.connect_timeout = cfg.connect_timeout();
+        self.keepalive_interval = cfg.keepalive_interval();
+        self.session_idle_timeout = cfg.session_idle_timeout();
+        self.stall_warning = cfg.stall_warning();
+        self.connect_attempts = cfg.connect_attempts();
+        self.command_timeout = cfg.command_timeout();
+        self.sequential = cfg.sequential;
+        self.wave_size = cfg.wave_size();
+        self.stop_on_error = cfg.stop_on_error();
+        self.forget_after_use = cfg.forget_passwords;
+        self.abort_on_auth_failure = cfg.abort_on_auth_failure;
+        if cfg.password_file != self.password_file {
+            credentials::use_password_file(cfg.password_file.as_deref());
+            self.unlock = cfg.password_file.clone().map(UnlockDialog::new);
+            self.password_file = cfg.password_file.clone();
+        }
+        self.terminal = cfg.terminal.clone();
+        self.webhook = cfg.webhook_url.clone().map(|url| (url, cfg.webhook_on));
+        self.usage_warning = cfg.usage_warning();
+        let (columns, rows) = cfg.pty_size();
+        self.pty = ssh::PtyRequest { term: cfg.pty_term().to_string(), columns, rows };
+        self.custom_commands = cfg.commands;
+
+        // Server indices held elsewhere now point into the new list
+        let servers = &self.servers;
+        let remap = |index: usize| {
+            old_names.get(index).and_then(|name| servers.iter().position(|s| &s.name == name))
+        };
+        let remap_set = |set: &HashSet<usize>| -> HashSet<usize> { set.iter().filter_map(|&i| remap(i)).collect() };
+        let remap_command = |command: &mut PendingCommand| {
+            if let Some(ref mut selection) = command.selection {
+                *selection = remap_set(selection);
+                if selection.is_empty() {
+                    return false;
+                }
+            }
+            match command.server {
+                Some(index) => match remap(index) {
+                    Some(new_index) => {
+                        command.server = Some(new_index);
+                        true
+                    }
+                    None => false,
+                },
+                None => true,
+            }
+        };
+        self.selected_tab = remap(self.selected_tab).unwrap_or(0);
+        self.selection = remap_set(&self.selection);
+        self.sudo_for = self.sudo_for.and_then(remap);
+        self.compare = self.compare.and_then(|(a, b)| Some((remap(a)?, remap(b)?)));
+        self.confirm_kill = self.confirm_kill.take().and_then(|(i, process)| Some((remap(i)?, process)));
+        self.history.retain_mut(|entry| remap_command(&mut entry.command));
+        for slot in [&mut self.last_command, &mut self.pending_command, &mut self.confirm_reboot, &mut self.confirm_run] {
+            if let Some(command) = slot
+                && !remap_command(command)
+            {
+                *slot = None;
+            }
+        }
+        if let Some((ref mut command, _)) = self.confirm_mismatch
+            && !remap_command(command)
+        {
+            self.confirm_mismatch = None;
+        }
+        self.second_factor_for = self.second_factor_for
+            .take()
+            .and_then(|(index, prompt)| Some((remap(index)?, prompt)));
+    }
+
+    fn get_missing_passwords(&self, pending: &PendingCommand) -> Vec<String> {
+        let mut missing = Vec::new();
+        for (i, server) in self.servers.iter().enumerate() {
+            if !pending.targets(i, server) {
+                continue;
+            }
+            let mut logins = vec![(server.login_key(), &server.key_path)];
+            if let Some(ref jump) = server.jump_host {
+                logins.push((login_key(&jump.username, jump.hostname()), &jump.key_path));
+            }
+            for (login, key_path) in logins {
+                // Key-based logins never need a password, and agent users
+                // are only prompted once the agent's keys have been refused
+                if key_path.is_some() || ssh::agent_available() {
+                    continue;
+                }
+                if !self.passwords.contains_key(&login) && !missing.contains(&login) {
+                    missing.push(login);
+                }
+            }
+        }
+        missing
+    }
+
+    fn auth_for(&self, login: &str, key_path: &Option<PathBuf>) -> Option<ssh::AuthMethod> {
+        match (key_path, self.passwords.get(login)) {
+            (Some(key), _) => Some(ssh::AuthMethod::Key(key.clone())),
+            (None, Some(pw)) => Some(ssh::AuthMethod::Password(pw.clone())),
+            (None, None) if ssh::agent_available() => Some(ssh::AuthMethod::Agent),
+            (None, None) => None,
+        }
+    }
+
+    fn check_auth_failures(&mut self) {
+        for (i, server) in self.servers.iter().enumerate() {
+            if server.take_sudo_prompt() {
+                let login = server.login_key();
+                self.password_error = Some(if self.sudo_passwords.remove(&login).is_some() {
+                    format!("sudo rejected the password on {}. Please try again.", server.name)
+                } else {
+                    format!("sudo on {} needs a password.", server.name)
+                });
+                self.sudo_for = Some(i);
+                self.password_needed_for = Some(login);
+                self.password_input.zeroize();
+
+                if let Some(ref last) = self.last_command {
+                    let mut retry = last.clone();
+                    retry.server = Some(i);
+                    self.pending_command = Some(retry);
+                }
+                break;
+            }
+
+            if let Some(prompt) = server.take_second_factor_prompt() {
+                self.second_factor_for = Some((i, prompt));
+                self.password_needed_for = Some(server.login_key());
+                self.password_error = None;
+                self.password_input.zeroize();
+
+                // Retry the last command on this server once the code is entered
+                if let Some(ref last) = self.last_command {
+                    let mut retry = last.clone();
+                    retry.server = Some(i);
+                    self.pending_command = Some(retry);
+                }
+                break;
+            }
+
+            if server.auth_failed() {
+                server.set_auth_failed(false);
+                let login = server.login_key();
+                let wrong = self.passwords.remove(&login).is_some();
+                if wrong {
+                    // A remembered password that stopped working is no use next launch either
+                    let _ = credentials::forget_password(&login);
+                }
+                // Leave it failed and look at the rest, nobody may be there to answer
+                if self.abort_on_auth_failure {
+                    server.append_output(">>> Not asking for another password (abort_on_auth_failure is set)");
+                    continue;
+                }
+                self.password_error = Some(if wrong {
+                    format!("Wrong password for '{}'. Please try again.", login)
+                } else {
+                    format!("SSH agent keys were refused for '{}'. Please enter a password.", login)
+                });
+                self.password_needed_for = Some(login);
+                self.password_input.zeroize();
+
+                // Set up pending command to retry the last command
+                if let Some(ref last) = self.last_command {
+                    self.pending_command = Some(last.clone());
+                }
+                break;
+            }
+        }
+    }
+
+    // Serve the servers' state for Prometheus on 127.0.0.1:port
+    fn start_metrics(&mut self, port: u16) -> std::io::Result<()> {
+        let shared = Arc::new(Mutex::new(self.servers.clone()));
+        let servers = shared.clone();
+        metrics::serve(port, move || {
+            servers.lock().unwrap().iter().map(ServerState::metrics).collect()
+        })?;
+        self.metrics = Some(shared);
+        Ok(())
+    }
+
+    // Interactive session in the system ssh client, for what exec can't do
+    fn open_terminal(&mut self, index: usize) {
+        let server = &self.servers[index];
+        let command = terminal_command(self.terminal.as_deref(), &ssh_args(server));
+        let name = server.name.clone();
+        match std::process::Command::new(&command[0]).args(&command[1..]).spawn() {
+            Ok(_) => self.notify(format!("Opened a terminal for {}", name), false),
+            Err(e) => self.notify(format!("Could not start {}: {}", command[0], e), true),
+        }
+    }
+
+    // "Forget after use": wipe passwords no running server still needs.
+    // Waits while a dialog is collecting passwords for a run that hasn't started.
+    fn forget_finished_passwords(&mut self) {
+        if !self.forget_after_use || self.pending_command.is_some() {
+            return;
+        }
+        let in_use: HashSet<String> = self.servers.iter()
+            .filter(|s| s.is_running())
+            .flat_map(|s| s.logins())
+            .collect();
+        self.passwords.retain(|login, _| in_use.contains(login));
+        self.sudo_passwords.retain(|login, _| in_use.contains(login));
+    }
+
+    // Connection details for a server, None while a password is still missing
+    fn build_target(&self, server: &ServerState, second_factor: Option<Zeroizing<String>>) -> Option<ssh::Target> {
+        let auth = self.auth_for(&server.login_key(), &server.key_path)?;
+        let jump = match server.jump_host {
+            Some(ref jump) => Some(Box::new(ssh::Target {
+                host: jump.hostname().to_string(),
+                port: jump.port(),
+                username: jump.username.clone(),
+                auth: self.auth_for(&login_key(&jump.username, jump.hostname()), &jump.key_path)?,
+                connect_timeout: self.connect_timeout,
+                keepalive_interval: self.keepalive_interval,
+                reuse_session: None,
+                jump: None,
+                second_factor: None,
+                stdin: None,
+                stdin_eof: false,
+                stall_warning: None,
+                input: None,
+                pty: None,
+                connect_attempts: self.connect_attempts,
+                on_retry: None,
+                command_timeout: None,
+                env: Vec::new(),
+                env_command: None,
+            })),
+            None => None,
+        };
+        Some(ssh::Target {
+            host: server.host.clone(),
+            port: server.port,
+            username: server.username.clone(),
+            auth,
+            connect_timeout: self.connect_timeout,
+            keepalive_interval: self.keepalive_interval,
+            reuse_session: self.session_idle_timeout,
+            jump,
+            second_factor,
+            stdin: None,
+            stdin_eof: false,
+            stall_warning: self.stall_warning,
+            input: None,
+            pty: None,
+            connect_attempts: self.connect_attempts,
+            on_retry: None,
+            command_timeout: self.command_timeout,
+            env: Vec::new(),
+            env_command: None,
+        })
+    }
+
+    fn run_command(&mut self, pending: PendingCommand) {
+        let batch = self.notify_when_done.then(|| Arc::new(Batch::new(pending.task.describe())));
+        let mut jobs = VecDeque::new();
+
+        for (i, server) in self.servers.iter().enumerate() {
+            if !pending.targets(i, server) {
+                continue;
+            }
+
+            if server.is_running() {
+                continue;
+            }
+
+            let Some(mut target) = self.build_target(server, pending.second_factor.clone()) else {
+                continue;
+            };
+
+            // Passwords to save once this server accepts them
+            let remember: Vec<(String, Zeroizing<String>)> = server.logins()
+                .into_iter()
+                .filter(|login| self.remember.contains(login))
+                .filter_map(|login| self.passwords.get(&login).map(|pw| (login.clone(), pw.clone())))
+                .collect();
+
+            let server_state = server.clone();
+            // sudo reads its password from stdin instead of a terminal we don't have
+            let mut task = pending.task.clone().for_server(server);
+            // Taken before the sudo wrapper, which only works with the password on stdin
+            *server.last_run.lock().unwrap() = shell_command(&task, &pending.env, &server.os_type);
+            if server.os_type.is_unix() {
+                let sudo_password = self.sudo_passwords.get(&server.login_key());
+                task = task.with_sudo(sudo_password.is_some());
+                target.stdin = sudo_password.map(|pw| Zeroizing::new(format!("{}\n", pw.as_str())));
+            }
+            target.input = Some(server.input.clone());
+            target.pty = pending.pty.then(|| self.pty.clone());
+            target.command_timeout = pending.timeout.or(target.command_timeout);
+            if let Task::Exec(ref command) = task {
+                set_command_env(&mut target, command, &pending.env, &server.os_type);
+            }
+            // The script goes in on stdin, after the sudo password when there is one
+            if let Task::Script { ref contents, .. } = task {
+                let mut input = Zeroizing::new(String::new());
+                let runner = match server.os_type {
+                    OsType::Windows => commands::run_script_windows_cmd().to_string(),
+                    OsType::Linux | OsType::Macos => {
+                        let sudo_password = self.sudo_passwords.get(&server.login_key());
+                        if let Some(password) = sudo_password {
+                            input.push_str(password);
+                            input.push('\n');
+                        }
+                        input.push_str(commands::script_sudo_preamble(sudo_password.is_some()));
+                        commands::run_script_linux_cmd(sudo_password.is_some()).to_string()
+                    }
+                };
+                input.push_str(contents);
+                target.stdin = Some(input);
+                target.stdin_eof = true;
+                task = Task::Exec(runner);
+            }
+            let retry_state = server.clone();
+            target.on_retry = Some(ssh::RetryNotice(Arc::new(move |notice| {
+                retry_state.append_output(&format!(">>> {}", notice));
+            })));
+
+            server_state.clear_output();
+            server_state.set_sudo_prompt(false);
+            server_state.input.lock().unwrap().clear();
+            server_state.cancel.store(false, Ordering::Relaxed);
+            server_state.set_running(true);
+            server_state.set_last_duration(None);
+            server_state.set_last_exit_code(None);
+            *server_state.last_error.lock().unwrap() = None;
+            server_state.set_status(if self.sequential { "Queued" } else { "Connecting..." });
+            server_state.log_run_start(&pending.task.describe());
+            match target.jump {
+                Some(ref jump) => server_state.append_output(&format!(
+                    ">>> Connecting to {}@{} via {}",
+                    target.username, target.host, jump.host
+                )),
+                None => server_state.append_output(&format!(">>> Connecting to {}@{}", target.username, target.host)),
+            }
+
+            let repaint = self.repaint.clone();
+            let usage_warning = self.usage_warning;
+            let filter = pending.filter.clone();
+            let env = pending.env.clone();
+            let webhook = self.webhook.clone();
+            let described = pending.task.describe();
+            let batch = batch.clone();
+            if let Some(ref batch) = batch {
+                batch.remaining.fetch_add(1, Ordering::SeqCst);
+            }
+
+            let name = server.name.clone();
+            let job: Job = Box::new(move |skip| {
+                if let Some(reason) = skip {
+                    server_state.append_output(&format!(">>> Skipped - {}", reason));
+                    server_state.set_status("Skipped");
+                    server_state.set_running(false);
+                    if let Some(batch) = batch {
+                        batch.finish(Some((server_state.name.clone(), server_state.get_status())));
+                    }
+                    return false;
+                }
+                let status_clone = server_state.status.clone();
+
+                server_state.set_status("Running...");
+                let started = Instant::now();
+
+                let (done_message, done_status) = match task {
+                    Task::Reboot(_) => (">>> Reboot command sent", "Rebooting"),
+                    _ => (">>> Done", "Done"),
+                };
+
+                // Whether a Windows update task was started or reattached to in this run
+                let mut update_task = matches!(&task, Task::Exec(cmd) if cmd == commands::resume_update_windows_cmd());
+
+                let result = match task {
+                    Task::Exec(cmd) => ssh::connect_and_execute_with_callback(
+                        &target,
+                        &cmd,
+                        &server_state.cancel,
+                        |line| {
+                            if commands::is_sudo_password_failure(line) {
+                                server_state.set_sudo_prompt(true);
+                            }
+                            if line.contains(commands::UPDATE_TASK_MARKER) {
+                                update_task = true;
+                            }
+                            if let Some(required) = commands::reboot_required(line) {
+                                *server_state.reboot_pending.lock().unwrap() = required;
+                            }
+                            // Shown as the sidebar's progress bar instead of in the output
+                            if let Some((done, step)) = commands::apt_progress(line) {
+                                *server_state.progress.lock().unwrap() = Some((done, step.to_string()));
+                                if let Some(ref ctx) = repaint {
+                                    ctx.request_repaint();
+                                }
+                                return;
+                            }
+                            if line.starts_with(ssh::STALL_WARNING) {
+                                server_state.set_status("Waiting for Input?");
+                            } else if server_state.get_status() == "Waiting for Input?" {
+                                server_state.set_status("Running...");
+                            }
+                            let line = mask_secrets(line, &env);
+                            if let Some(line) = filter_line(filter.as_ref(), &line) {
+                                server_state.append_output(line);
+                            }
+                            if let Some(ref ctx) = repaint {
+                                ctx.request_repaint();
+                            }
+                        },
+                    )
+                    .map(|(_, code)| {
+                        server_state.set_last_exit_code(code);
+                        if let Some(code) = code {
+                            server_state.append_output(&exit_code_line(code));
+                        }
+                        // Raw, so timestamps and color parsing can't get in the way
+                        let raw = server_state.raw_output.lock().unwrap().clone();
+                        server_state.set_info(commands::parse_info(&raw), usage_warning);
+                        if commands::is_top_processes(&cmd) {
+                            *server_state.processes.lock().unwrap() = Some(commands::parse_processes(&raw, &server_state.os_type));
+                        }
+                    }),
+                    Task::Upload { local, remote } => {
+                        server_state.append_output(&format!(">>> Uploading {} to {}", local.display(), remote));
+                        ssh::upload_file(&target, &local, &remote, &server_state.cancel, move |sent, total| {
+                            let percent = (sent * 100).checked_div(total).unwrap_or(100);
+                            *status_clone.lock().unwrap() = format!("Uploading {}%", percent);
+                        })
+                        .map(|sent| server_state.append_output(&format!(">>> Uploaded {} bytes", sent)))
+                    }
+                    Task::Download { remote, local } => {
+                        server_state.append_output(&format!(">>> Downloading {} to {}", remote, local.display()));
+                        ssh::download_file(&target, &remote, &local, &server_state.cancel, move |received, total| {
+                            let percent = (received * 100).checked_div(total).unwrap_or(100);
+                            *status_clone.lock().unwrap() = format!("Downloading {}%", percent);
+                        })
+                        .map(|received| server_state.append_output(&format!(">>> Downloaded {} bytes", received)))
+                    }
+                    Task::Reboot(cmd) => ssh::connect_and_reboot(&target, &cmd, &server_state.cancel),
+                    Task::Service(_) => unreachable!("resolved by Task::for_os"),
+                    Task::Script { .. } => unreachable!("turned into Task::Exec in run_command"),
+                    Task::Diagnose => ssh::diagnose(&target, |line| server_state.append_output(line)),
+                };
+
+                // A command that ran, even one that failed, means the login worked
+                let logged_in = match &result {
+                    Ok(_) => true,
+                    Err(e) => matches!(e.downcast_ref::<ssh::SshError>(), Some(ssh::SshError::CommandFailed { .. })),
+                };
+                let succeeded = result.is_ok();
+                if !matches!(&result, Err(e) if e.is::<ssh::Cancelled>()) {
+                    server_state.logged_in.store(logged_in, Ordering::Relaxed);
+                }
+                if logged_in {
+                    *server_state.last_success.lock().unwrap() = Some(SystemTime::now());
+                    for (login, password) in &remember {
+                        if let Err(e) = credentials::store_password(login, password) {
+                            server_state.append_output(&format!(">>> Could not remember password for {}: {}", login, e));
+                        }
+                    }
+                }
+
+                match result {
+                    Ok(_) => {
+                        if done_status == "Rebooting" {
+                            *server_state.reboot_pending.lock().unwrap() = false;
+                        }
+                        server_state.append_output("---");
+                        server_state.append_output(done_message);
+                        server_state.set_status(done_status);
+                    }
+                    Err(e) if e.is::<ssh::Cancelled>() => {
+                        server_state.append_output("---");
+                        server_state.append_output(">>> Cancelled");
+                        server_state.set_status("Cancelled");
+                    }
                     Err(e) => {
-                        let error_msg = e.to_string();
+                        server_state.failures.fetch_add(1, Ordering::Relaxed);
+                        // Connection errors can quote the server, which might echo a secret
+                        let error_msg = mask_secrets(&e.to_string(), &env).into_owned();
                         server_state.append_output("---");
                         server_state.append_output(&format!(">>> ERROR: {}", error_msg));
+                        let summary = error_summary(&error_msg, &server_state.raw_output.lock().unwrap());
+                        *server_state.last_error.lock().unwrap() = Some(summary);
+                        if let Some(ssh::SshError::CommandFailed { code, .. }) = e.downcast_ref::<ssh::SshError>() {
+                            server_state.set_last_exit_code(Some(*code));
+                            server_state.append_output(&exit_code_line(*code));
+                        }
+
+                        if let Some(host_key) = e.downcast_ref::<ssh::HostKeyError>() {
+                            match host_key {
+                                ssh::HostKeyError::Unknown { .. } => {
+                                    server_state.set_host_key_prompt(Some(host_key.clone()));
+                                    server_state.set_status("Unknown Host Key");
+                                }
+                                ssh::HostKeyError::Mismatch { .. } => {
+                                    server_state.set_status("Host Key Mismatch");
+                                }
+                            }
+                        } else if let Some(needed) = e.downcast_ref::<ssh::SecondFactorRequired>() {
+                            server_state.set_second_factor_prompt(Some(needed.prompt.clone()));
+                            server_state.set_status("Needs Second Factor");
+                        } else if let Some(ssh_error) = e.downcast_ref::<ssh::SshError>() {
+                            // Key logins and jump host logins can't be fixed by re-prompting
+                            if let ssh::SshError::Auth { host } = ssh_error
+                                && host == &target.host
+                                && !matches!(target.auth, ssh::AuthMethod::Key(_))
+                            {
+                                server_state.set_auth_failed(true);
+                            }
+                            server_state.set_status(&ssh_error_status(ssh_error));
+                        } else {
+                            server_state.set_status("Error");
+                        }
+                    }
+                }
+
+                // The scheduled task carries on without us when the session drops or is stopped
+                if update_task {
+                    *server_state.update_interrupted.lock().unwrap() = !logged_in;
+                    if !logged_in {
+                        server_state.append_output(">>> The update is still running on the server - Resume Update reattaches to it");
+                        server_state.set_status("Update Interrupted");
+                    }
+                }
+
+                if *server_state.sudo_prompt.lock().unwrap() {
+                    server_state.append_output(">>> sudo password required - enter it to run again");
+                    server_state.set_status("Sudo Password Required");
+                }
+
+                server_state.set_last_duration(Some(started.elapsed()));
+                *server_state.progress.lock().unwrap() = None;
+                server_state.set_running(false);
+                if let Some(ref ctx) = repaint {
+                    ctx.request_repaint();
+                }
+                if let Some((url, on)) = webhook
+                    && (on == config::WebhookOn::All || !succeeded)
+                {
+                    send_webhook(url, WebhookPayload::new(&server_state, described), server_state.clone());
+                }
+                if let Some(batch) = batch {
+                    batch.finish(Some((server_state.name.clone(), server_state.get_status())));
+                }
+                succeeded
+            });
+            jobs.push_back(QueuedJob { server: name, task: pending.task.describe(), job });
+        }
+
+        let (wave_size, stop_on_error) = if self.sequential {
+            (self.wave_size, self.stop_on_error)
+        } else {
+            (jobs.len(), false)
+        };
+        let queue: JobQueue = Arc::new(Mutex::new(jobs));
+        if self.sequential {
+            self.queues.push(queue.clone());
+        }
+        thread::spawn(move || run_in_waves(&queue, wave_size, stop_on_error));
+
+        // Drop the spawner's hold; fires now if every worker already finished
+        if let Some(batch) = batch {
+            batch.finish(None);
+        }
+
+        self.remember.clear();
+
+        // Store as last command for potential retry
+        let pending = PendingCommand {
+            second_factor: None,
+            ..pending
+        };
+        self.record_history(&pending);
+        self.last_command = Some(pending);
+    }
+
+    fn record_history(&mut self, command: &PendingCommand) {
+        if self.history.last().is_some_and(|entry| &entry.command == command) {
+            return;
+        }
+        self.history.push(HistoryEntry {
+            at: chrono::Local::now(),
+            command: command.clone(),
+        });
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+    }
+
+    // Where a command ran, for display
+    fn describe_scope(&self, command: &PendingCommand) -> String {
+        if let Some(index) = command.server {
+            return self.servers.get(index).map(|s| s.name.clone()).unwrap_or_default();
+        }
+        if let Some(ref tag) = command.tag {
+            return format!("#{}", tag);
+        }
+        if let Some(ref selection) = command.selection {
+            return format!("{} selected", selection.len());
+        }
+        match &command.os_filter {
+            None => "All".to_string(),
+            Some(os) => os.label().to_string(),
+        }
+    }
+
+    fn start_command(&mut self, command: &str, os_filter: Option<OsType>, pty: bool) {
+        let pending = PendingCommand {
+            task: Task::Exec(command.to_string()),
+            os_filter,
+            server: None,
+            tag: None,
+            second_factor: None,
+            pty,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        };
+        self.start_checked(pending);
+    }
+
+    // A [[commands]] button, with its filter
+    fn start_custom(&mut self, custom: &CustomCommand) {
+        self.start_checked(PendingCommand {
+            task: Task::Exec(custom.script.clone()),
+            os_filter: custom.os.clone(),
+            server: None,
+            tag: None,
+            second_factor: None,
+            pty: custom.pty,
+            selection: None,
+            filter: custom.filter.clone(),
+            timeout: custom.timeout(),
+            env: custom.env_vars(),
+        });
+    }
+
+    // Running on every OS: ask first if the command looks like it's for one of them
+    fn start_checked(&mut self, pending: PendingCommand) {
+        if let Some(os) = self.os_mismatch(&pending) {
+            self.confirm_mismatch = Some((pending, os));
+        } else {
+            self.start_pending(pending);
+        }
+    }
+
+    // The OS a typed command seems meant for, when it would also hit servers of the other one
+    fn os_mismatch(&self, pending: &PendingCommand) -> Option<OsType> {
+        let Task::Exec(ref command) = pending.task else {
+            return None;
+        };
+        if pending.os_filter.is_some() {
+            return None;
+        }
+        let os = commands::likely_os(command)?;
+        self.servers.iter().enumerate()
+            .any(|(i, s)| pending.targets(i, s) && !same_shell(&s.os_type, &os))
+            .then_some(os)
+    }
+
+    fn start_command_on(&mut self, index: usize, command: &str) {
+        self.start_pending(PendingCommand {
+            task: Task::Exec(command.to_string()),
+            os_filter: None,
+            server: Some(index),
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
+
+    // TCP probe of every idle server's first hop, no login; sets Online/Offline
+    fn ping_all(&mut self) {
+        let timeout = self.connect_timeout.min(Duration::from_secs(3));
+        for server in &self.servers {
+            if server.is_running() {
+                continue;
+            }
+            let (host, port) = match server.jump_host {
+                Some(ref jump) => (jump.hostname().to_string(), jump.port()),
+                None => (server.host.clone(), server.port),
+            };
+            let generation = server.ping_generation.fetch_add(1, Ordering::Relaxed) + 1;
+            server.set_status("Pinging...");
+            let job = PingJob {
+                server: server.clone(),
+                host,
+                port,
+                timeout,
+                generation,
+                repaint: self.repaint.clone(),
+            };
+            // The workers never exit, so the channel stays open
+            let _ = ping_pool().send(job);
+        }
+    }
 
-                        if error_msg.contains("Authentication failed") {
-                            server_state.set_auth_failed(true);
-                            server_state.set_status("Auth Failed");
-                        } else {
-                            server_state.set_status("Error");
-                        }
-                    }
-                }
+    // systemctl status / Get-Service for the name in the service field
+    fn check_service(&mut self) {
+        let name = self.service_name.trim().to_string();
+        if !commands::is_valid_service_name(&name) {
+            self.notify(format!("'{}' is not a valid service name", name), true);
+            return;
+        }
+        self.start_pending(PendingCommand {
+            task: Task::Service(name),
+            os_filter: self.command_os_filter.clone(),
+            server: None,
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
 
-                server_state.set_running(false);
-            });
+    fn start_upload(&mut self) {
+        let Some(local) = rfd::FileDialog::new().set_title("Upload file").pick_file() else {
+            return;
+        };
+        // A trailing slash means "into this directory"
+        let mut remote = self.upload_remote_path.trim().to_string();
+        if remote.is_empty() || remote.ends_with('/') {
+            let file_name = local.file_name().unwrap_or_default().to_string_lossy();
+            remote.push_str(&file_name);
+        }
+        self.start_pending(PendingCommand {
+            task: Task::Upload { local, remote },
+            os_filter: self.upload_os_filter.clone(),
+            server: None,
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
+
+    // Sends the file as it is now; a rerun from History sends the same contents again
+    fn start_script(&mut self) {
+        let Some(local) = rfd::FileDialog::new()
+            .set_title("Run script")
+            .add_filter("Scripts", &["sh", "bash", "ps1"])
+            .add_filter("All files", &["*"])
+            .pick_file()
+        else {
+            return;
+        };
+        let contents = match std::fs::read_to_string(&local) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.notify(format!("Could not read {}: {}", local.display(), e), true);
+                return;
+            }
+        };
+        self.start_pending(PendingCommand {
+            task: Task::Script { local, contents },
+            os_filter: None,
+            server: None,
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
+
+    fn start_download(&mut self, index: usize) {
+        let server = &self.servers[index];
+        let remote = match self.download_remote_path.trim() {
+            "" => default_download_path(&server.os_type).to_string(),
+            path => path.to_string(),
+        };
+        let file_name = remote.rsplit(['/', '\\']).next().unwrap_or_default();
+        let Some(local) = rfd::FileDialog::new()
+            .set_title("Save downloaded file")
+            .set_file_name(format!("{}-{}", server.name, file_name))
+            .save_file()
+        else {
+            return;
+        };
+        self.start_pending(PendingCommand {
+            task: Task::Download { remote, local },
+            os_filter: None,
+            server: Some(index),
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
+
+    // Reboots only run after the confirmation dialog
+    fn request_reboot(&mut self, os_type: OsType, server: Option<usize>) {
+        let command = match os_type {
+            OsType::Linux => commands::reboot_linux_cmd(),
+            OsType::Windows => commands::reboot_windows_cmd(),
+            OsType::Macos => commands::reboot_macos_cmd(),
+        };
+        self.confirm_reboot = Some(PendingCommand {
+            task: Task::Reboot(command.to_string()),
+            os_filter: Some(os_type),
+            server,
+            tag: None,
+            second_factor: None,
+            pty: false,
+            selection: None,
+            filter: None,
+            timeout: None,
+            env: Vec::new(),
+        });
+    }
+
+    // Short-lived message in the top panel
+    fn notify(&mut self, message: String, is_error: bool) {
+        self.notice = Some((message, is_error, Instant::now()));
+    }
+
+    fn save_output(&mut self, index: usize) {
+        let server = &self.servers[index];
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Save output")
+            .set_file_name(output_file_name(&server.name))
+            .add_filter("Log", &["log", "txt"])
+            .save_file()
+        else {
+            return;
+        };
+        match std::fs::write(&path, server.get_output()) {
+            Ok(_) => self.notify(format!("Saved {}", path.display()), false),
+            Err(e) => self.notify(format!("Could not save {}: {}", path.display(), e), true),
+        }
+    }
+
+    // Servers the last run targeted that ended in a connection or login failure
+    fn failed_servers(&self) -> HashSet<usize> {
+        let Some(ref last) = self.last_command else {
+            return HashSet::new();
+        };
+        self.servers.iter().enumerate()
+            .filter(|(i, server)| last.targets(*i, server) && !server.is_running() && is_retryable(&server.get_status()))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // The last command again, on just the servers where it failed
+    fn retry_failed(&mut self) {
+        let failed = self.failed_servers();
+        if let Some(last) = self.last_command.clone()
+            && !failed.is_empty()
+        {
+            self.start_pending(PendingCommand { selection: Some(failed), ..last });
+        }
+    }
+
+    // Machine-readable results of the last run on every server it targeted
+    fn export_json(&mut self) {
+        let Some(ref last) = self.last_command else {
+            return;
+        };
+        let export = RunExport {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            command: last.task.describe(),
+            results: self.servers.iter().enumerate()
+                .filter(|(i, server)| last.targets(*i, server))
+                .map(|(_, server)| RunResult {
+                    name: server.name.clone(),
+                    ip: server.ip.clone(),
+                    os: server.os_type.clone(),
+                    status: server.get_status(),
+                    exit_code: server.last_exit_code(),
+                    duration_secs: server.last_duration().map(|d| d.as_secs_f64()),
+                    output: server.get_output(),
+                })
+                .collect(),
+        };
+
+        let Some(path) = rfd::FileDialog::new()
+            .set_title("Export results")
+            .set_file_name(format!("run-{}.json", chrono::Local::now().format("%Y%m%d-%H%M%S")))
+            .add_filter("JSON", &["json"])
+            .save_file()
+        else {
+            return;
+        };
+        let written = serde_json::to_string_pretty(&export)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&path, json).map_err(|e| e.to_string()));
+        match written {
+            Ok(_) => self.notify(format!("Exported {}", path.display()), false),
+            Err(e) => self.notify(format!("Could not export {}: {}", path.display(), e), true),
+        }
+    }
+
+    fn save_all_outputs(&mut self) {
+        let Some(dir) = rfd::FileDialog::new().set_title("Save all outputs to...").pick_folder() else {
+            return;
+        };
+        for server in &self.servers {
+            let path = dir.join(output_file_name(&server.name));
+            if let Err(e) = std::fs::write(&path, server.get_output()) {
+                self.notify(format!("Could not save {}: {}", path.display(), e), true);
+                return;
+            }
+        }
+        self.notify(format!("Saved {} files to {}", self.servers.len(), dir.display()), false);
+    }
+
+    // Fill in passwords remembered in the keyring, returning those still missing
+    fn load_remembered_passwords(&mut self, pending: &PendingCommand) -> Vec<String> {
+        let mut missing = self.get_missing_passwords(pending);
+        missing.retain(|login| match credentials::load_password(login) {
+            Some(password) => {
+                self.passwords.insert(login.clone(), password);
+                false
+            }
+            None => true,
+        });
+        missing
+    }
+
+    // Updates, and [[commands]] marked confirm = true, are shown in a dialog first
+    fn needs_confirm(&self, pending: &PendingCommand) -> bool {
+        let Task::Exec(ref command) = pending.task else {
+            return false;
+        };
+        command == commands::update_linux_cmd()
+            || command == commands::update_windows_cmd()
+            || command == commands::update_macos_cmd()
+            || self.custom_commands.iter().any(|c| c.confirm && &c.script == command)
+    }
+
+    fn start_pending(&mut self, pending: PendingCommand) {
+        if !self.dry_run && self.needs_confirm(&pending) {
+            self.confirm_run = Some(pending);
+        } else {
+            self.start_confirmed(pending);
         }
     }
 
-    fn start_command(&mut self, command: &str, os_filter: Option<OsType>) {
-        let missing = self.get_missing_passwords(os_filter.as_ref());
+    fn start_confirmed(&mut self, pending: PendingCommand) {
+        if self.dry_run {
+            self.preview(&pending);
+            return;
+        }
+        let missing = self.load_remembered_passwords(&pending);
 
         if missing.is_empty() {
-            self.run_command(command, os_filter);
+            self.run_command(pending);
         } else {
-            self.pending_command = Some(PendingCommand {
-                command: command.to_string(),
-                os_filter,
-            });
+            self.pending_command = Some(pending);
             self.password_needed_for = Some(missing[0].clone());
             self.password_error = None;
-            self.password_input.clear();
+            self.password_input.zeroize();
+        }
+    }
+
+    // Dry run: print what each server would run, no connection or password needed
+    fn preview(&self, pending: &PendingCommand) {
+        for (i, server) in self.servers.iter().enumerate() {
+            if !pending.targets(i, server) || server.is_running() {
+                continue;
+            }
+            let mut task = pending.task.clone().for_server(server);
+            *server.last_run.lock().unwrap() = shell_command(&task, &pending.env, &server.os_type);
+            if server.os_type.is_unix() {
+                task = task.with_sudo(self.sudo_passwords.contains_key(&server.login_key()));
+            }
+            server.clear_output();
+            server.show_line(&format!(">>> Dry run on {}@{} - nothing is executed", server.username, server.host));
+            for line in dry_run_lines(&task, &pending.env) {
+                server.show_line(&line);
+            }
+            server.set_status("Dry Run");
         }
     }
 
     fn submit_password(&mut self) {
-        if let Some(username) = self.password_needed_for.take() {
-            self.passwords.insert(username, self.password_input.clone());
-            self.password_input.clear();
+        // The dialog was asking for a sudo password, retry on that server
+        if let Some(index) = self.sudo_for.take() {
+            if let Some(login) = self.password_needed_for.take() {
+                self.sudo_passwords.insert(login, std::mem::take(&mut self.password_input));
+            }
+            self.password_error = None;
+            // The login password may have been forgotten since the run
+            if let Some(mut pending) = self.pending_command.take() {
+                pending.server = Some(index);
+                self.start_confirmed(pending);
+            }
+            return;
+        }
+
+        // The dialog was asking for a one-time code, not the password
+        if let Some((index, _)) = self.second_factor_for.take() {
+            self.password_needed_for = None;
+            let code = std::mem::take(&mut self.password_input);
+            if let Some(mut pending) = self.pending_command.take() {
+                pending.server = Some(index);
+                pending.second_factor = Some(code);
+                self.start_confirmed(pending);
+            }
+            return;
+        }
+
+        if let Some(login) = self.password_needed_for.take() {
+            // The same account name on other servers of this run, when asked to share it
+            let mut logins = vec![login.clone()];
+            if self.same_for_user
+                && let Some(ref pending) = self.pending_command
+            {
+                let user = login_user(&login);
+                logins.extend(self.get_missing_passwords(pending).into_iter().filter(|other| login_user(other) == user));
+            }
+            for login in logins {
+                if self.remember_password && !self.forget_after_use {
+                    self.remember.insert(login.clone());
+                }
+                if self.use_for_sudo {
+                    self.sudo_passwords.insert(login.clone(), self.password_input.clone());
+                }
+                self.passwords.insert(login, self.password_input.clone());
+            }
+            self.password_input.zeroize();
             self.password_error = None;
 
-            if let Some(pending) = self.pending_command.take() {
-                let missing = self.get_missing_passwords(pending.os_filter.as_ref());
-                if missing.is_empty() {
-                    self.run_command(&pending.command, pending.os_filter);
-                } else {
-                    self.pending_command = Some(pending);
-                    self.password_needed_for = Some(missing[0].clone());
-                }
-            }
-        }
+            if let Some(pending) = self.pending_command.take() {
+                let missing = self.load_remembered_passwords(&pending);
+                if missing.is_empty() {
+                    self.run_command(pending);
+                } else {
+                    self.pending_command = Some(pending);
+                    self.password_needed_for = Some(missing[0].clone());
+                }
+            }
+        }
+    }
+
+    fn submit_unlock(&mut self) {
+        let Some(ref mut dialog) = self.unlock else {
+            return;
+        };
+        if dialog.creating && *dialog.passphrase != *dialog.confirm {
+            dialog.error = Some("The passphrases don't match".to_string());
+            return;
+        }
+        if dialog.passphrase.is_empty() {
+            dialog.error = Some("Enter a passphrase".to_string());
+            return;
+        }
+        match credentials::unlock_file(&dialog.path, &dialog.passphrase) {
+            Ok(()) => {
+                let created = dialog.creating;
+                self.unlock = None;
+                self.notify(if created { "Password file created" } else { "Password file unlocked" }.to_string(), false);
+            }
+            Err(e) => {
+                dialog.error = Some(e.to_string());
+                dialog.passphrase.zeroize();
+                dialog.confirm.zeroize();
+            }
+        }
+    }
+
+    fn cancel_password_dialog(&mut self) {
+        self.password_needed_for = None;
+        self.second_factor_for = None;
+        self.sudo_for = None;
+        self.pending_command = None;
+        self.password_input.zeroize();
+        self.password_error = None;
+    }
+
+    // Emergency stop: queued servers are skipped before the running ones are
+    // cancelled, so a wave finishing meanwhile can't start the next one
+    fn cancel_all(&mut self) {
+        for queue in self.queues.drain(..) {
+            let skipped: Vec<QueuedJob> = queue.lock().unwrap().drain(..).collect();
+            for queued in skipped {
+                (queued.job)(Some("cancelled"));
+            }
+        }
+        for server in &self.servers {
+            if server.is_running() {
+                server.request_cancel();
+            }
+        }
+        // A run still collecting passwords or waiting to be confirmed never starts
+        self.cancel_password_dialog();
+        self.confirm_reboot = None;
+        self.confirm_mismatch = None;
+        self.confirm_run = None;
+        self.confirm_kill = None;
+    }
+
+    // Keys listed in SHORTCUTS. Nothing fires while typing in a field,
+    // except Escape, which the password field gives up focus to.
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        if self.password_needed_for.is_some() {
+            if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
+                self.cancel_password_dialog();
+            }
+            return;
+        }
+        if self.confirm_reboot.is_some() || self.confirm_mismatch.is_some() || self.confirm_run.is_some() {
+            return;
+        }
+        if ctx.wants_keyboard_input() && !self.output_focused {
+            return;
+        }
+
+        let command = |key| ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, key));
+        if command(egui::Key::T) {
+            self.start_command(commands::test_cmd(), None, false);
+        }
+        if command(egui::Key::L) {
+            self.start_command(commands::info_cmd_linux(), Some(OsType::Linux), false);
+        }
+        if command(egui::Key::W) {
+            self.start_command(commands::info_cmd_windows(), Some(OsType::Windows), false);
+        }
+
+        let plain = |key| ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, key));
+        if plain(egui::Key::Questionmark) || plain(egui::Key::F1) {
+            self.show_shortcuts = !self.show_shortcuts;
+        }
+        if self.show_shortcuts && plain(egui::Key::Escape) {
+            self.show_shortcuts = false;
+        }
+        let numbers = [
+            egui::Key::Num1, egui::Key::Num2, egui::Key::Num3,
+            egui::Key::Num4, egui::Key::Num5, egui::Key::Num6,
+            egui::Key::Num7, egui::Key::Num8, egui::Key::Num9,
+        ];
+        for (index, key) in numbers.into_iter().enumerate() {
+            if plain(key) && index < self.servers.len() {
+                self.selected_tab = index;
+                self.grid_view = false;
+            }
+        }
+    }
+
+    // Grid view: a row per server; clicking a name opens its output
+    fn show_grid(&mut self, ui: &mut egui::Ui) {
+        let mut open = None;
+        egui::ScrollArea::both().auto_shrink([false; 2]).show(ui, |ui| {
+            egui::Grid::new("server_grid")
+                .striped(true)
+                .spacing([16.0, 6.0])
+                .show(ui, |ui| {
+                    for heading in ["Server", "Status", "Summary"] {
+                        ui.label(egui::RichText::new(heading).strong());
+                    }
+                    ui.end_row();
+                    for (i, server) in self.servers.iter().enumerate() {
+                        let name = egui::RichText::new(&server.name);
+                        let name = if i == self.selected_tab { name.strong() } else { name };
+                        if ui.link(name).on_hover_text(format!("{}@{}", server.username, server.ip)).clicked() {
+                            open = Some(i);
+                        }
+                        ui.horizontal(|ui| {
+                            if server.is_running() {
+                                ui.add(egui::Spinner::new().size(12.0));
+                            }
+                            ui.colored_label(status_color(&server.get_status(), server.is_running()), server.status_label());
+                        });
+                        ui.label(egui::RichText::new(server.summary())
+                            .monospace()
+                            .color(egui::Color32::from_rgb(190, 190, 195)));
+                        ui.end_row();
+                    }
+                });
+        });
+        if let Some(i) = open {
+            self.selected_tab = i;
+            self.grid_view = false;
+        }
+    }
+}
+
+// =============================================================================
+// UI RENDERING
+// =============================================================================
+// A collapsible block in the sidebar: servers sharing a first tag,
+// or untagged servers grouped by OS
+struct ServerGroup {
+    label: String,
+    is_tag: bool,
+    members: Vec<usize>,
+}
+
+// Groups of the servers passing `visible`, empty groups left out
+fn server_groups(servers: &[ServerState], visible: impl Fn(&ServerState) -> bool) -> Vec<ServerGroup> {
+    let mut groups: Vec<ServerGroup> = Vec::new();
+    for (i, server) in servers.iter().enumerate() {
+        if !visible(server) {
+            continue;
+        }
+        let (label, is_tag) = match server.tags.first() {
+            Some(tag) => (tag.clone(), true),
+            None => (server.os_type.label().to_string(), false),
+        };
+        match groups.iter_mut().find(|g| g.label == label && g.is_tag == is_tag) {
+            Some(group) => group.members.push(i),
+            None => groups.push(ServerGroup { label, is_tag, members: vec![i] }),
+        }
+    }
+    groups
+}
+
+// Case-insensitive match on name, ip or username; `query` must be lowercase
+fn matches_search(server: &ServerState, query: &str) -> bool {
+    query.is_empty()
+        || server.name.to_lowercase().contains(query)
+        || server.ip.to_lowercase().contains(query)
+        || server.username.to_lowercase().contains(query)
+}
+
+// Rows from `from` to `to` as the sidebar showed them, so filtered-out and
+// collapsed servers are left out; just `to` when `from` wasn't shown
+fn shown_range(order: &[usize], from: usize, to: usize) -> Vec<usize> {
+    let position = |server| order.iter().position(|&shown| shown == server);
+    match (position(from), position(to)) {
+        (Some(a), Some(b)) => order[a.min(b)..=a.max(b)].to_vec(),
+        _ => vec![to],
+    }
+}
+
+// Presets offered for a tag group or a selection, with the OS each applies to
+fn scoped_presets() -> [(&'static str, &'static str, Option<OsType>); 13] {
+    [
+        ("Test", commands::test_cmd(), None),
+        ("Info Linux", commands::info_cmd_linux(), Some(OsType::Linux)),
+        ("Info Windows", commands::info_cmd_windows(), Some(OsType::Windows)),
+        ("Uptime Linux", commands::uptime_cmd_linux(), Some(OsType::Linux)),
+        ("Uptime Windows", commands::uptime_cmd_windows(), Some(OsType::Windows)),
+        ("Disk Linux", commands::disk_cmd_linux(), Some(OsType::Linux)),
+        ("Disk Windows", commands::disk_cmd_windows(), Some(OsType::Windows)),
+        ("Update Linux", commands::update_linux_cmd(), Some(OsType::Linux)),
+        ("Update Windows", commands::update_windows_cmd(), Some(OsType::Windows)),
+        ("Info macOS", commands::info_cmd_macos(), Some(OsType::Macos)),
+        ("Uptime macOS", commands::uptime_cmd_linux(), Some(OsType::Macos)),
+        ("Disk macOS", commands::disk_cmd_macos(), Some(OsType::Macos)),
+        ("Update macOS", commands::update_macos_cmd(), Some(OsType::Macos)),
+    ]
+}
+
+// One sidebar entry, returns true when clicked
+fn server_row(ui: &mut egui::Ui, server: &ServerState, is_selected: bool) -> bool {
+    let status = server.get_status();
+    let status_color = status_color(&status, server.is_running());
+
+    let bg = if is_selected {
+        egui::Color32::from_rgb(55, 60, 70)
+    } else {
+        egui::Color32::TRANSPARENT
+    };
+
+    let mut clicked = false;
+    egui::Frame::none()
+        .fill(bg)
+        .rounding(egui::Rounding::same(4.0))
+        .inner_margin(egui::Margin::symmetric(6.0, 4.0))
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                // The spinner keeps repainting while it's shown, and only then
+                if server.is_running() {
+                    ui.add(egui::Spinner::new().size(12.0).color(status_color));
+                } else {
+                    ui.colored_label(status_color, "●");
+                }
+
+                let os_color = match server.os_type {
+                    OsType::Linux => egui::Color32::from_rgb(200, 140, 60),
+                    OsType::Windows => egui::Color32::from_rgb(100, 140, 200),
+                    OsType::Macos => egui::Color32::from_rgb(170, 170, 170),
+                };
+                let os_char = match server.os_type {
+                    OsType::Linux => "L",
+                    OsType::Windows => "W",
+                    OsType::Macos => "M",
+                };
+                ui.colored_label(os_color, os_char);
+
+                if let Some(health) = server.health() {
+                    ui.colored_label(egui::Color32::from_rgb(220, 160, 60), "⚠").on_hover_text(health);
+                }
+
+                let name_color = if is_selected {
+                    egui::Color32::WHITE
+                } else {
+                    egui::Color32::from_rgb(230, 230, 235)
+                };
+                let mut name = ui.selectable_label(
+                    is_selected,
+                    egui::RichText::new(&server.name).color(name_color)
+                );
+                if let Some(error) = server.last_error() {
+                    name = name.on_hover_text(egui::RichText::new(error).color(egui::Color32::from_rgb(230, 110, 110)));
+                }
+                if name.clicked() {
+                    clicked = true;
+                }
+
+                if let Some((done, step)) = server.progress() {
+                    ui.add(egui::ProgressBar::new(done).desired_width(70.0).desired_height(12.0).show_percentage())
+                        .on_hover_text(step);
+                }
+
+                if server.reboot_pending() {
+                    ui.label(egui::RichText::new("Reboot pending")
+                        .small()
+                        .color(egui::Color32::from_rgb(210, 140, 70)))
+                        .on_hover_text("The last update asked for a reboot");
+                }
+
+                if let Some(duration) = server.last_duration()
+                    && !server.is_running()
+                {
+                    ui.label(egui::RichText::new(format_duration(duration))
+                        .small()
+                        .color(egui::Color32::from_rgb(130, 130, 140)));
+                }
+
+                // Right-aligned so a long-unreachable box stands out in the column
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let (seen, hover) = match server.last_success() {
+                        Some(at) => (
+                            format_ago(at.elapsed().unwrap_or_default()),
+                            chrono::DateTime::<chrono::Local>::from(at).format("Last login %Y-%m-%d %H:%M").to_string(),
+                        ),
+                        None => ("never".to_string(), "No successful login yet".to_string()),
+                    };
+                    ui.label(egui::RichText::new(seen)
+                        .small()
+                        .color(egui::Color32::from_rgb(110, 110, 120)))
+                        .on_hover_text(hover);
+                });
+            });
+        });
+    clicked
+}
+
+// Whether a command that looks written for `likely` runs on `os`; macOS takes the Linux ones
+fn same_shell(os: &OsType, likely: &OsType) -> bool {
+    os == likely || (os.is_unix() && likely.is_unix())
+}
+
+fn os_filter_combo(ui: &mut egui::Ui, id: &str, os_filter: &mut Option<OsType>) {
+    egui::ComboBox::from_id_salt(id)
+        .width(80.0)
+        .selected_text(match os_filter {
+            None => "All",
+            Some(os) => os.label(),
+        })
+        .show_ui(ui, |ui| {
+            ui.selectable_value(os_filter, None, "All");
+            ui.selectable_value(os_filter, Some(OsType::Linux), "Linux");
+            ui.selectable_value(os_filter, Some(OsType::Windows), "Windows");
+            ui.selectable_value(os_filter, Some(OsType::Macos), "macOS");
+        });
+}
+
+// One server's part of a run; given a reason it only reports being skipped, returns whether it succeeded
+type Job = Box<dyn FnOnce(Option<&str>) -> bool + Send>;
+
+// A server's part of a run that hasn't started yet
+struct QueuedJob {
+    server: String,
+    task: String,
+    job: Job,
+}
+
+// A run's waiting servers, front first; the queue panel reorders and drops them while earlier waves run
+type JobQueue = Arc<Mutex<VecDeque<QueuedJob>>>;
+
+// Start `wave_size` jobs at a time from the front of the queue, waiting for each wave to finish.
+// After a failed wave the rest are only told to skip when `stop_on_error` is set.
+// Returns how many failed or were skipped.
+fn run_in_waves(queue: &Mutex<VecDeque<QueuedJob>>, wave_size: usize, stop_on_error: bool) -> usize {
+    let mut failed = 0;
+    loop {
+        let skip = (stop_on_error && failed > 0).then_some("an earlier server failed");
+        let wave: Vec<QueuedJob> = {
+            let mut queue = queue.lock().unwrap();
+            let take = wave_size.max(1).min(queue.len());
+            queue.drain(..take).collect()
+        };
+        if wave.is_empty() {
+            return failed;
+        }
+        let wave: Vec<_> = wave.into_iter()
+            .map(|queued| thread::spawn(move || (queued.job)(skip)))
+            .collect();
+        failed += wave.into_iter().map(|worker| worker.join()).filter(|r| !matches!(r, Ok(true))).count();
+    }
+}
+
+// Move `server` into `onto`'s place, shifting the ones in between towards where it was
+fn reorder_queue(queue: &mut VecDeque<QueuedJob>, server: &str, onto: &str) {
+    let position = |name: &str| queue.iter().position(|q| q.server == name);
+    let (Some(from), Some(to)) = (position(server), position(onto)) else {
+        return;
+    };
+    if let Some(moved) = queue.remove(from) {
+        queue.insert(to, moved);
+    }
+}
+
+// At most this many probes at once, however many servers or clicks
+const PING_WORKERS: usize = 8;
+
+struct PingJob {
+    server: ServerState,
+    host: String,
+    port: u16,
+    timeout: Duration,
+    generation: u64,  // The server's ping_generation when this was queued
+    repaint: Option<egui::Context>,
+}
+
+// Queue served by PING_WORKERS threads started on first use
+fn ping_pool() -> &'static mpsc::Sender<PingJob> {
+    static POOL: OnceLock<mpsc::Sender<PingJob>> = OnceLock::new();
+    POOL.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<PingJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..PING_WORKERS {
+            let receiver = receiver.clone();
+            thread::spawn(move || loop {
+                let Ok(job) = receiver.lock().unwrap().recv() else {
+                    return;
+                };
+                run_ping(job);
+            });
+        }
+        sender
+    })
+}
+
+fn run_ping(job: PingJob) {
+    let server = &job.server;
+    // Superseded while waiting in the queue
+    if server.ping_generation.load(Ordering::Relaxed) != job.generation {
+        return;
+    }
+    match ssh::probe(&job.host, job.port, job.timeout) {
+        Ok(took) => {
+            server.set_ping_status(job.generation, &format!("Online ({} ms)", took.as_millis()));
+        }
+        Err(e) => {
+            if server.set_ping_status(job.generation, "Offline") {
+                server.append_output(&format!(">>> {} unreachable: {}", ssh::format_address(&job.host, job.port), e));
+            }
+        }
+    }
+    if let Some(ctx) = job.repaint {
+        ctx.request_repaint();
+    }
+}
+
+// Servers started by one run_command call, for the "finished" notification
+struct Batch {
+    task: String,
+    remaining: AtomicUsize,  // Starts at 1, held by the spawning loop
+    results: Mutex<Vec<(String, String)>>,  // Server name, final status
+}
+
+impl Batch {
+    fn new(task: String) -> Self {
+        Batch {
+            task,
+            remaining: AtomicUsize::new(1),
+            results: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Record a finished server (or the spawner letting go); the last one notifies
+    fn finish(&self, result: Option<(String, String)>) {
+        if let Some(result) = result {
+            self.results.lock().unwrap().push(result);
+        }
+        if self.remaining.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+
+        let results = self.results.lock().unwrap();
+        let (summary, body) = match results.as_slice() {
+            [] => return,
+            [(name, status)] => (format!("{}: {}", name, status), self.task.clone()),
+            _ => {
+                let failed: Vec<String> = results.iter()
+                    .filter(|(_, status)| status != "Done" && status != "Rebooting")
+                    .map(|(name, status)| format!("{}: {}", name, status))
+                    .collect();
+                let summary = format!(
+                    "{} finished on {} servers, {} failed",
+                    self.task, results.len(), failed.len()
+                );
+                (summary, failed.join("\n"))
+            }
+        };
+        // Best effort - there may be no notification daemon
+        let _ = notify_rust::Notification::new()
+            .appname("Server Manager")
+            .summary(&summary)
+            .body(&body)
+            .show();
+    }
+}
+
+// Counts for the summary strip; only runs that are going, done or failed count,
+// not servers that are Ready, pinged, cancelled or skipped
+struct RunSummary {
+    running: usize,
+    done: usize,
+    failed: usize,
+    queued: usize,  // Waiting on the password dialog
+}
+
+impl RunSummary {
+    fn of(servers: &[ServerState], queued: usize) -> Self {
+        let mut summary = RunSummary { running: 0, done: 0, failed: 0, queued };
+        for server in servers {
+            let status = server.get_status();
+            if server.is_running() {
+                summary.running += 1;
+            } else if status == "Done" || status == "Rebooting" {
+                summary.done += 1;
+            } else if is_failure(&status) {
+                summary.failed += 1;
+            }
+        }
+        summary
+    }
+
+    fn total(&self) -> usize {
+        self.running + self.done + self.failed + self.queued
+    }
+
+    fn progress(&self) -> f32 {
+        (self.done + self.failed) as f32 / self.total().max(1) as f32
+    }
+}
+
+// Statuses a run ends in when it went wrong
+fn is_failure(status: &str) -> bool {
+    status.starts_with("Failed")
+        || matches!(
+            status,
+            "Error" | "Unreachable" | "Timeout" | "Timed Out" | "Auth Failed" | "Host Key Mismatch"
+                | "Unknown Host Key" | "Needs Second Factor" | "Sudo Password Required" | "Update Interrupted"
+        )
+}
+
+// Sidebar status for a run that failed with `error`; every one of them reads as a failure
+fn ssh_error_status(error: &ssh::SshError) -> String {
+    match error {
+        ssh::SshError::Connect(_) => "Unreachable".to_string(),
+        ssh::SshError::Timeout(_) => "Timeout".to_string(),
+        ssh::SshError::CommandTimeout(_) => "Timed Out".to_string(),
+        ssh::SshError::Auth { .. } => "Auth Failed".to_string(),
+        ssh::SshError::CommandFailed { code } => format!("Failed (exit {})", code),
+    }
+}
+
+// Statuses worth a second attempt, as opposed to a command that ran and failed
+fn is_retryable(status: &str) -> bool {
+    matches!(status, "Error" | "Unreachable" | "Timeout" | "Timed Out" | "Auth Failed")
+}
+
+fn status_color(status: &str, is_running: bool) -> egui::Color32 {
+    if is_running || status == "Rebooting" {
+        egui::Color32::from_rgb(200, 170, 80)  // Soft yellow
+    } else if status == "Done" || status.starts_with("Online") {
+        egui::Color32::from_rgb(100, 180, 100)  // Soft green
+    } else if status == "Error" || status == "Unreachable" || status == "Timeout" || status == "Offline"
+        || status == "Timed Out"
+    {
+        egui::Color32::from_rgb(200, 100, 100)  // Soft red - down or broken
+    } else if status == "Auth Failed"
+        || status == "Host Key Mismatch"
+        || status == "Unknown Host Key"
+        || status == "Needs Second Factor"
+        || status == "Update Interrupted"
+    {
+        egui::Color32::from_rgb(210, 140, 70)  // Orange - misconfigured
+    } else if status.starts_with("Failed") {
+        egui::Color32::from_rgb(180, 120, 200)  // Soft purple - command failed
+    } else {
+        egui::Color32::from_rgb(120, 120, 130)  // Gray
     }
 }
 
-// =============================================================================
-// UI RENDERING
-// =============================================================================
 impl eframe::App for ServerManagerApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, SELECTED_TAB_KEY, &self.selected_tab);
+        eframe::set_value(storage, GRID_VIEW_KEY, &self.grid_view);
+        eframe::set_value(storage, WRAP_OUTPUT_KEY, &self.wrap_output);
+        let last_seen: HashMap<String, u64> = self.servers.iter()
+            .filter_map(|s| Some((s.name.clone(), s.last_success()?.duration_since(SystemTime::UNIX_EPOCH).ok()?.as_secs())))
+            .collect();
+        eframe::set_value(storage, LAST_SEEN_KEY, &last_seen);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        ctx.request_repaint();
+        // Workers wake us on new output; tick slowly for progress and so the notice can fade
+        if self.servers.iter().any(|s| s.is_running()) {
+            ctx.request_repaint_after(Duration::from_millis(250));
+        }
+        if let Some((_, _, shown)) = self.notice {
+            ctx.request_repaint_after(Duration::from_secs(4).saturating_sub(shown.elapsed()));
+        }
 
         self.check_auth_failures();
+        self.forget_finished_passwords();
+        self.handle_shortcuts(ctx);
+
+        // Master passphrase for the password file; skipping it means passwords are asked for as usual
+        if let Some(ref mut dialog) = self.unlock {
+            let mut submit = false;
+            let mut skip = false;
+            egui::Window::new(if dialog.creating { "Create Password File" } else { "Unlock Passwords" })
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(320.0)
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.add_space(12.0);
+                        if let Some(ref error) = dialog.error {
+                            ui.colored_label(egui::Color32::from_rgb(220, 90, 90), error);
+                            ui.add_space(8.0);
+                        }
+                        ui.label(egui::RichText::new(if dialog.creating {
+                            format!("Choose a master passphrase for {}", dialog.path.display())
+                        } else {
+                            format!("Master passphrase for {}", dialog.path.display())
+                        })
+                            .size(15.0)
+                            .color(egui::Color32::from_rgb(200, 200, 205)));
+                        ui.add_space(12.0);
+
+                        let response = ui.add_sized(
+                            [280.0, 28.0],
+                            egui::TextEdit::singleline(&mut *dialog.passphrase)
+                                .password(true)
+                                .hint_text("Passphrase...")
+                        );
+                        if dialog.passphrase.is_empty() && dialog.confirm.is_empty() {
+                            response.request_focus();
+                        }
+                        let mut enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if dialog.creating {
+                            ui.add_space(6.0);
+                            let response = ui.add_sized(
+                                [280.0, 28.0],
+                                egui::TextEdit::singleline(&mut *dialog.confirm)
+                                    .password(true)
+                                    .hint_text("Same again...")
+                            );
+                            enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        }
+                        submit |= enter;
+
+                        ui.add_space(12.0);
+                        ui.horizontal(|ui| {
+                            ui.add_space(70.0);
+                            let label = if dialog.creating { "Create" } else { "Unlock" };
+                            submit |= ui.add_sized([70.0, 26.0], egui::Button::new(label)).clicked();
+                            ui.add_space(8.0);
+                            skip = ui.add_sized([70.0, 26.0], egui::Button::new("Skip"))
+                                .on_hover_text("Nothing is remembered or filled in until the next launch")
+                                .clicked();
+                        });
+                        ui.add_space(8.0);
+                    });
+                });
+            if submit {
+                self.submit_unlock();
+            } else if skip {
+                self.unlock = None;
+            }
+        }
 
         // Password Dialog
-        if self.password_needed_for.is_some() {
+        if self.unlock.is_none() && self.password_needed_for.is_some() {
             egui::Window::new("Authentication")
                 .collapsible(false)
                 .resizable(false)
@@ -436,15 +3768,23 @@ impl eframe::App for ServerManagerApp {
                             ui.add_space(8.0);
                         }
 
-                        let username = self.password_needed_for.clone().unwrap();
-                        ui.label(egui::RichText::new(format!("Password for: {}", username))
+                        let login = self.password_needed_for.clone().unwrap();
+                        let prompt = match (self.second_factor_for.as_ref(), self.sudo_for) {
+                            (Some((i, prompt)), _) => format!("{}: {}", self.servers[*i].name, prompt.trim()),
+                            (None, Some(i)) => format!("sudo password for {} on {}", login_user(&login), self.servers[i].name),
+                            (None, None) => match self.servers.iter().find(|s| s.login_key() == login) {
+                                Some(server) => format!("Password for {} ({})", login, server.name),
+                                None => format!("Password for {} (jump host)", login),
+                            },
+                        };
+                        ui.label(egui::RichText::new(prompt)
                             .size(15.0)
                             .color(egui::Color32::from_rgb(200, 200, 205)));
                         ui.add_space(12.0);
 
                         let response = ui.add_sized(
                             [280.0, 28.0],
-                            egui::TextEdit::singleline(&mut self.password_input)
+                            egui::TextEdit::singleline(&mut *self.password_input)
                                 .password(true)
                                 .hint_text("Enter password...")
                         );
@@ -453,6 +3793,22 @@ impl eframe::App for ServerManagerApp {
                             response.request_focus();
                         }
 
+                        if self.second_factor_for.is_none() && self.sudo_for.is_none() {
+                            ui.add_space(6.0);
+                            ui.add_enabled(!self.forget_after_use, egui::Checkbox::new(&mut self.remember_password, "Remember password"))
+                                .on_hover_text(if self.password_file.is_some() {
+                                    "Saved in the encrypted password file after a successful login"
+                                } else {
+                                    "Saved in the system keyring after a successful login"
+                                });
+                            ui.checkbox(&mut self.forget_after_use, "Forget after use")
+                                .on_hover_text("Discard passwords once the servers using them finish, and ask again next time");
+                            ui.checkbox(&mut self.use_for_sudo, "Also use for sudo")
+                                .on_hover_text("Fed to sudo on Linux servers instead of waiting for a terminal");
+                            ui.checkbox(&mut self.same_for_user, format!("Use for every server with user '{}'", login_user(&login)))
+                                .on_hover_text("Otherwise each server is asked for separately");
+                        }
+
                         if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
                             self.submit_password();
                         }
@@ -465,10 +3821,55 @@ impl eframe::App for ServerManagerApp {
                             }
                             ui.add_space(8.0);
                             if ui.add_sized([70.0, 26.0], egui::Button::new("Cancel")).clicked() {
-                                self.password_needed_for = None;
-                                self.pending_command = None;
-                                self.password_input.clear();
-                                self.password_error = None;
+                                self.cancel_password_dialog();
+                            }
+                        });
+                        ui.add_space(8.0);
+                    });
+                });
+        }
+
+        // Host Key Dialog - trust on first use
+        if self.password_needed_for.is_none()
+            && let Some(server) = self.servers.iter().find(|s| s.host_key_prompt().is_some())
+            && let Some(prompt) = server.host_key_prompt()
+            && let ssh::HostKeyError::Unknown { host, port, key } = &prompt
+        {
+            let fingerprint = prompt.fingerprint();
+            egui::Window::new("Unknown Host Key")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(380.0)
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.add_space(12.0);
+                        ui.label(egui::RichText::new(format!("{} ({}:{})", server.name, host, port))
+                            .size(15.0)
+                            .color(egui::Color32::from_rgb(200, 200, 205)));
+                        ui.add_space(8.0);
+                        ui.label("The server's host key is not in known_hosts:");
+                        ui.label(egui::RichText::new(&fingerprint).monospace());
+                        ui.add_space(12.0);
+
+                        ui.horizontal(|ui| {
+                            ui.add_space(110.0);
+                            if ui.add_sized([70.0, 26.0], egui::Button::new("Trust")).clicked() {
+                                server.set_host_key_prompt(None);
+                                match ssh::trust_host_key(host, *port, key) {
+                                    Ok(_) => {
+                                        server.append_output(">>> Host key saved to known_hosts, run the command again");
+                                        server.set_status("Ready");
+                                    }
+                                    Err(e) => {
+                                        server.append_output(&format!(">>> ERROR: Could not save host key: {}", e));
+                                        server.set_status("Error");
+                                    }
+                                }
+                            }
+                            ui.add_space(8.0);
+                            if ui.add_sized([70.0, 26.0], egui::Button::new("Reject")).clicked() {
+                                server.set_host_key_prompt(None);
                             }
                         });
                         ui.add_space(8.0);
@@ -476,6 +3877,308 @@ impl eframe::App for ServerManagerApp {
                 });
         }
 
+        // Reboot Confirmation Dialog
+        if let Some(pending) = self.confirm_reboot.clone() {
+            let targets: Vec<&str> = self.servers.iter().enumerate()
+                .filter(|(i, s)| pending.targets(*i, s) && !s.is_running())
+                .map(|(_, s)| s.name.as_str())
+                .collect();
+            let mut close = false;
+            let mut confirmed = false;
+            egui::Window::new("Confirm Reboot")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(320.0)
+                .show(ctx, |ui| {
+                    ui.add_space(8.0);
+                    if targets.is_empty() {
+                        ui.label("No idle servers match.");
+                    } else {
+                        ui.label(format!("These {} server(s) will be rebooted:", targets.len()));
+                        ui.add_space(4.0);
+                        egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                            for name in &targets {
+                                ui.label(egui::RichText::new(*name).monospace());
+                            }
+                        });
+                    }
+                    ui.add_space(12.0);
+                    ui.horizontal(|ui| {
+                        let reboot = egui::Button::new(egui::RichText::new("Reboot")
+                            .color(egui::Color32::from_rgb(220, 90, 90)));
+                        if ui.add_enabled(!targets.is_empty(), reboot).clicked() {
+                            confirmed = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if confirmed {
+                self.confirm_reboot = None;
+                self.start_confirmed(pending);
+            } else if close {
+                self.confirm_reboot = None;
+            }
+        }
+
+        // Kill Confirmation Dialog, from the processes table
+        if let Some((index, process)) = self.confirm_kill.clone() {
+            let server = &self.servers[index];
+            let mut close = false;
+            let mut confirmed = false;
+            egui::Window::new("Confirm Kill")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(320.0)
+                .show(ctx, |ui| {
+                    ui.add_space(8.0);
+                    ui.label(format!("Kill this process on {}?", server.name));
+                    ui.add_space(4.0);
+                    ui.label(egui::RichText::new(format!("{}  {}  CPU {}", process.pid, process.command, process.cpu)).monospace());
+                    ui.add_space(12.0);
+                    ui.horizontal(|ui| {
+                        let kill = egui::Button::new(egui::RichText::new("Kill")
+                            .color(egui::Color32::from_rgb(220, 90, 90)));
+                        if ui.add_enabled(!server.is_running(), kill).clicked() {
+                            confirmed = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if confirmed {
+                let command = match server.os_type {
+                    OsType::Windows => commands::kill_process_windows_cmd(process.pid),
+                    OsType::Linux | OsType::Macos => commands::kill_process_linux_cmd(process.pid),
+                };
+                self.confirm_kill = None;
+                self.start_command_on(index, &command);
+            } else if close {
+                self.confirm_kill = None;
+            }
+        }
+
+        // OS Mismatch Confirmation Dialog
+        if let Some((pending, os)) = self.confirm_mismatch.clone() {
+            let mismatched: Vec<&str> = self.servers.iter().enumerate()
+                .filter(|(i, s)| pending.targets(*i, s) && !same_shell(&s.os_type, &os))
+                .map(|(_, s)| s.name.as_str())
+                .collect();
+            let os_name = os.label();
+            let mut close = false;
+            let mut run_on = None;
+            egui::Window::new("Confirm Command")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(320.0)
+                .show(ctx, |ui| {
+                    ui.add_space(8.0);
+                    ui.label(format!("This looks like a {} command, but it will also run on:", os_name));
+                    ui.add_space(4.0);
+                    egui::ScrollArea::vertical().max_height(240.0).show(ui, |ui| {
+                        for name in &mismatched {
+                            ui.label(egui::RichText::new(*name).monospace());
+                        }
+                    });
+                    ui.add_space(12.0);
+                    ui.horizontal(|ui| {
+                        if ui.button(format!("Only {} servers", os_name)).clicked() {
+                            run_on = Some(Some(os.clone()));
+                        }
+                        let anyway = egui::Button::new(egui::RichText::new("Run on all anyway")
+                            .color(egui::Color32::from_rgb(220, 90, 90)));
+                        if ui.add(anyway).clicked() {
+                            run_on = Some(None);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if let Some(os_filter) = run_on {
+                self.confirm_mismatch = None;
+                self.start_pending(PendingCommand { os_filter, ..pending });
+            } else if close {
+                self.confirm_mismatch = None;
+            }
+        }
+
+        // Run Confirmation Dialog
+        if let Some(pending) = self.confirm_run.clone() {
+            let Task::Exec(ref command) = pending.task else {
+                unreachable!("only shell commands need confirming");
+            };
+            let targets: Vec<String> = self.servers.iter().enumerate()
+                .filter(|(i, s)| pending.targets(*i, s) && !s.is_running())
+                .map(|(_, s)| match s.override_for(command) {
+                    Some(own) => format!("{} (its own: {})", s.name, own),
+                    None => s.name.clone(),
+                })
+                .collect();
+            let mut close = false;
+            let mut start = None;
+            egui::Window::new("Confirm Run")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .min_width(360.0)
+                .show(ctx, |ui| {
+                    ui.add_space(8.0);
+                    ui.label("This command:");
+                    ui.add_space(4.0);
+                    egui::ScrollArea::vertical().id_salt("confirm_command").max_height(160.0).show(ui, |ui| {
+                        ui.label(egui::RichText::new(command).monospace());
+                    });
+                    if let Some(script) = commands::decode_script(command)
+                        && ui.small_button("View script")
+                            .on_hover_text("The PowerShell encoded in this command")
+                            .clicked()
+                    {
+                        self.view_script = Some(script);
+                    }
+                    ui.add_space(8.0);
+                    if targets.is_empty() {
+                        ui.label("No idle servers match.");
+                    } else {
+                        ui.label(format!("will run on these {} server(s):", targets.len()));
+                        ui.add_space(4.0);
+                        egui::ScrollArea::vertical().id_salt("confirm_targets").max_height(200.0).show(ui, |ui| {
+                            for name in &targets {
+                                ui.label(egui::RichText::new(name).monospace());
+                            }
+                        });
+                    }
+                    ui.add_space(12.0);
+                    ui.horizontal(|ui| {
+                        let run = egui::Button::new(egui::RichText::new("Run")
+                            .color(egui::Color32::from_rgb(220, 90, 90)));
+                        if ui.add_enabled(!targets.is_empty(), run).clicked() {
+                            start = Some(false);
+                        }
+                        if ui.add_enabled(!targets.is_empty(), egui::Button::new("Dry Run"))
+                            .on_hover_text("Show what each server would run without running it")
+                            .clicked()
+                        {
+                            start = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            close = true;
+                        }
+                    });
+                });
+            if let Some(dry_run) = start {
+                self.confirm_run = None;
+                if dry_run {
+                    self.preview(&pending);
+                } else {
+                    self.start_confirmed(pending);
+                }
+            } else if close {
+                self.confirm_run = None;
+            }
+        }
+
+        // Output Diff Window
+        if let Some((left, right)) = self.compare {
+            let mut open = true;
+            match (self.servers.get(left), self.servers.get(right)) {
+                (Some(a), Some(b)) => {
+                    // Only diffed again when either output changed, not every frame
+                    let key = {
+                        use std::hash::{Hash, Hasher};
+                        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                        (left, right).hash(&mut hasher);
+                        a.raw_output.lock().unwrap().hash(&mut hasher);
+                        b.raw_output.lock().unwrap().hash(&mut hasher);
+                        hasher.finish()
+                    };
+                    if self.compare_diff.as_ref().is_none_or(|(cached, _)| *cached != key) {
+                        let lines = diff_lines(
+                            &comparable_output(&a.raw_output.lock().unwrap()),
+                            &comparable_output(&b.raw_output.lock().unwrap()),
+                        );
+                        self.compare_diff = Some((key, lines));
+                    }
+                    let lines = &self.compare_diff.as_ref().unwrap().1;
+                    egui::Window::new(format!("Compare {} / {}", a.name, b.name))
+                        .id(egui::Id::new("compare_output"))
+                        .open(&mut open)
+                        .default_size([720.0, 520.0])
+                        .show(ctx, |ui| {
+                            let changed = lines.iter().filter(|(tag, _)| *tag != similar::ChangeTag::Equal).count();
+                            ui.label(format!("- only on {}   + only on {}   ({} line(s) differ)", a.name, b.name, changed));
+                            ui.add_space(4.0);
+                            egui::ScrollArea::both().auto_shrink([false; 2]).show(ui, |ui| {
+                                for (tag, line) in lines {
+                                    let (sign, color, background) = match tag {
+                                        similar::ChangeTag::Delete => ("-", egui::Color32::from_rgb(230, 140, 140), egui::Color32::from_rgb(70, 35, 35)),
+                                        similar::ChangeTag::Insert => ("+", egui::Color32::from_rgb(140, 210, 140), egui::Color32::from_rgb(35, 65, 35)),
+                                        similar::ChangeTag::Equal => (" ", egui::Color32::from_rgb(160, 160, 165), egui::Color32::TRANSPARENT),
+                                    };
+                                    ui.label(egui::RichText::new(format!("{} {}", sign, line))
+                                        .monospace()
+                                        .color(color)
+                                        .background_color(background));
+                                }
+                            });
+                        });
+                }
+                _ => open = false,
+            }
+            if !open {
+                self.compare = None;
+                self.compare_diff = None;
+            }
+        }
+
+        // Decoded Script Window
+        if let Some(script) = self.view_script.clone() {
+            let mut open = true;
+            let mut copy = false;
+            egui::Window::new("View Script")
+                .open(&mut open)
+                .collapsible(false)
+                .default_size([640.0, 480.0])
+                .show(ctx, |ui| {
+                    if ui.small_button("Copy").clicked() {
+                        copy = true;
+                    }
+                    ui.add_space(4.0);
+                    egui::ScrollArea::both().auto_shrink([false; 2]).show(ui, |ui| {
+                        ui.label(egui::RichText::new(&script).monospace());
+                    });
+                });
+            if copy {
+                ctx.copy_text(script);
+            }
+            if !open {
+                self.view_script = None;
+            }
+        }
+
+        // Keyboard Shortcuts Overlay
+        if self.show_shortcuts {
+            egui::Window::new("Keyboard Shortcuts")
+                .collapsible(false)
+                .resizable(false)
+                .open(&mut self.show_shortcuts)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ctx, |ui| {
+                    egui::Grid::new("shortcuts").spacing([24.0, 6.0]).show(ui, |ui| {
+                        for (keys, action) in SHORTCUTS {
+                            ui.label(egui::RichText::new(keys).monospace().strong());
+                            ui.label(action);
+                            ui.end_row();
+                        }
+                    });
+                });
+        }
+
         // Top Panel
         egui::TopBottomPanel::top("top_panel")
             .frame(egui::Frame::none()
@@ -488,46 +4191,304 @@ impl eframe::App for ServerManagerApp {
                         .color(egui::Color32::from_rgb(210, 210, 215)));
                     ui.add_space(15.0);
 
-                    if let Some(ref err) = self.config_error {
-                        ui.colored_label(egui::Color32::from_rgb(220, 90, 90), err);
-                    } else {
-                        ui.label(egui::RichText::new(format!("{} servers", self.servers.len()))
-                            .color(egui::Color32::from_rgb(140, 140, 150)));
+                    if let Some(ref err) = self.config_error {
+                        ui.colored_label(egui::Color32::from_rgb(220, 90, 90), err);
+                    } else {
+                        ui.label(egui::RichText::new(format!("{} servers", self.servers.len()))
+                            .color(egui::Color32::from_rgb(140, 140, 150)));
+                    }
+
+                    if ui.small_button("Reload Config")
+                        .on_hover_text(format!("Re-read {}", self.config_path))
+                        .clicked()
+                    {
+                        self.reload_config();
+                        if self.config_error.is_none() {
+                            self.notify(format!("Reloaded {} servers", self.servers.len()), false);
+                        }
+                    }
+
+                    if let Some((ref message, is_error, shown)) = self.notice {
+                        if shown.elapsed() < Duration::from_secs(4) {
+                            ui.add_space(15.0);
+                            let color = if is_error {
+                                egui::Color32::from_rgb(220, 90, 90)
+                            } else {
+                                egui::Color32::from_rgb(100, 180, 100)
+                            };
+                            ui.colored_label(color, message);
+                        } else {
+                            self.notice = None;
+                        }
+                    }
+
+                    // Batch progress, right aligned
+                    let queued = match self.pending_command {
+                        Some(ref pending) => self.servers.iter().enumerate()
+                            .filter(|(i, server)| pending.targets(*i, server))
+                            .count(),
+                        None => 0,
+                    };
+                    let summary = RunSummary::of(&self.servers, queued);
+                    if summary.total() > 0 {
+                        ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                            ui.add(egui::ProgressBar::new(summary.progress()).desired_width(140.0));
+                            ui.label(egui::RichText::new(format!(
+                                "Running: {}, Done: {}, Failed: {}, Queued: {}",
+                                summary.running, summary.done, summary.failed, summary.queued
+                            )).color(egui::Color32::from_rgb(140, 140, 150)));
+                        });
+                    }
+                });
+
+                ui.add_space(8.0);
+
+                ui.horizontal(|ui| {
+                    if ui.button("Test All").clicked() {
+                        self.start_command(commands::test_cmd(), None, false);
+                    }
+
+                    if ui.button("Ping All").on_hover_text("Check the SSH port answers, without logging in").clicked() {
+                        self.ping_all();
+                    }
+
+                    if ui.button("Info Linux").clicked() {
+                        self.start_command(commands::info_cmd_linux(), Some(OsType::Linux), false);
+                    }
+
+                    if ui.button("Info Windows").clicked() {
+                        self.start_command(commands::info_cmd_windows(), Some(OsType::Windows), false);
+                    }
+
+                    if ui.button("Uptime Linux").clicked() {
+                        self.start_command(commands::uptime_cmd_linux(), Some(OsType::Linux), false);
+                    }
+
+                    if ui.button("Uptime Windows").clicked() {
+                        self.start_command(commands::uptime_cmd_windows(), Some(OsType::Windows), false);
+                    }
+
+                    if ui.button("Disk Linux").clicked() {
+                        self.start_command(commands::disk_cmd_linux(), Some(OsType::Linux), false);
+                    }
+
+                    if ui.button("Disk Windows").clicked() {
+                        self.start_command(commands::disk_cmd_windows(), Some(OsType::Windows), false);
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Update Linux").clicked() {
+                        self.start_command(commands::update_linux_cmd(), Some(OsType::Linux), false);
+                    }
+
+                    if ui.button("Update Windows").clicked() {
+                        self.start_command(commands::update_windows_cmd(), Some(OsType::Windows), false);
+                    }
+
+                    if ui.button("Reboot Linux").clicked() {
+                        self.request_reboot(OsType::Linux, None);
+                    }
+
+                    if ui.button("Reboot Windows").clicked() {
+                        self.request_reboot(OsType::Windows, None);
+                    }
+
+                    // Only worth the space when the config has Macs in it
+                    if self.servers.iter().any(|s| s.os_type == OsType::Macos) {
+                        ui.menu_button("macOS", |ui| {
+                            let presets = [
+                                ("Info", commands::info_cmd_macos()),
+                                ("Uptime", commands::uptime_cmd_linux()),
+                                ("Disk", commands::disk_cmd_macos()),
+                                ("Update", commands::update_macos_cmd()),
+                            ];
+                            for (label, command) in presets {
+                                if ui.button(label).clicked() {
+                                    self.start_command(command, Some(OsType::Macos), false);
+                                    ui.close_menu();
+                                }
+                            }
+                            if ui.button("Reboot").clicked() {
+                                self.request_reboot(OsType::Macos, None);
+                                ui.close_menu();
+                            }
+                        });
+                    }
+
+                    // User-defined [[commands]] from servers.toml
+                    if !self.custom_commands.is_empty() {
+                        ui.separator();
+                        for custom in self.custom_commands.clone() {
+                            if ui.button(&custom.name).on_hover_text(&custom.script).clicked() {
+                                self.start_custom(&custom);
+                            }
+                        }
+                    }
+
+                    ui.separator();
+
+                    if ui.button("Upload...").clicked() {
+                        self.start_upload();
+                    }
+                    ui.add(egui::TextEdit::singleline(&mut self.upload_remote_path)
+                        .desired_width(160.0)
+                        .hint_text("Remote path"));
+                    os_filter_combo(ui, "upload_os", &mut self.upload_os_filter);
+
+                    if ui.button("Run script...")
+                        .on_hover_text("Runs a local file with bash on Linux and macOS, or a .ps1 with PowerShell on Windows")
+                        .clicked()
+                    {
+                        self.start_script();
+                    }
+
+                    ui.separator();
+
+                    let cancel_all = egui::Button::new(egui::RichText::new("Cancel All")
+                        .color(egui::Color32::from_rgb(220, 90, 90)));
+                    if (self.servers.iter().any(|s| s.is_running()) || self.pending_command.is_some())
+                        && ui.add(cancel_all)
+                            .on_hover_text("Stop every running server and drop the queued ones, so nothing else starts")
+                            .clicked()
+                    {
+                        self.cancel_all();
+                    }
+
+                    if ui.button("Clear").clicked() {
+                        for server in &self.servers {
+                            server.clear_output();
+                            server.set_status("Ready");
+                            server.set_last_duration(None);
+                        }
+                    }
+
+                    if ui.small_button("?").on_hover_text("Keyboard shortcuts").clicked() {
+                        self.show_shortcuts = !self.show_shortcuts;
+                    }
+
+                    ui.checkbox(&mut self.grid_view, "Grid view")
+                        .on_hover_text("Every server's status and latest result side by side");
+
+                    if ui.checkbox(&mut self.show_timestamps, "Show timestamps").changed() {
+                        for server in &self.servers {
+                            server.set_timestamps(self.show_timestamps);
+                        }
+                    }
+
+                    if ui.checkbox(&mut self.collapse_repeats, "Collapse repeats")
+                        .on_hover_text("Show identical lines in a row once, with how many times they came")
+                        .changed()
+                    {
+                        for server in &self.servers {
+                            server.set_collapse_repeats(self.collapse_repeats);
+                        }
                     }
-                });
 
-                ui.add_space(8.0);
+                    ui.checkbox(&mut self.dry_run, "Dry run")
+                        .on_hover_text("Show what each server would run instead of running it");
 
-                ui.horizontal(|ui| {
-                    if ui.button("Test All").clicked() {
-                        self.start_command(commands::test_cmd(), None);
+                    ui.checkbox(&mut self.sequential, "One at a time")
+                        .on_hover_text(if self.stop_on_error {
+                            format!("Run on {} server(s) at a time in list order, skipping the rest after a failure", self.wave_size)
+                        } else {
+                            format!("Run on {} server(s) at a time in list order", self.wave_size)
+                        });
+
+                    ui.checkbox(&mut self.notify_when_done, "Notify when done")
+                        .on_hover_text("Desktop notification once every server in a run has finished");
+
+                    if ui.button("Save All").clicked() {
+                        self.save_all_outputs();
                     }
 
-                    if ui.button("Info Linux").clicked() {
-                        self.start_command(commands::info_cmd_linux(), Some(OsType::Linux));
+                    let failed = self.failed_servers().len();
+                    if ui.add_enabled(failed > 0, egui::Button::new(format!("Retry failed ({})", failed)))
+                        .on_hover_text("Run the last command again on servers that were unreachable, timed out or failed to log in")
+                        .clicked()
+                    {
+                        self.retry_failed();
                     }
 
-                    if ui.button("Info Windows").clicked() {
-                        self.start_command(commands::info_cmd_windows(), Some(OsType::Windows));
+                    if ui.add_enabled(self.last_command.is_some(), egui::Button::new("Export JSON"))
+                        .on_hover_text("Status, exit code, duration and output of the last run")
+                        .clicked()
+                    {
+                        self.export_json();
+                    }
+
+                    if ui.button("Copy All").clicked() {
+                        let all: String = self.servers.iter()
+                            .map(|s| format!("=== {} ===\n{}\n", s.name, s.get_output()))
+                            .collect();
+                        ctx.copy_text(all);
+                        self.notify(format!("Copied output of {} servers", self.servers.len()), false);
                     }
 
                     ui.separator();
 
-                    if ui.button("Update Linux").clicked() {
-                        self.start_command(commands::update_linux_cmd(), Some(OsType::Linux));
+                    let mut rerun = None;
+                    ui.add_enabled_ui(!self.history.is_empty(), |ui| {
+                        ui.menu_button("History", |ui| {
+                            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                                for entry in self.history.iter().rev() {
+                                    let label = format!(
+                                        "{}  [{}]  {}",
+                                        entry.at.format("%H:%M:%S"),
+                                        self.describe_scope(&entry.command),
+                                        entry.command.task.describe()
+                                    );
+                                    if ui.button(label).clicked() {
+                                        rerun = Some(entry.command.clone());
+                                        ui.close_menu();
+                                    }
+                                }
+                            });
+                        });
+                    });
+                    if let Some(command) = rerun {
+                        self.start_pending(command);
                     }
+                });
 
-                    if ui.button("Update Windows").clicked() {
-                        self.start_command(commands::update_windows_cmd(), Some(OsType::Windows));
+                ui.add_space(4.0);
+
+                // Free-form command
+                ui.horizontal(|ui| {
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.command_input)
+                        .desired_width(420.0)
+                        .font(egui::TextStyle::Monospace)
+                        .hint_text("Command, e.g. systemctl status docker"));
+                    os_filter_combo(ui, "command_os", &mut self.command_os_filter);
+                    ui.checkbox(&mut self.command_pty, "PTY")
+                        .on_hover_text("Run in a pseudo-terminal, for tools that need a TTY");
+
+                    let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if (ui.button("Run").clicked() || enter) && !self.command_input.trim().is_empty() {
+                        let command = self.command_input.trim().to_string();
+                        self.start_command(&command, self.command_os_filter.clone(), self.command_pty);
+                    }
+
+                    // Flag servers a one-OS command would hit when "All" is picked
+                    if self.command_os_filter.is_none()
+                        && let Some(os) = commands::likely_os(&self.command_input)
+                    {
+                        let others = self.servers.iter().filter(|s| !same_shell(&s.os_type, &os)).count();
+                        if others > 0 {
+                            ui.label(egui::RichText::new(format!("⚠ {} server(s) of another OS", others))
+                                .color(egui::Color32::from_rgb(220, 160, 60)))
+                                .on_hover_text(format!("Looks like a {:?} command", os));
+                        }
                     }
 
                     ui.separator();
 
-                    if ui.button("Clear").clicked() {
-                        for server in &self.servers {
-                            server.clear_output();
-                            server.set_status("Ready");
-                        }
+                    let response = ui.add(egui::TextEdit::singleline(&mut self.service_name)
+                        .desired_width(140.0)
+                        .hint_text("Service, e.g. nginx"));
+                    let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                    if ui.button("Check Service").clicked() || enter {
+                        self.check_service();
                     }
                 });
             });
@@ -543,66 +4504,173 @@ impl eframe::App for ServerManagerApp {
                     .size(14.0)
                     .color(egui::Color32::from_rgb(170, 170, 180)));
                 ui.add_space(8.0);
+                ui.add(egui::TextEdit::singleline(&mut self.server_search)
+                    .desired_width(f32::INFINITY)
+                    .hint_text("Search servers..."));
+                ui.add_space(4.0);
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.sidebar_os, None, "All");
+                    ui.selectable_value(&mut self.sidebar_os, Some(OsType::Linux), "Linux");
+                    ui.selectable_value(&mut self.sidebar_os, Some(OsType::Windows), "Windows");
+                    if self.servers.iter().any(|s| s.os_type == OsType::Macos) {
+                        ui.selectable_value(&mut self.sidebar_os, Some(OsType::Macos), "macOS");
+                    }
+                });
+                ui.add_space(4.0);
                 ui.separator();
                 ui.add_space(8.0);
 
+                // View filter only - commands still target every server
+                let query = self.server_search.trim().to_lowercase();
+                let sidebar_os = self.sidebar_os.clone();
+                let visible = |s: &ServerState| {
+                    matches_search(s, &query) && sidebar_os.as_ref().is_none_or(|os| &s.os_type == os)
+                };
+                let mut group_command = None;
+                let mut shown = Vec::new();
                 egui::ScrollArea::vertical().show(ui, |ui| {
-                    for (i, server) in self.servers.iter().enumerate() {
-                        let status = server.get_status();
-                        let is_running = server.is_running();
-                        let is_selected = self.selected_tab == i;
-
-                        // Softer status colors
-                        let status_color = if is_running {
-                            egui::Color32::from_rgb(200, 170, 80)  // Soft yellow
-                        } else if status == "Done" {
-                            egui::Color32::from_rgb(100, 180, 100)  // Soft green
-                        } else if status == "Error" || status == "Auth Failed" {
-                            egui::Color32::from_rgb(200, 100, 100)  // Soft red
-                        } else {
-                            egui::Color32::from_rgb(120, 120, 130)  // Gray
-                        };
-
-                        let bg = if is_selected {
-                            egui::Color32::from_rgb(55, 60, 70)
-                        } else {
-                            egui::Color32::TRANSPARENT
-                        };
-
-                        egui::Frame::none()
-                            .fill(bg)
-                            .rounding(egui::Rounding::same(4.0))
-                            .inner_margin(egui::Margin::symmetric(6.0, 4.0))
+                    for group in server_groups(&self.servers, visible) {
+                        let header = egui::CollapsingHeader::new(format!("{} ({})", group.label, group.members.len()))
+                            .id_salt(("group", &group.label, group.is_tag))
+                            .default_open(true)
                             .show(ui, |ui| {
-                                ui.horizontal(|ui| {
-                                    ui.colored_label(status_color, "●");
-
-                                    let os_color = match server.os_type {
-                                        OsType::Linux => egui::Color32::from_rgb(200, 140, 60),
-                                        OsType::Windows => egui::Color32::from_rgb(100, 140, 200),
-                                    };
-                                    let os_char = match server.os_type {
-                                        OsType::Linux => "L",
-                                        OsType::Windows => "W",
-                                    };
-                                    ui.colored_label(os_color, os_char);
-
-                                    let name_color = if is_selected {
-                                        egui::Color32::WHITE
-                                    } else {
-                                        egui::Color32::from_rgb(230, 230, 235)
-                                    };
-                                    if ui.selectable_label(
-                                        is_selected,
-                                        egui::RichText::new(&server.name).color(name_color)
-                                    ).clicked() {
+                                for &i in &group.members {
+                                    shown.push(i);
+                                    let highlighted = self.selected_tab == i || self.selection.contains(&i);
+                                    if server_row(ui, &self.servers[i], highlighted) {
+                                        let modifiers = ui.input(|input| input.modifiers);
+                                        if modifiers.shift {
+                                            self.selection.extend(shown_range(&self.sidebar_order, self.selected_tab, i));
+                                        } else if modifiers.command {
+                                            if self.selection.is_empty() {
+                                                self.selection.insert(self.selected_tab);
+                                            }
+                                            if !self.selection.remove(&i) {
+                                                self.selection.insert(i);
+                                            }
+                                        } else {
+                                            self.selection.clear();
+                                        }
                                         self.selected_tab = i;
                                     }
-                                });
+                                    ui.add_space(2.0);
+                                }
+                            });
+
+                        // Right-click a tag group to run a command on just that group
+                        if group.is_tag {
+                            header.header_response.context_menu(|ui| {
+                                for (label, command, os_filter) in scoped_presets() {
+                                    if ui.button(label).clicked() {
+                                        group_command = Some(PendingCommand {
+                                            task: Task::Exec(command.to_string()),
+                                            os_filter,
+                                            server: None,
+                                            tag: Some(group.label.clone()),
+                                            second_factor: None,
+                                            pty: false,
+                                            selection: None,
+                                            filter: None,
+                                            timeout: None,
+                                            env: Vec::new(),
+                                        });
+                                        ui.close_menu();
+                                    }
+                                }
                             });
-                        ui.add_space(2.0);
+                        }
                     }
                 });
+                self.sidebar_order = shown;
+
+                // Ctrl/Shift-click builds a selection that commands can target directly
+                if !self.selection.is_empty() {
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{} selected", self.selection.len()));
+                        ui.menu_button("Run on selected", |ui| {
+                            let mut chosen = scoped_presets().to_vec();
+                            let typed = self.command_input.trim().to_string();
+                            if !typed.is_empty() {
+                                chosen.push(("Typed command", typed.as_str(), self.command_os_filter.clone()));
+                            }
+                            for (label, command, os_filter) in chosen {
+                                if ui.button(label).clicked() {
+                                    group_command = Some(PendingCommand {
+                                        task: Task::Exec(command.to_string()),
+                                        os_filter,
+                                        server: None,
+                                        tag: None,
+                                        second_factor: None,
+                                        pty: false,
+                                        selection: Some(self.selection.clone()),
+                                        filter: None,
+                                        timeout: None,
+                                        env: Vec::new(),
+                                    });
+                                    ui.close_menu();
+                                }
+                            }
+                        });
+                        if self.selection.len() == 2
+                            && ui.small_button("Compare").on_hover_text("Diff the two servers' output").clicked()
+                        {
+                            let mut pair: Vec<usize> = self.selection.iter().copied().collect();
+                            pair.sort();
+                            self.compare = Some((pair[0], pair[1]));
+                        }
+                        if ui.small_button("Clear").clicked() {
+                            self.selection.clear();
+                        }
+                    });
+                }
+                if let Some(command) = group_command {
+                    self.start_pending(command);
+                }
+
+                // Servers of sequential runs waiting for a slot: drag to reorder, ✖ to drop
+                self.queues.retain(|queue| !queue.lock().unwrap().is_empty());
+                if !self.queues.is_empty() {
+                    ui.separator();
+                    let waiting: usize = self.queues.iter().map(|queue| queue.lock().unwrap().len()).sum();
+                    egui::CollapsingHeader::new(format!("Queue ({})", waiting))
+                        .id_salt("queue")
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            for (q, queue) in self.queues.iter().enumerate() {
+                                let mut moved = None;
+                                let mut removed = None;
+                                for queued in queue.lock().unwrap().iter() {
+                                    let row = ui.horizontal(|ui| {
+                                        let id = egui::Id::new(("queued", q, &queued.server));
+                                        ui.dnd_drag_source(id, (q, queued.server.clone()), |ui| {
+                                            ui.label(format!("☰ {}", queued.server));
+                                        }).response.on_hover_text(&queued.task);
+                                        if ui.small_button("✖").on_hover_text("Remove from the queue").clicked() {
+                                            removed = Some(queued.server.clone());
+                                        }
+                                    }).response;
+                                    // Dropped on a row of the same run: takes its place
+                                    if let Some(dragged) = row.dnd_release_payload::<(usize, String)>()
+                                        && dragged.0 == q
+                                    {
+                                        moved = Some((dragged.1.clone(), queued.server.clone()));
+                                    }
+                                }
+                                let mut queue = queue.lock().unwrap();
+                                if let Some((server, onto)) = moved {
+                                    reorder_queue(&mut queue, &server, &onto);
+                                }
+                                if let Some(server) = removed
+                                    && let Some(at) = queue.iter().position(|queued| queued.server == server)
+                                    && let Some(queued) = queue.remove(at)
+                                {
+                                    drop(queue);
+                                    (queued.job)(Some("removed from the queue"));
+                                }
+                            }
+                        });
+                }
             });
 
         // Main Panel - Output
@@ -624,7 +4692,21 @@ impl eframe::App for ServerManagerApp {
                     self.selected_tab = 0;
                 }
 
-                let server = &self.servers[self.selected_tab];
+                if self.grid_view {
+                    self.show_grid(ui);
+                    return;
+                }
+
+                // Cloned so commands can be started from the header
+                let server = self.servers[self.selected_tab].clone();
+                let mut run_here = None;
+                let mut reboot_here = false;
+                let mut diagnose_here = false;
+                let mut save_here = false;
+                let mut copied = false;
+                let mut copied_command = false;
+                let mut terminal_here = false;
+                let mut compare_with = None;
 
                 // Header
                 ui.horizontal(|ui| {
@@ -638,43 +4720,798 @@ impl eframe::App for ServerManagerApp {
                     ui.add_space(10.0);
 
                     let status = server.get_status();
-                    let status_color = if server.is_running() {
-                        egui::Color32::from_rgb(200, 170, 80)
-                    } else if status == "Done" {
-                        egui::Color32::from_rgb(100, 180, 100)
-                    } else if status == "Error" || status == "Auth Failed" {
-                        egui::Color32::from_rgb(200, 100, 100)
-                    } else {
-                        egui::Color32::from_rgb(120, 120, 130)
-                    };
+                    let status_color = status_color(&status, server.is_running());
 
-                    ui.colored_label(status_color, &status);
+                    ui.colored_label(status_color, server.status_label());
+
+                    let stderr_lines = server.output.lock().unwrap().stderr_lines;
+                    if stderr_lines > 0 {
+                        ui.add_space(6.0);
+                        ui.colored_label(egui::Color32::from_rgb(205, 95, 95), format!("{} stderr", stderr_lines))
+                            .on_hover_text("Lines written to stderr in the last run");
+                    }
+
+                    ui.add_space(10.0);
+                    if ui.small_button("Copy").clicked() {
+                        let text = if self.show_raw {
+                            server.raw_output.lock().unwrap().clone()
+                        } else {
+                            server.output.lock().unwrap().filtered(server.stream_filter).text
+                        };
+                        ui.ctx().copy_text(text);
+                        copied = true;
+                    }
+                    let last_run = server.last_run.lock().unwrap().clone();
+                    if let Some(command) = last_run
+                        && ui.small_button("Copy command")
+                            .on_hover_text("The command of the last run, to paste into your own SSH session")
+                            .clicked()
+                    {
+                        // Encoded scripts are copied as the PowerShell they contain
+                        ui.ctx().copy_text(commands::decode_script(&command).unwrap_or(command));
+                        copied_command = true;
+                    }
+                    if ui.small_button("Save Output").clicked() {
+                        save_here = true;
+                    }
+                    if ui.small_button("Open terminal")
+                        .on_hover_text("Log in with the system ssh client in a new terminal window")
+                        .clicked()
+                    {
+                        terminal_here = true;
+                    }
+                    ui.checkbox(&mut self.show_raw, "Raw")
+                        .on_hover_text("Show output exactly as received, before CLIXML and color parsing");
+                    ui.checkbox(&mut self.wrap_output, "Wrap")
+                        .on_hover_text("Wrap long lines at the panel width instead of scrolling sideways");
+                    ui.add_enabled_ui(!self.show_raw, |ui| {
+                        let filter = &mut self.servers[self.selected_tab].stream_filter;
+                        ui.selectable_value(filter, StreamFilter::All, "All");
+                        ui.selectable_value(filter, StreamFilter::Stdout, "stdout");
+                        ui.selectable_value(filter, StreamFilter::Stderr, "stderr");
+                    });
+                    ui.menu_button("Compare", |ui| {
+                        for (i, other) in self.servers.iter().enumerate() {
+                            if i != self.selected_tab && ui.button(&other.name).clicked() {
+                                compare_with = Some(i);
+                                ui.close_menu();
+                            }
+                        }
+                    })
+                    .response
+                    .on_hover_text("Diff this server's output against another's");
+                    if server.is_running() {
+                        if ui.small_button("Stop").clicked() {
+                            server.request_cancel();
+                        }
+                    } else {
+                        if server.update_interrupted()
+                            && ui.small_button(egui::RichText::new("Resume Update")
+                                .color(egui::Color32::from_rgb(210, 140, 70)))
+                                .clicked()
+                        {
+                            run_here = Some(commands::resume_update_windows_cmd());
+                        }
+                        ui.menu_button("Run on this server", |ui| {
+                            let (info, uptime, disk, top, update) = match server.os_type {
+                                OsType::Linux => (
+                                    commands::info_cmd_linux(),
+                                    commands::uptime_cmd_linux(),
+                                    commands::disk_cmd_linux(),
+                                    commands::top_processes_cmd_linux(),
+                                    commands::update_linux_cmd(),
+                                ),
+                                OsType::Windows => (
+                                    commands::info_cmd_windows(),
+                                    commands::uptime_cmd_windows(),
+                                    commands::disk_cmd_windows(),
+                                    commands::top_processes_cmd_windows(),
+                                    commands::update_windows_cmd(),
+                                ),
+                                OsType::Macos => (
+                                    commands::info_cmd_macos(),
+                                    commands::uptime_cmd_linux(),
+                                    commands::disk_cmd_macos(),
+                                    commands::top_processes_cmd_macos(),
+                                    commands::update_macos_cmd(),
+                                ),
+                            };
+                            if ui.button("Test").clicked() {
+                                run_here = Some(commands::test_cmd());
+                                ui.close_menu();
+                            }
+                            if ui.button("Info").clicked() {
+                                run_here = Some(info);
+                                ui.close_menu();
+                            }
+                            if ui.button("Uptime").clicked() {
+                                run_here = Some(uptime);
+                                ui.close_menu();
+                            }
+                            if ui.button("Disk").clicked() {
+                                run_here = Some(disk);
+                                ui.close_menu();
+                            }
+                            if ui.button("Top processes").on_hover_text("The ten using the most CPU, with a Kill button each").clicked() {
+                                run_here = Some(top);
+                                ui.close_menu();
+                            }
+                            if ui.button("Update").clicked() {
+                                run_here = Some(update);
+                                ui.close_menu();
+                            }
+                            if server.os_type == OsType::Windows
+                                && ui.button("Resume Update")
+                                    .on_hover_text("Reattach to an update whose connection dropped")
+                                    .clicked()
+                            {
+                                run_here = Some(commands::resume_update_windows_cmd());
+                                ui.close_menu();
+                            }
+                            if ui.button("Diagnose connection")
+                                .on_hover_text("Server version and the auth methods it allows, without logging in")
+                                .clicked()
+                            {
+                                diagnose_here = true;
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            if ui.button("Reboot...").clicked() {
+                                reboot_here = true;
+                                ui.close_menu();
+                            }
+                        });
+                    }
                 });
 
+                if let Some(command) = run_here {
+                    self.start_command_on(self.selected_tab, command);
+                }
+                if save_here {
+                    self.save_output(self.selected_tab);
+                }
+                if terminal_here {
+                    self.open_terminal(self.selected_tab);
+                }
+                if let Some(other) = compare_with {
+                    self.compare = Some((self.selected_tab, other));
+                }
+                if copied {
+                    self.notify(format!("Copied output of {}", server.name), false);
+                }
+                if copied_command {
+                    self.notify(format!("Copied the command run on {}", server.name), false);
+                }
+                if diagnose_here {
+                    self.start_pending(PendingCommand {
+                        task: Task::Diagnose,
+                        os_filter: None,
+                        server: Some(self.selected_tab),
+                        tag: None,
+                        second_factor: None,
+                        pty: false,
+                        selection: None,
+                        filter: None,
+                        timeout: None,
+                        env: Vec::new(),
+                    });
+                }
+                if reboot_here {
+                    self.request_reboot(server.os_type.clone(), Some(self.selected_tab));
+                }
+
+                // Download
+                if !server.is_running() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut self.download_remote_path)
+                            .desired_width(280.0)
+                            .hint_text(default_download_path(&server.os_type)));
+                        if ui.small_button("Download...").clicked() {
+                            self.start_download(self.selected_tab);
+                        }
+                    });
+                }
+
+                // The command went quiet, offer to answer its prompt
+                if server.is_running() && server.get_status() == "Waiting for Input?" {
+                    ui.horizontal(|ui| {
+                        ui.colored_label(egui::Color32::from_rgb(220, 150, 80), "No output - it may be waiting for input:");
+                        let response = ui.add(egui::TextEdit::singleline(&mut self.stdin_input)
+                            .desired_width(220.0)
+                            .hint_text("e.g. y"));
+                        let enter = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                        if ui.small_button("Send").clicked() || enter {
+                            server.send_input(&self.stdin_input);
+                            // Not the text itself, which is often a password
+                            server.append_output(&format!(">>> Sent input ({} chars)", self.stdin_input.chars().count()));
+                            self.stdin_input.clear();
+                            server.set_status("Running...");
+                        }
+                    });
+                }
+
                 ui.add_space(8.0);
                 ui.separator();
                 ui.add_space(8.0);
 
+                // Info results as a table, one row per server that has them
+                if !self.show_raw && server.info().is_some() {
+                    let rows: Vec<(usize, commands::ServerInfo)> = self.servers.iter()
+                        .enumerate()
+                        .filter_map(|(i, s)| Some((i, s.info()?)))
+                        .collect();
+                    egui::ScrollArea::both().auto_shrink([false; 2]).show(ui, |ui| {
+                        egui::Grid::new("info_table")
+                            .striped(true)
+                            .spacing([16.0, 6.0])
+                            .show(ui, |ui| {
+                                for heading in ["Server", "OS", "Kernel", "Uptime", "CPU", "Memory", "Disk"] {
+                                    ui.label(egui::RichText::new(heading).strong());
+                                }
+                                ui.end_row();
+                                for (i, info) in rows {
+                                    let name = egui::RichText::new(&self.servers[i].name);
+                                    if i == self.selected_tab {
+                                        ui.label(name.strong().color(egui::Color32::from_rgb(120, 170, 230)));
+                                    } else if ui.link(name).clicked() {
+                                        self.selected_tab = i;
+                                    }
+                                    for value in [&info.os, &info.kernel, &info.uptime, &info.cpu, &info.memory, &info.disk] {
+                                        ui.label(egui::RichText::new(value).monospace());
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        ui.add_space(8.0);
+                        ui.label(egui::RichText::new("Tick Raw to see the full output")
+                            .color(egui::Color32::from_rgb(130, 130, 140)));
+                    });
+                    return;
+                }
+
+                // Top processes as a table with a Kill button per row
+                if !self.show_raw && let Some(processes) = server.processes() {
+                    let running = server.is_running();
+                    let mut refresh = false;
+                    egui::ScrollArea::both().auto_shrink([false; 2]).show(ui, |ui| {
+                        egui::Grid::new("process_table")
+                            .striped(true)
+                            .spacing([16.0, 6.0])
+                            .show(ui, |ui| {
+                                for heading in ["PID", "User", "CPU", "Memory", "Command", ""] {
+                                    ui.label(egui::RichText::new(heading).strong());
+                                }
+                                ui.end_row();
+                                for process in processes {
+                                    for value in [&process.pid.to_string(), &process.user, &process.cpu, &process.memory, &process.command] {
+                                        ui.label(egui::RichText::new(value).monospace());
+                                    }
+                                    if ui.add_enabled(!running, egui::Button::new("Kill").small()).clicked() {
+                                        self.confirm_kill = Some((self.selected_tab, process));
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                        ui.add_space(8.0);
+                        ui.horizontal(|ui| {
+                            refresh = ui.add_enabled(!running, egui::Button::new("Refresh")).clicked();
+                            ui.label(egui::RichText::new("Tick Raw to see the full output")
+                                .color(egui::Color32::from_rgb(130, 130, 140)));
+                        });
+                    });
+                    if refresh && let Some(command) = preset_command("top", &server.os_type, &[]) {
+                        self.start_command_on(self.selected_tab, &command);
+                    }
+                    return;
+                }
+
+                // Find bar
+                if ui.input(|i| i.modifiers.command && i.key_pressed(egui::Key::F))
+                    && (self.output_focused || self.find_open)
+                {
+                    self.find_open = true;
+                    ui.memory_mut(|m| m.request_focus(egui::Id::new("find_query")));
+                }
+
+                // One snapshot so matches and layout agree while output streams in
+                let mut buffer = server.output.lock().unwrap().filtered(self.servers[self.selected_tab].stream_filter);
+                if self.show_raw {
+                    // Unparsed stream, uncolored, so find and copy see exactly what arrived
+                    buffer.text = server.raw_output.lock().unwrap().clone();
+                    buffer.spans.clear();
+                }
+                let matches = if self.find_open {
+                    find_matches(&buffer.text, &self.find_query, self.find_case_sensitive)
+                } else {
+                    Vec::new()
+                };
+                if self.find_current >= matches.len() {
+                    self.find_current = 0;
+                }
+
+                if self.find_open {
+                    ui.add_space(6.0);
+                    ui.horizontal(|ui| {
+                        let response = ui.add(egui::TextEdit::singleline(&mut self.find_query)
+                            .id(egui::Id::new("find_query"))
+                            .desired_width(240.0)
+                            .hint_text("Find in output"));
+                        if response.changed() {
+                            self.find_current = 0;
+                            self.find_scroll = true;
+                        }
+
+                        // Enter / Shift+Enter step through matches, Escape closes
+                        if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) && !matches.is_empty() {
+                            self.find_current = if ui.input(|i| i.modifiers.shift) {
+                                (self.find_current + matches.len() - 1) % matches.len()
+                            } else {
+                                (self.find_current + 1) % matches.len()
+                            };
+                            self.find_scroll = true;
+                            response.request_focus();
+                        }
+                        if ui.checkbox(&mut self.find_case_sensitive, "Aa")
+                            .on_hover_text("Case sensitive")
+                            .changed()
+                        {
+                            self.find_current = 0;
+                        }
+
+                        let count = if matches.is_empty() {
+                            "No matches".to_string()
+                        } else {
+                            format!("{}/{}", self.find_current + 1, matches.len())
+                        };
+                        ui.label(egui::RichText::new(count).color(egui::Color32::from_rgb(140, 140, 150)));
+
+                        if ui.input(|i| i.key_pressed(egui::Key::Escape)) || ui.small_button("✕").clicked() {
+                            self.find_open = false;
+                        }
+                    });
+                }
+
                 // Output
-                let output = server.get_output();
+                let font = egui::TextStyle::Monospace.resolve(ui.style());
+                let default_color = egui::Color32::from_rgb(190, 190, 195);
+                let highlights: Vec<(std::ops::Range<usize>, egui::Color32)> = matches.iter()
+                    .enumerate()
+                    .map(|(i, range)| {
+                        let color = if i == self.find_current {
+                            egui::Color32::from_rgb(150, 110, 40)  // Focused match
+                        } else {
+                            egui::Color32::from_rgb(80, 70, 40)
+                        };
+                        (range.clone(), color)
+                    })
+                    .collect();
+                let job = buffer.layout_job(font, default_color, &highlights);
+                let scroll_to = matches.get(self.find_current)
+                    .filter(|_| self.find_scroll)
+                    .map(|range| buffer.text[..range.start].chars().count());
+                let wrap = self.wrap_output;
+                let mut clear_output = false;
+                let mut save_output = false;
+                let mut layouter = |ui: &egui::Ui, _text: &str, wrap_width: f32| {
+                    let mut job = job.clone();
+                    job.wrap.max_width = if wrap { wrap_width } else { f32::INFINITY };
+                    ui.fonts(|f| f.layout_job(job))
+                };
                 egui::Frame::none()
                     .fill(egui::Color32::from_rgb(28, 28, 32))
                     .rounding(egui::Rounding::same(4.0))
                     .inner_margin(egui::Margin::same(8.0))
                     .show(ui, |ui| {
-                        egui::ScrollArea::vertical()
+                        egui::ScrollArea::new([!wrap, true])
                             .auto_shrink([false; 2])
-                            .stick_to_bottom(true)
+                            .stick_to_bottom(scroll_to.is_none() && !self.find_open)
                             .show(ui, |ui| {
-                                ui.add(
-                                    egui::TextEdit::multiline(&mut output.as_str())
-                                        .font(egui::TextStyle::Monospace)
-                                        .desired_width(f32::INFINITY)
-                                        .desired_rows(28)
-                                        .text_color(egui::Color32::from_rgb(190, 190, 195))
-                                );
+                                let shown = egui::TextEdit::multiline(&mut buffer.text.as_str())
+                                    .font(egui::TextStyle::Monospace)
+                                    .desired_width(f32::INFINITY)
+                                    .desired_rows(28)
+                                    .layouter(&mut layouter)
+                                    .show(ui);
+                                self.output_focused = shown.response.has_focus();
+
+                                // A right-click collapses the selection, so the menu works from a copy
+                                let selected = shown.cursor_range
+                                    .map(|range| range.slice_str(&buffer.text))
+                                    .unwrap_or_default();
+                                if !selected.is_empty() {
+                                    self.output_selection = selected.to_string();
+                                } else if ui.input(|i| i.pointer.primary_pressed()) {
+                                    self.output_selection.clear();
+                                }
+                                shown.response.context_menu(|ui| {
+                                    if ui.add_enabled(!self.output_selection.is_empty(), egui::Button::new("Copy selection"))
+                                        .clicked()
+                                    {
+                                        ui.ctx().copy_text(self.output_selection.clone());
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Copy all").clicked() {
+                                        ui.ctx().copy_text(buffer.text.clone());
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Clear").clicked() {
+                                        clear_output = true;
+                                        ui.close_menu();
+                                    }
+                                    if ui.button("Save to file...").clicked() {
+                                        save_output = true;
+                                        ui.close_menu();
+                                    }
+                                });
+
+                                if let Some(char_index) = scroll_to {
+                                    let rect = shown.galley
+                                        .pos_from_cursor(&shown.galley.from_ccursor(egui::text::CCursor::new(char_index)))
+                                        .translate(shown.galley_pos.to_vec2());
+                                    ui.scroll_to_rect(rect, Some(egui::Align::Center));
+                                    self.find_scroll = false;
+                                }
                             });
                     });
+                if clear_output {
+                    server.clear_output();
+                    server.set_status("Ready");
+                    server.set_last_duration(None);
+                    self.output_selection.clear();
+                }
+                if save_output {
+                    self.save_output(self.selected_tab);
+                }
             });
     }
 }
+
+
+// =============================================================================
+// TESTS
+// =============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Captured from `Install-Module` over ssh: a progress record and an error
+    const CLIXML_PROGRESS_ERROR: &str = r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04"><Obj S="progress" RefId="0"><TN RefId="0"><T>System.Management.Automation.PSCustomObject</T><T>System.Object</T></TN><MS><I64 N="SourceId">1</I64><PR N="Record"><AV>Preparing modules for first use.</AV><AI>0</AI><Nil /><PI>-1</PI><PC>-1</PC><T>Completed</T><SR>-1</SR><SD> </SD></PR></MS></Obj><S S="Error">Install-Module : A parameter cannot be found that matches parameter name 'Foo'._x000D__x000A_</S><S S="Error">    + CategoryInfo          : InvalidArgument: (:) [Install-Module], ParameterBindingException_x000D__x000A_</S></Objs>"#;
+
+    // Captured from `Get-Service WinRM | Select-Object Name, Status, DependentServices`
+    const CLIXML_OBJECT: &str = r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04"><Obj RefId="0"><TN RefId="0"><T>Selected.System.ServiceProcess.ServiceController</T><T>System.Management.Automation.PSCustomObject</T><T>System.Object</T></TN><ToString>@{Name=WinRM; Status=Running; DependentServices=System.ServiceProcess.ServiceController[]}</ToString><MS><S N="Name">WinRM</S><Obj N="Status" RefId="2"><TN RefId="1"><T>System.ServiceProcess.ServiceControllerStatus</T><T>System.Enum</T></TN><ToString>Running</ToString></Obj><Obj N="DependentServices" RefId="3"><TN RefId="2"><T>System.ServiceProcess.ServiceController[]</T></TN><LST><Obj RefId="4"><ToString>System.ServiceProcess.ServiceController</ToString><Props><S N="Name">WinRM-Helper</S><S N="DisplayName">Remote &amp; Helper</S></Props></Obj></LST></Obj></MS></Obj></Objs>"#;
+
+    #[test]
+    fn test_clixml_streams() {
+        let text = parse_clixml(CLIXML_PROGRESS_ERROR);
+        assert_eq!(
+            text,
+            "[ERROR] Install-Module : A parameter cannot be found that matches parameter name 'Foo'.\n\
+             [ERROR] + CategoryInfo          : InvalidArgument: (:) [Install-Module], ParameterBindingException\n"
+        );
+    }
+
+    #[test]
+    fn test_clixml_nested_object() {
+        let text = parse_clixml(CLIXML_OBJECT);
+        assert!(text.contains("Name: WinRM\n"), "{}", text);
+        assert!(text.contains("Status: Running\n"), "{}", text);
+        assert!(text.contains("DependentServices:\n  Name: WinRM-Helper\n  DisplayName: Remote & Helper\n"), "{}", text);
+        assert!(!text.contains("ServiceControllerStatus"), "{}", text);
+    }
+
+    #[test]
+    fn test_clixml_primitives() {
+        let xml = r#"<Objs Version="1.1.0.1" xmlns="http://schemas.microsoft.com/powershell/2004/04"><I32>42</I32><B>true</B><DT>2024-01-31T14:25:00.1234567+01:00</DT><Obj RefId="0"><MS><I64 N="FreeSpace">1073741824</I64><B N="IsReadOnly">false</B><Nil N="Label" /></MS></Obj></Objs>"#;
+        assert_eq!(
+            parse_clixml(xml),
+            "42\nTrue\n2024-01-31 14:25:00 +01:00\nFreeSpace: 1073741824\nIsReadOnly: False\nLabel: null\n"
+        );
+    }
+
+    #[test]
+    fn test_clixml_passthrough_and_escapes() {
+        assert_eq!(parse_clixml("plain line"), "plain line");
+        assert_eq!(decode_clixml_string("a_x000D__x000A_b_x0009_c_xZZ"), "a\nb\tc_xZZ");
+    }
+
+    #[test]
+    fn test_ansi_colors_and_reset() {
+        let mut style = AnsiStyle::default();
+        let segments = parse_ansi("\x1b[31mred\x1b[0m plain", &mut style);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0], ("red".to_string(), AnsiStyle { fg: Some(1), bold: false }));
+        assert_eq!(segments[1], (" plain".to_string(), AnsiStyle::default()));
+    }
+
+    #[test]
+    fn test_ansi_unknown_sequences_stripped() {
+        let mut style = AnsiStyle::default();
+        let segments = parse_ansi("\x1b[2K\x1b]0;title\x07done\x1b(B", &mut style);
+        assert_eq!(segments, vec![("done".to_string(), AnsiStyle::default())]);
+    }
+
+    #[test]
+    fn test_output_buffer_keeps_text_plain() {
+        let mut buffer = OutputBuffer::new(100);
+        buffer.push_line("\x1b[1;32mok\x1b[0m");
+        buffer.push_line("");
+        assert_eq!(buffer.text, "ok\n\n");
+        assert_eq!(buffer.spans, vec![(0..2, AnsiStyle { fg: Some(2), bold: true })]);
+    }
+
+    #[test]
+    fn test_output_buffer_colors_stderr() {
+        let mut buffer = OutputBuffer::new(100);
+        buffer.push_line("fine");
+        buffer.push_line("[stderr] E: broken");
+        assert_eq!(buffer.stderr_lines, 1);
+        assert_eq!(buffer.spans, vec![(5..23, AnsiStyle { fg: Some(STDERR_COLOR), bold: false })]);
+    }
+
+    #[test]
+    fn test_output_buffer_stream_filter() {
+        let mut buffer = OutputBuffer::new(100);
+        buffer.push_line("\x1b[32mok\x1b[0m");
+        buffer.push_line("[stderr] boom");
+        buffer.push_line("done");
+
+        let stdout = buffer.filtered(StreamFilter::Stdout);
+        assert_eq!(stdout.text, "ok\ndone\n");
+        assert_eq!(&stdout.text[stdout.spans[0].0.clone()], "ok");
+        let stderr = buffer.filtered(StreamFilter::Stderr);
+        assert_eq!(stderr.text, "[stderr] boom\n");
+        assert_eq!(stderr.spans.len(), 1);
+        assert_eq!(buffer.filtered(StreamFilter::All).text, buffer.text);
+    }
+
+    #[test]
+    fn test_mask_secrets() {
+        let env = vec![
+            config::EnvVar { name: "TOKEN".to_string(), value: "s3cret".to_string(), secret: true },
+            config::EnvVar { name: "LANG".to_string(), value: "C".to_string(), secret: false },
+        ];
+        assert_eq!(mask_secrets("token=s3cret LANG=C", &env), "token=**** LANG=C");
+        assert!(matches!(mask_secrets("nothing here", &env), std::borrow::Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_format_ago() {
+        assert_eq!(format_ago(Duration::from_secs(5)), "just now");
+        assert_eq!(format_ago(Duration::from_secs(150)), "2m ago");
+        assert_eq!(format_ago(Duration::from_secs(2 * 3600 + 59)), "2h ago");
+        assert_eq!(format_ago(Duration::from_secs(3 * 86400)), "3d ago");
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let text = "Error: disk\nerror again\nERROR";
+        assert_eq!(find_matches(text, "error", false), vec![0..5, 12..17, 24..29]);
+        assert_eq!(find_matches(text, "error", true), vec![12..17]);
+        assert!(find_matches(text, "", false).is_empty());
+    }
+
+    #[test]
+    fn test_is_failure() {
+        for status in ["Failed (exit 2)", "Error", "Timed Out", "Auth Failed", "Unreachable"] {
+            assert!(is_failure(status), "{}", status);
+        }
+        for status in ["Done", "Online (3 ms)", "Offline", "Pinging...", "Dry Run", "Cancelled", "Skipped", "Queued"] {
+            assert!(!is_failure(status), "{}", status);
+        }
+    }
+
+    #[test]
+    fn test_stale_ping_results_are_dropped() {
+        let server = Server {
+            name: "web".into(),
+            ip: "10.0.0.1".into(),
+            username: "admin".into(),
+            os_type: OsType::Linux,
+            port: None,
+            key_path: None,
+            jump_host: None,
+            tags: Vec::new(),
+            update_cmd: None,
+            info_cmd: None,
+        };
+        let state = ServerState::new(&server, 100);
+        let first = state.ping_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        let second = state.ping_generation.fetch_add(1, Ordering::Relaxed) + 1;
+        assert!(!state.set_ping_status(first, "Offline"));
+        assert!(state.set_ping_status(second, "Online (3 ms)"));
+        assert_eq!(state.get_status(), "Online (3 ms)");
+
+        // A run that starts before the answer wins
+        state.set_running(true);
+        assert!(!state.set_ping_status(second, "Offline"));
+        assert_eq!(state.get_status(), "Online (3 ms)");
+    }
+
+    #[test]
+    fn test_shown_range() {
+        // Grouped by tag, so the list order isn't index order
+        let order = [3, 0, 4, 1];
+        assert_eq!(shown_range(&order, 0, 1), [0, 4, 1]);
+        assert_eq!(shown_range(&order, 1, 3), [3, 0, 4, 1]);
+        assert_eq!(shown_range(&order, 2, 4), [4]);
+    }
+
+    #[test]
+    fn test_trim_raw_output_multibyte() {
+        let mut raw = String::new();
+        for _ in 0..20 {
+            raw.push_str("große Ausgabe ─ 🚀\n");
+        }
+        let line_len = raw.len() / 20;
+        trim_raw_output(&mut raw, line_len * 5 + 3);
+        assert_eq!(raw.lines().count(), 5);
+        assert!(raw.lines().all(|line| line == "große Ausgabe ─ 🚀"));
+    }
+
+    #[test]
+    fn test_output_buffer_drops_oldest_lines() {
+        let mut buffer = OutputBuffer::new(10);
+        for i in 0..10 {
+            buffer.push_line(&format!("line {}", i));
+        }
+        buffer.push_line("\x1b[31mred\x1b[0m");
+        assert!(!buffer.truncated);
+
+        buffer.push_line("line 11");
+        assert!(buffer.text.starts_with("[output truncated]\nline 2\n"));
+        assert_eq!(buffer.lines, 10);
+        let (range, _) = &buffer.spans[0];
+        assert_eq!(&buffer.text[range.clone()], "red");
+    }
+
+    #[test]
+    fn test_command_timeout_is_an_error_state() {
+        let timed_out = ssh::SshError::CommandTimeout(Duration::from_secs(30));
+        let status = ssh_error_status(&timed_out);
+        assert_eq!(status, "Timed Out");
+        assert!(is_retryable(&status));
+        assert_eq!(status_color(&status, false), status_color("Error", false));
+
+        let summary = error_summary(&timed_out.to_string(), "");
+        assert_eq!(summary, "Command timed out after 30s");
+    }
+
+    #[test]
+    fn test_output_buffer_collapses_repeats() {
+        let mut buffer = OutputBuffer::new(100);
+        buffer.collapse_repeats = true;
+        buffer.push_line("start");
+        for _ in 0..3 {
+            buffer.push_line("\x1b[33mretrying\x1b[0m");
+        }
+        buffer.push_line("done");
+        buffer.push_line("done");
+        assert_eq!(buffer.text, "start\nretrying (x3)\ndone (x2)\n");
+        assert_eq!(buffer.lines, 3);
+        assert_eq!(buffer.spans.len(), 1);
+        assert_eq!(&buffer.text[buffer.spans[0].0.clone()], "retrying");
+
+        buffer.collapse_repeats = false;
+        buffer.push_line("done");
+        assert!(buffer.text.ends_with("done (x2)\ndone\n"));
+    }
+
+    #[test]
+    fn test_latest_log() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        for name in ["web-2026-10-13.log", "web-2026-10-14.log", "web-2-2026-10-15.log", "web-notes.log"] {
+            std::fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(latest_log(dir, "web"), Some(dir.join("web-2026-10-14.log")));
+        assert_eq!(latest_log(dir, "db"), None);
+
+        let date = chrono::NaiveDate::from_ymd_opt(2026, 10, 14).unwrap();
+        assert_eq!(log_file_name("../../etc/cron.d/x", date), ".._.._etc_cron.d_x-2026-10-14.log");
+    }
+
+    #[test]
+    fn test_run_in_waves_stops_after_failure() {
+        let ran = Arc::new(Mutex::new(Vec::new()));
+        let job = |name: &'static str, ok: bool| -> QueuedJob {
+            let ran = ran.clone();
+            QueuedJob {
+                server: name.to_string(),
+                task: "true".to_string(),
+                job: Box::new(move |skip| {
+                    ran.lock().unwrap().push((name, skip.is_some()));
+                    ok && skip.is_none()
+                }),
+            }
+        };
+        let jobs = Mutex::new(VecDeque::from([job("a", true), job("b", false), job("c", true), job("d", true)]));
+        assert_eq!(run_in_waves(&jobs, 1, true), 3);
+        assert_eq!(*ran.lock().unwrap(), [("a", false), ("b", false), ("c", true), ("d", true)]);
+
+        ran.lock().unwrap().clear();
+        let jobs = Mutex::new(VecDeque::from([job("a", false), job("b", true)]));
+        assert_eq!(run_in_waves(&jobs, 1, false), 1);
+        assert_eq!(*ran.lock().unwrap(), [("a", false), ("b", false)]);
+    }
+
+    #[test]
+    fn test_shell_command_masks_secrets() {
+        let env = vec![config::EnvVar { name: "TOKEN".into(), value: "hunter2".into(), secret: true }];
+        assert_eq!(
+            shell_command(&Task::Exec("sudo deploy".into()), &env, &OsType::Linux).as_deref(),
+            Some("export TOKEN='****'; sudo deploy")
+        );
+        assert_eq!(shell_command(&Task::Diagnose, &env, &OsType::Linux), None);
+    }
+
+    #[test]
+    fn test_reorder_queue() {
+        let job = |name: &str| QueuedJob { server: name.to_string(), task: "true".to_string(), job: Box::new(|_| true) };
+        let mut queue = VecDeque::from([job("a"), job("b"), job("c")]);
+        let order = |queue: &VecDeque<QueuedJob>| queue.iter().map(|q| q.server.clone()).collect::<Vec<_>>();
+        reorder_queue(&mut queue, "c", "a");
+        assert_eq!(order(&queue), ["c", "a", "b"]);
+        reorder_queue(&mut queue, "c", "b");
+        assert_eq!(order(&queue), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_dry_run_lines() {
+        let env = vec![
+            config::EnvVar { name: "LANG".into(), value: "C".into(), secret: false },
+            config::EnvVar { name: "TOKEN".into(), value: "hunter2".into(), secret: true },
+        ];
+        assert_eq!(
+            dry_run_lines(&Task::Exec("apt update\napt upgrade -y".into()), &env),
+            [">>> Would run:", "    apt update", "    apt upgrade -y", ">>> With LANG=C", ">>> With TOKEN=****"]
+        );
+        assert_eq!(dry_run_lines(&Task::Reboot("sudo reboot".into()), &env), [">>> Would reboot with: sudo reboot"]);
+        let script = Task::Script { local: PathBuf::from("deploy.sh"), contents: "set -e\nmake install\n".into() };
+        assert_eq!(dry_run_lines(&script, &env), [">>> Would run deploy.sh (2 lines) from stdin"]);
+        assert!(is_powershell_script(std::path::Path::new("C:/scripts/Cleanup.PS1")));
+        assert!(!is_powershell_script(std::path::Path::new("deploy.sh")));
+    }
+
+    #[test]
+    fn test_terminal_command() {
+        let ssh = ["ssh", "-p", "2222", "deploy@10.0.0.5"].map(String::from);
+        assert_eq!(
+            terminal_command(Some("gnome-terminal --"), &ssh),
+            ["gnome-terminal", "--", "ssh", "-p", "2222", "deploy@10.0.0.5"]
+        );
+        assert_eq!(terminal_command(Some("  "), &ssh), terminal_command(None, &ssh));
+        assert_eq!(shell_quote("C:/My Keys/id"), "'C:/My Keys/id'");
+        assert_eq!(shell_quote("deploy@10.0.0.5"), "deploy@10.0.0.5");
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        use similar::ChangeTag::*;
+        let diff = diff_lines("os=Ubuntu\nkernel=6.8\ndisk=40%", "os=Ubuntu\nkernel=6.5\ndisk=40%");
+        assert_eq!(diff, [
+            (Equal, "os=Ubuntu".to_string()),
+            (Delete, "kernel=6.8".to_string()),
+            (Insert, "kernel=6.5".to_string()),
+            (Equal, "disk=40%".to_string()),
+        ]);
+        assert_eq!(comparable_output("\x1b[31m>>> Exit code: 1\x1b[0m"), ">>> Exit code: 1");
+    }
+
+    #[test]
+    fn test_error_summary() {
+        let raw = "starting\n[stderr] E: Unable to locate package ngnix\n[stderr] second\n";
+        assert_eq!(
+            error_summary("Command failed with exit code 100", raw),
+            "Command failed with exit code 100 - E: Unable to locate package ngnix"
+        );
+        let long = error_summary(&"x".repeat(500), "");
+        assert_eq!(long.chars().count(), ERROR_SUMMARY_CHARS + 3);
+    }
+
+    #[test]
+    fn test_output_tail() {
+        assert_eq!(output_tail("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(output_tail("a\nb", 5), "a\nb");
+        assert_eq!(output_tail("", 3), "");
+    }
+}
@@ -0,0 +1,134 @@
+// =============================================================================
+// METRICS ENDPOINT
+// =============================================================================
+// Read-only Prometheus text exposition of each server's state, served on
+// 127.0.0.1 when started with --metrics-port. Off by default.
+// =============================================================================
+
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// One server's numbers at scrape time
+pub struct ServerMetrics {
+    pub name: String,
+    /// The last connection attempt logged in
+    pub up: bool,
+    pub running: bool,
+    pub last_duration: Option<Duration>,
+    /// Runs that ended in an error since the config was loaded
+    pub failures: u64,
+    pub last_seen: Option<SystemTime>,
+}
+
+/// Listen on `127.0.0.1:port` and answer `GET /metrics` with whatever `snapshot` returns.
+/// Fails only if the port can't be bound; the server runs on its own thread.
+pub fn serve<F>(port: u16, snapshot: F) -> std::io::Result<()>
+where
+    F: Fn() -> Vec<ServerMetrics> + Send + 'static,
+{
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A stuck or broken client only costs its own request
+            let _ = respond(stream, &snapshot);
+        }
+    });
+    Ok(())
+}
+
+fn respond<F>(mut stream: TcpStream, snapshot: &F) -> std::io::Result<()>
+where
+    F: Fn() -> Vec<ServerMetrics>,
+{
+    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+    let mut request = [0u8; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.strip_prefix("GET ").and_then(|rest| rest.split_whitespace().next());
+
+    let (status, body) = match path {
+        Some("/metrics") | Some("/") => ("200 OK", render(&snapshot())),
+        _ => ("404 Not Found", "Only GET /metrics is served\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+/// The Prometheus text format for `servers`
+pub fn render(servers: &[ServerMetrics]) -> String {
+    let mut out = String::new();
+    let mut family = |name: &str, kind: &str, help: &str, value: &dyn Fn(&ServerMetrics) -> Option<String>| {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        for server in servers {
+            if let Some(value) = value(server) {
+                let _ = writeln!(out, "{}{{name=\"{}\"}} {}", name, escape_label(&server.name), value);
+            }
+        }
+    };
+
+    family("server_up", "gauge", "Whether the last connection attempt logged in.", &|s| {
+        Some(u8::from(s.up).to_string())
+    });
+    family("server_running", "gauge", "Whether a command is running on the server.", &|s| {
+        Some(u8::from(s.running).to_string())
+    });
+    family("last_command_duration_seconds", "gauge", "How long the last run took.", &|s| {
+        s.last_duration.map(|d| d.as_secs_f64().to_string())
+    });
+    family("command_failures_total", "counter", "Runs that ended in an error.", &|s| {
+        Some(s.failures.to_string())
+    });
+    family("server_last_seen_timestamp_seconds", "gauge", "When the server last accepted a login.", &|s| {
+        let since_epoch = s.last_seen?.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+        Some(since_epoch.as_secs().to_string())
+    });
+    out
+}
+
+/// Backslash, quote and newline are the characters a label value must escape
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let servers = [
+            ServerMetrics {
+                name: "web-01".to_string(),
+                up: true,
+                running: false,
+                last_duration: Some(Duration::from_millis(1500)),
+                failures: 2,
+                last_seen: Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            },
+            ServerMetrics {
+                name: "odd \"name\"".to_string(),
+                up: false,
+                running: true,
+                last_duration: None,
+                failures: 0,
+                last_seen: None,
+            },
+        ];
+        let text = render(&servers);
+        assert!(text.contains("# TYPE command_failures_total counter\n"));
+        assert!(text.contains("server_up{name=\"web-01\"} 1\n"));
+        assert!(text.contains("server_up{name=\"odd \\\"name\\\"\"} 0\n"));
+        assert!(text.contains("last_command_duration_seconds{name=\"web-01\"} 1.5\n"));
+        assert!(!text.contains("last_command_duration_seconds{name=\"odd"));
+        assert!(text.contains("server_last_seen_timestamp_seconds{name=\"web-01\"} 1700000000\n"));
+    }
+}
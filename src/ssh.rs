@@ -5,84 +5,888 @@
 // =============================================================================
 
 use russh::*;
-use russh_keys::*;
-use std::sync::Arc;
+use russh::keys::*;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use zeroize::Zeroizing;
+
+/// How to authenticate against a server
+#[derive(Clone, Debug)]
+pub enum AuthMethod {
+    /// Password login, wiped from memory on drop
+    Password(Zeroizing<String>),
+    /// Private key file (unencrypted)
+    Key(PathBuf),
+    /// Keys held by the running ssh-agent only
+    Agent,
+}
+
+impl AuthMethod {
+    /// How the login is made, without the secret; sessions are only shared within one
+    fn identity(&self) -> String {
+        match self {
+            AuthMethod::Password(_) => "password".to_string(),
+            AuthMethod::Key(path) => format!("key {}", path.display()),
+            AuthMethod::Agent => "agent".to_string(),
+        }
+    }
+}
+
+/// Where to connect and how to log in
+#[derive(Clone, Debug)]
+pub struct Target {
+    pub host: String,
+    pub port: u16,
+    pub username: String,
+    pub auth: AuthMethod,
+    /// Limit for DNS, TCP connect and authentication together
+    pub connect_timeout: Duration,
+    /// How often to send keepalives on an idle connection, `None` to never
+    pub keepalive_interval: Option<Duration>,
+    /// Keep the session open for later commands until idle this long, `None` to close after each
+    pub reuse_session: Option<Duration>,
+    /// Bastion to tunnel through (its own `jump` is ignored)
+    pub jump: Option<Box<Target>>,
+    /// Answer for a keyboard-interactive prompt that follows the password (e.g. OTP)
+    pub second_factor: Option<Zeroizing<String>>,
+    /// Written to the command's stdin once it starts (e.g. a sudo password)
+    pub stdin: Option<Zeroizing<String>>,
+    /// Close stdin after writing `stdin`, for commands that read it to the end (a piped script)
+    pub stdin_eof: bool,
+    /// Warn through the callback after this long without output, `None` to never
+    pub stall_warning: Option<Duration>,
+    /// Lines queued here while a command runs are sent to its stdin
+    pub input: Option<Arc<Mutex<Vec<String>>>>,
+    /// Request a pseudo-terminal before running the command
+    pub pty: Option<PtyRequest>,
+    /// Tries for connect + login when the failure looks transient, at least 1
+    pub connect_attempts: u32,
+    /// Told about each retry, e.g. "Retry 2/3 in 1s (Connection failed: ...)"
+    pub on_retry: Option<RetryNotice>,
+    /// Interrupt the command and fail with `CommandTimeout` after this long, `None` to wait forever
+    pub command_timeout: Option<Duration>,
+    /// Environment variables requested for the command's channel
+    pub env: Vec<(String, String)>,
+    /// Run instead of the command when the server refuses any of `env`,
+    /// usually the same command with the variables set inline
+    pub env_command: Option<String>,
+}
+
+/// Callback for connect retries; a newtype so `Target` can stay `Debug`
+#[derive(Clone)]
+pub struct RetryNotice(pub Arc<dyn Fn(&str) + Send + Sync>);
+
+impl fmt::Debug for RetryNotice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("RetryNotice")
+    }
+}
+
+/// Terminal modes for `channel.request_pty`: no echo, or the sudo password
+/// written to stdin would come straight back as output
+const PTY_MODES: &[(Pty, u32)] = &[(Pty::ECHO, 0)];
+
+/// Terminal type and size for `channel.request_pty`
+#[derive(Clone, Debug)]
+pub struct PtyRequest {
+    pub term: String,
+    pub columns: u32,
+    pub rows: u32,
+}
+
+/// Marks callback lines that came from the command's stderr
+pub const STDERR_PREFIX: &str = "[stderr] ";
+
+/// Start of the line passed to the callback when a command has gone quiet
+pub const STALL_WARNING: &str = ">>> Possibly waiting for input";
+
+/// Keyboard-interactive login asked for more than the password
+#[derive(Debug)]
+pub struct SecondFactorRequired {
+    pub prompt: String,
+}
+
+impl fmt::Display for SecondFactorRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Second factor required: {}", self.prompt.trim())
+    }
+}
+
+impl std::error::Error for SecondFactorRequired {}
+
+/// The run was stopped by the user
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled by user")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Why a run failed, so the UI can tell a down server from a misconfigured one
+#[derive(Debug)]
+pub enum SshError {
+    /// DNS lookup, TCP connect or tunnel setup failed
+    Connect(String),
+    /// The server, or the jump host when it is `host`, refused the credentials
+    Auth { host: String },
+    /// Connecting and authenticating took longer than the allowed time
+    Timeout(Duration),
+    /// The command ran but exited non-zero. Its output went to the callback
+    /// already and is left out here, where it would skip secret masking.
+    CommandFailed { code: u32 },
+    /// The command was still running when `Target::command_timeout` ran out
+    CommandTimeout(Duration),
+}
+
+impl fmt::Display for SshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SshError::Connect(reason) => write!(f, "Connection failed: {}", reason),
+            SshError::Auth { host } => write!(f, "Authentication failed on {}", host),
+            SshError::Timeout(after) => write!(f, "Connection timed out after {}s", after.as_secs()),
+            SshError::CommandFailed { code } => write!(f, "Command failed with exit code {}", code),
+            SshError::CommandTimeout(after) => write!(f, "Command timed out after {}s", after.as_secs()),
+        }
+    }
+}
+
+impl std::error::Error for SshError {}
+
+/// Why a server's host key was rejected
+#[derive(Clone, Debug)]
+pub enum HostKeyError {
+    /// No known_hosts entry yet - the key can be trusted on first use
+    Unknown {
+        host: String,
+        port: u16,
+        key: PublicKey,
+    },
+    /// The key differs from the one recorded in known_hosts
+    Mismatch { fingerprint: String, line: usize },
+}
+
+impl HostKeyError {
+    pub fn fingerprint(&self) -> String {
+        match self {
+            HostKeyError::Unknown { key, .. } => key.fingerprint(HashAlg::Sha256).to_string(),
+            HostKeyError::Mismatch { fingerprint, .. } => fingerprint.clone(),
+        }
+    }
+}
+
+impl fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyError::Unknown { .. } => {
+                write!(f, "Unknown host key {}", self.fingerprint())
+            }
+            HostKeyError::Mismatch { fingerprint, line } => write!(
+                f,
+                "Host key mismatch: server sent {} but known_hosts line {} differs",
+                fingerprint, line
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HostKeyError {}
+
+/// Record an accepted host key in ~/.ssh/known_hosts
+pub fn trust_host_key(
+    host: &str,
+    port: u16,
+    key: &PublicKey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    known_hosts::learn_known_hosts(host, port, key)?;
+    Ok(())
+}
+
+/// Whether an ssh-agent looks reachable (SSH_AUTH_SOCK is set).
+/// The agent is only looked for through SSH_AUTH_SOCK, so this is always false on Windows.
+pub fn agent_available() -> bool {
+    cfg!(unix) && std::env::var_os("SSH_AUTH_SOCK").is_some()
+}
+
+/// Try each identity offered by the ssh-agent until one is accepted.
+/// Returns false if the agent is unreachable or none of its keys work.
+async fn authenticate_with_agent(
+    session: &mut client::Handle<Client>,
+    username: &str,
+) -> bool {
+    let Ok(mut agent) = agent::client::AgentClient::connect_env().await else {
+        return false;
+    };
+    let Ok(identities) = agent.request_identities().await else {
+        return false;
+    };
+
+    // RSA keys sign with the best SHA-2 hash the server takes
+    let rsa_hash = session.best_supported_rsa_hash().await.ok().flatten().flatten();
+    for key in identities {
+        let hash = key.algorithm().is_rsa().then_some(rsa_hash).flatten();
+        if let Ok(result) = session.authenticate_publickey_with(username, key, hash, &mut agent).await
+            && result.success()
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// An authenticated session, plus the bastion session it tunnels through
+struct Session {
+    handle: client::Handle<Client>,
+    _jump: Option<client::Handle<Client>>,
+    /// How the login succeeded, as returned by `authenticate`
+    auth_method: &'static str,
+}
+
+/// Run the SSH handshake over `stream`.
+/// Host key problems are returned as `HostKeyError`.
+async fn handshake<S>(
+    target: &Target,
+    stream: S,
+) -> Result<client::Handle<Client>, Box<dyn std::error::Error>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    // Keepalives stop firewalls and NAT from dropping long, quiet runs
+    let config = Arc::new(client::Config {
+        keepalive_interval: target.keepalive_interval,
+        keepalive_max: 3,
+        ..Default::default()
+    });
+    let rejected = Arc::new(Mutex::new(None));
+    let handler = Client {
+        host: target.host.clone(),
+        port: target.port,
+        rejected: rejected.clone(),
+    };
+    match client::connect_stream(config, stream, handler).await {
+        Ok(session) => Ok(session),
+        Err(e) => {
+            // Surface host key problems as their own error type
+            if let Some(reason) = rejected.lock().unwrap().take() {
+                return Err(reason.into());
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// Answer keyboard-interactive prompts: the password goes to the first one,
+/// the second factor (if known) to the next. Any further prompt is returned
+/// as `SecondFactorRequired` so the user can be asked for it.
+async fn authenticate_keyboard_interactive(
+    session: &mut client::Handle<Client>,
+    username: &str,
+    password: &str,
+    second_factor: Option<&str>,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut answers = [password, second_factor.unwrap_or_default()].into_iter();
+    let mut remaining = if second_factor.is_some() { 2 } else { 1 };
+
+    let mut response = session
+        .authenticate_keyboard_interactive_start(username, None)
+        .await?;
+    loop {
+        match response {
+            client::KeyboardInteractiveAuthResponse::Success => return Ok(true),
+            client::KeyboardInteractiveAuthResponse::Failure { .. } => return Ok(false),
+            client::KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } => {
+                let mut responses = Vec::new();
+                for prompt in prompts {
+                    if remaining == 0 {
+                        return Err(SecondFactorRequired { prompt: prompt.prompt }.into());
+                    }
+                    remaining -= 1;
+                    responses.push(answers.next().unwrap_or_default().to_string());
+                }
+                response = session
+                    .authenticate_keyboard_interactive_respond(responses)
+                    .await?;
+            }
+        }
+    }
+}
+
+/// Log in with the target's auth method.
+/// Returns the method that worked, e.g. "publickey (agent)", or `None` if all were refused.
+async fn authenticate(
+    session: &mut client::Handle<Client>,
+    target: &Target,
+) -> Result<Option<&'static str>, Box<dyn std::error::Error>> {
+    let username = target.username.as_str();
+    let method = match &target.auth {
+        AuthMethod::Password(password) => {
+            // Agent keys first, the typed password only if they are refused,
+            // then keyboard-interactive for hosts that disallow plain password
+            if authenticate_with_agent(session, username).await {
+                Some("publickey (agent)")
+            } else if session.authenticate_password(username, password.as_str()).await?.success() {
+                Some("password")
+            } else if authenticate_keyboard_interactive(
+                session,
+                username,
+                password,
+                target.second_factor.as_ref().map(|code| code.as_str()),
+            )
+            .await?
+            {
+                Some("keyboard-interactive")
+            } else {
+                None
+            }
+        }
+        AuthMethod::Key(path) => {
+            let key = load_secret_key(path, None)
+                .map_err(|e| format!("Failed to load key {}: {}", path.display(), e))?;
+            let rsa_hash = session.best_supported_rsa_hash().await?.flatten();
+            let key = PrivateKeyWithHashAlg::new(Arc::new(key), rsa_hash);
+            session.authenticate_publickey(username, key).await?.success().then_some("publickey")
+        }
+        AuthMethod::Agent => authenticate_with_agent(session, username).await.then_some("publickey (agent)"),
+    };
+    Ok(method)
+}
+
+/// `host:port`, with IPv6 literals bracketed as in `[2001:db8::1]:22`
+pub fn format_address(host: &str, port: u16) -> String {
+    if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// TCP connect with DNS and refused-connection errors reported as `SshError::Connect`
+async fn connect_tcp(host: &str, port: u16) -> Result<tokio::net::TcpStream, SshError> {
+    tokio::net::TcpStream::connect((host, port))
+        .await
+        .map_err(|e| SshError::Connect(format!("{}: {}", format_address(host, port), e)))
+}
+
+/// Open a session and authenticate it, going through the jump host if set
+async fn connect_and_authenticate(
+    target: &Target,
+) -> Result<Session, Box<dyn std::error::Error>> {
+    let (mut handle, jump) = connect_unauthenticated(target).await?;
+
+    let Some(auth_method) = authenticate(&mut handle, target).await? else {
+        return Err(SshError::Auth { host: target.host.clone() }.into());
+    };
+
+    Ok(Session { handle, _jump: jump, auth_method })
+}
+
+/// Handshake with the target, logging in to the jump host (if any) on the way
+async fn connect_unauthenticated(
+    target: &Target,
+) -> Result<(client::Handle<Client>, Option<client::Handle<Client>>), Box<dyn std::error::Error>> {
+    Ok(match &target.jump {
+        None => {
+            let socket = connect_tcp(&target.host, target.port).await?;
+            (handshake(target, socket).await?, None)
+        }
+        Some(jump) => {
+            let socket = connect_tcp(&jump.host, jump.port).await?;
+            let mut jump_session = handshake(jump, socket).await?;
+            if authenticate(&mut jump_session, jump).await?.is_none() {
+                return Err(SshError::Auth { host: jump.host.clone() }.into());
+            }
+            let channel = jump_session
+                .channel_open_direct_tcpip(target.host.as_str(), target.port as u32, "127.0.0.1", 0)
+                .await
+                .map_err(|e| SshError::Connect(format!("tunnel via {} to {}: {}", jump.host, target.host, e)))?;
+            (handshake(target, channel.into_stream()).await?, Some(jump_session))
+        }
+    })
+}
+
+/// Resolves once `cancel` has been set
+async fn cancelled(cancel: &AtomicBool) {
+    while !cancel.load(Ordering::Relaxed) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Connect and log in within the target's timeout, giving up early on cancel
+async fn open_session_once(target: &Target, cancel: &AtomicBool) -> Result<Session, Box<dyn std::error::Error>> {
+    let connect = tokio::time::timeout(target.connect_timeout, connect_and_authenticate(target));
+    tokio::select! {
+        result = connect => result.map_err(|_| SshError::Timeout(target.connect_timeout))?,
+        _ = cancelled(cancel) => Err(Cancelled.into()),
+    }
+}
+
+/// Errors a second try may fix: timeouts, refused or reset connections.
+/// Auth, host key and cancel errors are final.
+fn is_transient(e: &(dyn std::error::Error + 'static)) -> bool {
+    if let Some(ssh_error) = e.downcast_ref::<SshError>() {
+        return matches!(ssh_error, SshError::Connect(_) | SshError::Timeout(_));
+    }
+    matches!(
+        e.downcast_ref::<russh::Error>(),
+        Some(russh::Error::IO(_) | russh::Error::Disconnect | russh::Error::ConnectionTimeout)
+    ) || e.is::<std::io::Error>()
+}
+
+/// `open_session_once`, retried with backoff (1s, 2s, 4s...) on transient errors
+async fn open_session(target: &Target, cancel: &AtomicBool) -> Result<Session, Box<dyn std::error::Error>> {
+    let attempts = target.connect_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match open_session_once(target, cancel).await {
+            Err(e) if attempt < attempts && is_transient(e.as_ref()) => {
+                let delay = Duration::from_secs(1 << (attempt - 1).min(5));
+                attempt += 1;
+                if let Some(ref notice) = target.on_retry {
+                    (notice.0)(&format!("Retry {}/{} in {}s ({})", attempt, attempts, delay.as_secs(), e));
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = cancelled(cancel) => return Err(Cancelled.into()),
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+// =============================================================================
+// SESSION CACHE
+// =============================================================================
+// Logged-in sessions kept per target so back-to-back commands skip the
+// handshake. Their background tasks live on one shared runtime.
+// =============================================================================
+
+/// Runtime every call runs on; cached sessions must outlive any single call
+fn runtime() -> &'static tokio::runtime::Runtime {
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| tokio::runtime::Runtime::new().expect("failed to start the tokio runtime"))
+}
+
+struct CachedSession {
+    session: Arc<Session>,
+    last_used: Instant,
+    idle_timeout: Duration,
+}
+
+fn session_cache() -> &'static Mutex<HashMap<String, CachedSession>> {
+    static CACHE: OnceLock<Mutex<HashMap<String, CachedSession>>> = OnceLock::new();
+    CACHE.get_or_init(|| {
+        // Close sessions that sat idle too long, even if nothing asks for them again
+        runtime().spawn(async {
+            loop {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                session_cache().lock().unwrap().retain(|_, cached| {
+                    cached.last_used.elapsed() < cached.idle_timeout && !cached.session.handle.is_closed()
+                });
+            }
+        });
+        Mutex::new(HashMap::new())
+    })
+}
+
+/// Sessions are shared by user, host, port, login method and jump host, so
+/// switching a server to another key or method opens a new one
+fn cache_key(target: &Target) -> String {
+    let mut key = format!("{}@{} ({})", target.username, format_address(&target.host, target.port), target.auth.identity());
+    if let Some(ref jump) = target.jump {
+        key.push_str(&format!(" via {}@{} ({})", jump.username, format_address(&jump.host, jump.port), jump.auth.identity()));
+    }
+    key
+}
+
+/// Drop the cached session for `target`, closing it once no run is using it
+pub fn forget_session(target: &Target) {
+    session_cache().lock().unwrap().remove(&cache_key(target));
+}
+
+/// A logged-in session, from the cache when allowed; the flag is true if reused
+async fn session_for(target: &Target, cancel: &AtomicBool) -> Result<(Arc<Session>, bool), Box<dyn std::error::Error>> {
+    let Some(idle_timeout) = target.reuse_session else {
+        return Ok((Arc::new(open_session(target, cancel).await?), false));
+    };
+
+    let key = cache_key(target);
+    if let Some(cached) = session_cache().lock().unwrap().get_mut(&key)
+        && !cached.session.handle.is_closed()
+        && cached.last_used.elapsed() < cached.idle_timeout
+    {
+        cached.last_used = Instant::now();
+        return Ok((cached.session.clone(), true));
+    }
+
+    let session = Arc::new(open_session(target, cancel).await?);
+    session_cache().lock().unwrap().insert(key, CachedSession {
+        session: session.clone(),
+        last_used: Instant::now(),
+        idle_timeout,
+    });
+    Ok((session, false))
+}
+
+/// Open a session channel. A cached session the server has since dropped
+/// is replaced with a fresh one. The flag is true if the session was reused.
+async fn open_channel(
+    target: &Target,
+    cancel: &AtomicBool,
+) -> Result<(Arc<Session>, Channel<client::Msg>, bool), Box<dyn std::error::Error>> {
+    let (session, reused) = session_for(target, cancel).await?;
+    match session.handle.channel_open_session().await {
+        Ok(channel) => Ok((session, channel, reused)),
+        Err(_) if reused => {
+            forget_session(target);
+            let (session, _) = session_for(target, cancel).await?;
+            let channel = session.handle.channel_open_session().await?;
+            Ok((session, channel, false))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The run log line saying how this session logged in
+fn auth_notice(session: &Session, reused: bool) -> String {
+    if reused {
+        format!(">>> Reusing session (authenticated via {})", session.auth_method)
+    } else {
+        format!(">>> Authenticated via {}", session.auth_method)
+    }
+}
+
+/// Start the SFTP subsystem on a new channel
+async fn open_sftp(
+    target: &Target,
+    cancel: &AtomicBool,
+) -> Result<(Arc<Session>, russh_sftp::client::SftpSession), Box<dyn std::error::Error>> {
+    let (session, channel, _) = open_channel(target, cancel).await?;
+    channel.request_subsystem(true, "sftp").await?;
+    Ok((session, russh_sftp::client::SftpSession::new(channel.into_stream()).await?))
+}
+
+/// Copy a local file to `remote_path` over SFTP.
+/// The callback is called with (bytes sent, total bytes) after each chunk.
+/// Fails with a clear message if the remote directory doesn't exist.
+pub fn upload_file<F>(
+    target: &Target,
+    local_path: &Path,
+    remote_path: &str,
+    cancel: &AtomicBool,
+    mut progress: F,
+) -> Result<u64, Box<dyn std::error::Error>>
+where
+    F: FnMut(u64, u64),
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    runtime().block_on(async {
+        let mut local = tokio::fs::File::open(local_path)
+            .await
+            .map_err(|e| format!("Cannot open {}: {}", local_path.display(), e))?;
+        let total = local.metadata().await?.len();
+
+        let (_session, sftp) = open_sftp(target, cancel).await?;
+
+        if let Some((dir, _)) = remote_path.rsplit_once('/')
+            && !dir.is_empty()
+            && !sftp.try_exists(dir).await?
+        {
+            return Err(format!("Remote directory {} does not exist", dir).into());
+        }
+
+        let mut remote = sftp.create(remote_path).await?;
+        let mut buffer = vec![0u8; 32 * 1024];
+        let mut sent = 0u64;
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled.into());
+            }
+            let n = local.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            remote.write_all(&buffer[..n]).await?;
+            sent += n as u64;
+            progress(sent, total);
+        }
+        remote.shutdown().await?;
+        sftp.close().await?;
+
+        Ok(sent)
+    })
+}
+
+/// Connect and send a reboot command.
+/// Once the command is sent the connection is expected to drop, so a lost
+/// connection or no answer counts as success; only a non-zero exit, e.g. a
+/// refused sudo, is an error.
+pub fn connect_and_reboot(
+    target: &Target,
+    command: &str,
+    cancel: &AtomicBool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    runtime().block_on(async {
+        let (_session, mut channel, _) = open_channel(target, cancel).await?;
+        channel.exec(true, command).await?;
+        if let Some(ref input) = target.stdin {
+            channel.data(input.as_bytes()).await?;
+        }
+        // The server is going away, so its cached session is useless
+        forget_session(target);
+
+        // Give the command a moment to run before tearing the session down
+        let exit = tokio::time::timeout(Duration::from_secs(30), async {
+            while let Some(msg) = channel.wait().await {
+                if let ChannelMsg::ExitStatus { exit_status } = msg {
+                    return Some(exit_status);
+                }
+            }
+            None
+        })
+        .await;
+
+        match exit {
+            Ok(Some(code)) if code != 0 => Err(SshError::CommandFailed { code }.into()),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Turn SFTP "no such file" / "permission denied" into readable messages
+fn sftp_error(path: &str, error: russh_sftp::client::error::Error) -> Box<dyn std::error::Error> {
+    use russh_sftp::client::error::Error as SftpError;
+    use russh_sftp::protocol::StatusCode;
+
+    match error {
+        SftpError::Status(status) if matches!(status.status_code, StatusCode::NoSuchFile) => {
+            format!("Remote file {} not found", path).into()
+        }
+        SftpError::Status(status) if matches!(status.status_code, StatusCode::PermissionDenied) => {
+            format!("Permission denied reading {}", path).into()
+        }
+        other => other.into(),
+    }
+}
+
+/// Copy `remote_path` to a local file over SFTP.
+/// The callback is called with (bytes received, total bytes) after each chunk.
+pub fn download_file<F>(
+    target: &Target,
+    remote_path: &str,
+    local_path: &Path,
+    cancel: &AtomicBool,
+    mut progress: F,
+) -> Result<u64, Box<dyn std::error::Error>>
+where
+    F: FnMut(u64, u64),
+{
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    runtime().block_on(async {
+        let (_session, sftp) = open_sftp(target, cancel).await?;
+
+        let total = sftp
+            .metadata(remote_path)
+            .await
+            .map_err(|e| sftp_error(remote_path, e))?
+            .len();
+        let mut remote = sftp
+            .open(remote_path)
+            .await
+            .map_err(|e| sftp_error(remote_path, e))?;
+        let mut local = tokio::fs::File::create(local_path)
+            .await
+            .map_err(|e| format!("Cannot create {}: {}", local_path.display(), e))?;
+
+        let mut buffer = vec![0u8; 32 * 1024];
+        let mut received = 0u64;
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Err(Cancelled.into());
+            }
+            let n = remote.read(&mut buffer).await?;
+            if n == 0 {
+                break;
+            }
+            local.write_all(&buffer[..n]).await?;
+            received += n as u64;
+            progress(received, total);
+        }
+        local.flush().await?;
+        sftp.close().await?;
+
+        Ok(received)
+    })
+}
+
+/// Check that `host:port` accepts TCP connections, without any SSH traffic.
+/// Returns how long the connect took.
+pub fn probe(host: &str, port: u16, timeout: Duration) -> Result<Duration, SshError> {
+    runtime().block_on(async {
+        let started = Instant::now();
+        match tokio::time::timeout(timeout, connect_tcp(host, port)).await {
+            Ok(Ok(_)) => Ok(started.elapsed()),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(SshError::Timeout(timeout)),
+        }
+    })
+}
+
+/// Request each variable and wait for the answer; false if any was refused
+async fn set_env(channel: &mut Channel<client::Msg>, env: &[(String, String)]) -> Result<bool, russh::Error> {
+    let mut accepted = true;
+    for (name, value) in env {
+        channel.set_env(true, name.as_str(), value.as_str()).await?;
+        loop {
+            match channel.wait().await {
+                Some(ChannelMsg::Success) => break,
+                Some(ChannelMsg::Failure) => {
+                    accepted = false;
+                    break;
+                }
+                None => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+    Ok(accepted)
+}
 
 /// Connect to a server and execute a command with streaming output.
 /// The callback is called for each line of output as it arrives.
+/// Setting `cancel` aborts the run and returns `Cancelled`.
+/// Returns the output and exit code (`None` if the server never sent one);
+/// a non-zero code is returned as `SshError::CommandFailed`.
 pub fn connect_and_execute_with_callback<F>(
-    ip: &str,
-    username: &str,
-    password: &str,
+    target: &Target,
     command: &str,
+    cancel: &AtomicBool,
     mut callback: F,
-) -> Result<String, Box<dyn std::error::Error>>
+) -> Result<(String, Option<u32>), Box<dyn std::error::Error>>
 where
     F: FnMut(&str),
 {
-    let rt = tokio::runtime::Runtime::new()?;
-
-    rt.block_on(async {
-        // Add default port if not specified
-        let address = if ip.contains(':') {
-            ip.to_string()
-        } else {
-            format!("{}:22", ip)
-        };
-
-        let config = Arc::new(client::Config::default());
-        let mut session = client::connect(config, &address, Client {}).await?;
+    runtime().block_on(async {
+        let (session, mut channel, reused) = open_channel(target, cancel).await?;
+        callback(&auth_notice(&session, reused));
 
-        // Authenticate
-        let auth_result = session.authenticate_password(username, password).await?;
-        if !auth_result {
-            return Err("Authentication failed".into());
+        // With a PTY stdout and stderr arrive merged, as in a terminal
+        if let Some(ref pty) = target.pty {
+            channel.request_pty(false, &pty.term, pty.columns, pty.rows, 0, 0, PTY_MODES).await?;
         }
 
+        // OpenSSH only accepts variables listed in the server's AcceptEnv
+        let command = match target.env_command {
+            Some(ref inline) if !set_env(&mut channel, &target.env).await? => inline.as_str(),
+            _ => command,
+        };
+
         // Execute command
-        let mut channel = session.channel_open_session().await?;
         channel.exec(true, command).await?;
+        if let Some(ref input) = target.stdin {
+            channel.data(input.as_bytes()).await?;
+        }
+        if target.stdin_eof {
+            channel.eof().await?;
+        }
 
         // Read output with streaming
         let mut output = String::new();
         let mut code = None;
-        let mut line_buffer = String::new();
+        // Unfinished lines, one per stream so interleaved chunks can't mix
+        let (mut stdout_line, mut stderr_line) = (String::new(), String::new());
+        let (mut stdout, mut stderr) = (OutputDecoder::default(), OutputDecoder::default());
+        // Without a PTY a prompt just hangs, so watch for silence and pass on queued input
+        let mut last_output = Instant::now();
+        let mut stall_warned = false;
+        let mut tick = tokio::time::interval(Duration::from_millis(250));
+        let started = Instant::now();
 
         loop {
-            let msg = channel.wait().await;
+            let msg = tokio::select! {
+                msg = channel.wait() => msg,
+                _ = cancelled(cancel) => {
+                    // Best effort - the server may already be gone
+                    let _ = channel.signal(Sig::INT).await;
+                    let _ = channel.close().await;
+                    return Err(Cancelled.into());
+                }
+                _ = tick.tick() => {
+                    if let Some(limit) = target.command_timeout
+                        && started.elapsed() >= limit
+                    {
+                        let _ = channel.signal(Sig::INT).await;
+                        let _ = channel.close().await;
+                        return Err(SshError::CommandTimeout(limit).into());
+                    }
+                    let queued = match target.input {
+                        Some(ref input) => std::mem::take(&mut *input.lock().unwrap()),
+                        None => Vec::new(),
+                    };
+                    for line in queued {
+                        channel.data(line.as_bytes()).await?;
+                        last_output = Instant::now();
+                        stall_warned = false;
+                    }
+                    if let Some(after) = target.stall_warning
+                        && !stall_warned
+                        && last_output.elapsed() >= after
+                    {
+                        stall_warned = true;
+                        callback(&format!("{} (no output for {}s)", STALL_WARNING, after.as_secs()));
+                    }
+                    continue;
+                }
+            };
+            if matches!(msg, Some(ChannelMsg::Data { .. } | ChannelMsg::ExtendedData { .. })) {
+                last_output = Instant::now();
+                stall_warned = false;
+            }
             match msg {
                 Some(ChannelMsg::Data { ref data }) => {
-                    let chunk = String::from_utf8_lossy(data);
+                    let chunk = stdout.decode(data);
                     output.push_str(&chunk);
-                    line_buffer.push_str(&chunk);
-
-                    while let Some(pos) = line_buffer.find('\n') {
-                        let line = line_buffer[..pos].to_string();
-                        line_buffer = line_buffer[pos + 1..].to_string();
-                        callback(&line);
-                    }
+                    stdout_line.push_str(&chunk);
+                    drain_lines(&mut stdout_line, "", &mut callback);
                 }
                 Some(ChannelMsg::ExtendedData { ref data, ext }) => {
-                    let chunk = String::from_utf8_lossy(data);
+                    let chunk = stderr.decode(data);
                     output.push_str(&chunk);
-                    line_buffer.push_str(&chunk);
-
-                    while let Some(pos) = line_buffer.find('\n') {
-                        let line = line_buffer[..pos].to_string();
-                        line_buffer = line_buffer[pos + 1..].to_string();
-                        if ext == 1 {
-                            callback(&format!("[stderr] {}", line));
-                        } else {
-                            callback(&line);
-                        }
+                    if ext == 1 {
+                        stderr_line.push_str(&chunk);
+                        drain_lines(&mut stderr_line, STDERR_PREFIX, &mut callback);
+                    } else {
+                        stdout_line.push_str(&chunk);
+                        drain_lines(&mut stdout_line, "", &mut callback);
                     }
                 }
                 Some(ChannelMsg::ExitStatus { exit_status }) => {
                     code = Some(exit_status);
                 }
                 Some(ChannelMsg::Eof) => {
-                    if !line_buffer.is_empty() {
-                        callback(&line_buffer);
+                    for (rest, line, prefix) in [
+                        (stdout.finish(), &mut stdout_line, ""),
+                        (stderr.finish(), &mut stderr_line, STDERR_PREFIX),
+                    ] {
+                        output.push_str(&rest);
+                        line.push_str(&rest);
+                        drain_lines(line, prefix, &mut callback);
+                        if !line.is_empty() {
+                            callback(&format!("{}{}", prefix, overwrite_carriage_returns(line)));
+                        }
+                    }
+                    let replaced = stdout.replaced + stderr.replaced;
+                    if replaced > 0 {
+                        callback(&format!(">>> {} byte(s) of output were not valid UTF-8 and show as \u{FFFD}", replaced));
                     }
                     break;
                 }
@@ -91,33 +895,321 @@ where
             }
         }
 
-        if let Some(exit_status) = code {
-            if exit_status != 0 {
-                return Err(format!(
-                    "Command failed with exit code {}: {}",
-                    exit_status,
-                    output.trim()
-                ).into());
-            }
+        if let Some(exit_status) = code
+            && exit_status != 0
+        {
+            return Err(SshError::CommandFailed { code: exit_status }.into());
         }
 
-        Ok(output)
+        Ok((output, code))
     })
 }
 
+/// Pass each complete line in `buffer` to `callback` with `prefix`, keeping
+/// the unfinished rest for the next chunk
+fn drain_lines<F: FnMut(&str)>(buffer: &mut String, prefix: &str, callback: &mut F) {
+    while let Some(pos) = buffer.find('\n') {
+        let line: String = buffer.drain(..=pos).collect();
+        callback(&format!("{}{}", prefix, overwrite_carriage_returns(&line[..pos])));
+    }
+}
+
+/// Progress bars (apt, dpkg, curl) redraw a line with `\r`; keep only the
+/// last state, as a terminal would. A trailing `\r` from CRLF is ignored.
+fn overwrite_carriage_returns(line: &str) -> &str {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    match line.rfind('\r') {
+        Some(pos) => &line[pos + 1..],
+        None => line,
+    }
+}
+
+/// Turns one of a channel's byte streams into text as packets arrive.
+/// UTF-16LE, which some Windows tools write when redirected, is recognised by
+/// its BOM or NUL bytes and decoded as such. Anything else is UTF-8: characters
+/// split across packets wait for the rest, and invalid bytes become U+FFFD and
+/// are counted so the run can say so.
+#[derive(Default)]
+struct OutputDecoder {
+    /// Decided from the first two bytes or more
+    utf16: Option<bool>,
+    pending: Vec<u8>,
+    replaced: usize,
+}
+
+impl OutputDecoder {
+    fn decode(&mut self, data: &[u8]) -> String {
+        self.pending.extend_from_slice(data);
+        let utf16 = match self.utf16 {
+            Some(utf16) => utf16,
+            None if self.pending.len() < 2 => return String::new(),
+            None => {
+                let utf16 = looks_like_utf16le(&self.pending);
+                self.utf16 = Some(utf16);
+                if utf16 && self.pending.starts_with(&[0xFF, 0xFE]) {
+                    self.pending.drain(..2);
+                }
+                utf16
+            }
+        };
+        if utf16 { self.decode_utf16() } else { self.decode_utf8() }
+    }
+
+    fn decode_utf8(&mut self) -> String {
+        let mut text = String::new();
+        let mut rest = self.pending.as_slice();
+        while let Err(e) = std::str::from_utf8(rest) {
+            let (valid, after) = rest.split_at(e.valid_up_to());
+            text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+            match e.error_len() {
+                Some(len) => {
+                    text.push(char::REPLACEMENT_CHARACTER);
+                    self.replaced += len;
+                    rest = &after[len..];
+                }
+                // Cut off mid-character, the next packet has the rest
+                None => {
+                    rest = after;
+                    break;
+                }
+            }
+        }
+        if let Ok(valid) = std::str::from_utf8(rest) {
+            text.push_str(valid);
+            rest = &[];
+        }
+        self.pending = rest.to_vec();
+        text
+    }
+
+    fn decode_utf16(&mut self) -> String {
+        let mut units: Vec<u16> = self.pending.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+        let mut keep = self.pending.len() % 2;
+        // A lone high surrogate waits for its pair
+        if units.last().is_some_and(|unit| (0xD800..0xDC00).contains(unit)) {
+            units.pop();
+            keep += 2;
+        }
+        self.pending.drain(..self.pending.len() - keep);
+        char::decode_utf16(units)
+            .map(|c| c.unwrap_or_else(|_| {
+                self.replaced += 2;
+                char::REPLACEMENT_CHARACTER
+            }))
+            .collect()
+    }
+
+    /// Whatever was still waiting when the stream ended
+    fn finish(&mut self) -> String {
+        // Output too short to pick an encoding from, e.g. a lone "1", is plain text
+        let mut text = match self.utf16 {
+            None => {
+                self.utf16 = Some(false);
+                self.decode_utf8()
+            }
+            Some(_) => String::new(),
+        };
+        if !self.pending.is_empty() {
+            self.replaced += self.pending.len();
+            self.pending.clear();
+            text.push(char::REPLACEMENT_CHARACTER);
+        }
+        text
+    }
+}
+
+/// A BOM, or mostly-ASCII text with a NUL after nearly every byte
+fn looks_like_utf16le(bytes: &[u8]) -> bool {
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        return true;
+    }
+    let sample = &bytes[..bytes.len().min(64) & !1];
+    let pairs = sample.len() / 2;
+    let nul_high = sample.chunks_exact(2).filter(|pair| pair[0] != 0 && pair[1] == 0).count();
+    pairs >= 2 && nul_high * 4 >= pairs * 3
+}
+
 /// SSH client handler
-struct Client {}
+struct Client {
+    host: String,
+    port: u16,
+    // Filled in when the host key is rejected, so the caller can report why
+    rejected: Arc<Mutex<Option<HostKeyError>>>,
+}
 
-#[async_trait::async_trait]
 impl client::Handler for Client {
     type Error = russh::Error;
 
-    async fn check_server_key(
-        &mut self,
-        _server_public_key: &key::PublicKey,
-    ) -> Result<bool, Self::Error> {
-        // Accept all server keys (for simplicity)
-        // In production, you should verify the server's key
-        Ok(true)
+    async fn check_server_key(&mut self, server_public_key: &PublicKey) -> Result<bool, Self::Error> {
+        // Verify against ~/.ssh/known_hosts
+        let reason = match check_known_hosts(&self.host, self.port, server_public_key) {
+            Ok(true) => return Ok(true),
+            Ok(false) => HostKeyError::Unknown {
+                host: self.host.clone(),
+                port: self.port,
+                key: server_public_key.clone(),
+            },
+            Err(keys::Error::KeyChanged { line }) => HostKeyError::Mismatch {
+                fingerprint: server_public_key.fingerprint(HashAlg::Sha256).to_string(),
+                line,
+            },
+            Err(e) => return Err(e.into()),
+        };
+        *self.rejected.lock().unwrap() = Some(reason);
+        Ok(false)
+    }
+}
+
+// =============================================================================
+// DIAGNOSTICS
+// =============================================================================
+// Shows what a server offers before login: its identification line, and the
+// auth methods a refused "none" login lists.
+// =============================================================================
+
+/// As the server lists them, e.g. "publickey,password"
+fn auth_methods(methods: &[MethodKind]) -> String {
+    methods.iter().map(<&str>::from).collect::<Vec<_>>().join(",")
+}
+
+/// Read the `SSH-2.0-...` identification line, skipping any text sent before it
+async fn read_identification<S>(stream: S) -> Result<String, Box<dyn std::error::Error>>
+where
+    S: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncBufReadExt;
+    let mut reader = tokio::io::BufReader::new(stream);
+    for _ in 0..20 {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            break;
+        }
+        if line.starts_with("SSH-") {
+            return Ok(line.trim_end().to_string());
+        }
+    }
+    Err(SshError::Connect("server sent no SSH identification".to_string()).into())
+}
+
+/// Report the server's identification string and the auth methods it offers,
+/// without logging in to it (a jump host is still logged in to).
+/// Each finding is passed to `report` as a line.
+pub fn diagnose<F>(target: &Target, mut report: F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: FnMut(&str),
+{
+    runtime().block_on(async {
+        let work = async {
+            let (mut handle, jump) = connect_unauthenticated(target).await?;
+
+            let identification = match jump {
+                None => read_identification(connect_tcp(&target.host, target.port).await?).await?,
+                Some(ref jump) => {
+                    let channel = jump
+                        .channel_open_direct_tcpip(target.host.as_str(), target.port as u32, "127.0.0.1", 0)
+                        .await?;
+                    read_identification(channel.into_stream()).await?
+                }
+            };
+            report(&format!(">>> Server identification: {}", identification));
+
+            match handle.authenticate_none(target.username.as_str()).await? {
+                client::AuthResult::Success => report(">>> Login accepted without any credentials"),
+                client::AuthResult::Failure { remaining_methods } if remaining_methods.is_empty() => {
+                    report(">>> The server did not list its auth methods")
+                }
+                client::AuthResult::Failure { remaining_methods } => {
+                    report(&format!(">>> Auth methods offered for {}: {}", target.username, auth_methods(&remaining_methods)))
+                }
+            }
+            Ok::<(), Box<dyn std::error::Error>>(())
+        };
+        tokio::time::timeout(target.connect_timeout, work)
+            .await
+            .map_err(|_| SshError::Timeout(target.connect_timeout))?
+    })
+}
+
+// =============================================================================
+// TESTS
+// =============================================================================
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_identity() {
+        let first = AuthMethod::Key(PathBuf::from("/home/me/.ssh/id_ed25519"));
+        let second = AuthMethod::Key(PathBuf::from("/home/me/.ssh/id_rsa"));
+        assert_ne!(first.identity(), second.identity());
+        assert_ne!(AuthMethod::Password(Zeroizing::new("hunter2".into())).identity(), AuthMethod::Agent.identity());
+        assert!(!AuthMethod::Password(Zeroizing::new("hunter2".into())).identity().contains("hunter2"));
+    }
+
+    #[test]
+    fn test_pty_modes_turn_off_echo() {
+        // Anything else lets a tty echo the sudo password back into the output
+        assert!(PTY_MODES.contains(&(Pty::ECHO, 0)));
+    }
+
+    #[test]
+    fn test_overwrite_carriage_returns() {
+        assert_eq!(overwrite_carriage_returns("10%\r50%\r100% done"), "100% done");
+        assert_eq!(overwrite_carriage_returns("windows line\r"), "windows line");
+        assert_eq!(overwrite_carriage_returns("plain"), "plain");
+    }
+
+    #[test]
+    fn test_drain_lines_keeps_streams_apart() {
+        let (mut out, mut err) = (String::new(), String::new());
+        let mut lines = Vec::new();
+        let mut callback = |line: &str| lines.push(line.to_string());
+        out.push_str("half ");
+        drain_lines(&mut out, "", &mut callback);
+        err.push_str("oops\n");
+        drain_lines(&mut err, STDERR_PREFIX, &mut callback);
+        out.push_str("line\nrest");
+        drain_lines(&mut out, "", &mut callback);
+        assert_eq!(lines, ["[stderr] oops", "half line"]);
+        assert_eq!(out, "rest");
+    }
+
+    #[test]
+    fn test_output_decoder() {
+        // "é" and "✓" split across packets
+        let mut utf8 = OutputDecoder::default();
+        let text = [utf8.decode(b"caf\xC3"), utf8.decode(b"\xA9 \xE2\x9C"), utf8.decode(b"\x93 \xFF!\n")].concat();
+        assert_eq!(text, "café ✓ \u{FFFD}!\n");
+        assert_eq!(utf8.replaced, 1);
+
+        let mut utf16 = OutputDecoder::default();
+        let bytes: Vec<u8> = "Größe\r\n".encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let (first, second) = bytes.split_at(5);
+        assert_eq!(utf16.decode(first) + &utf16.decode(second), "Größe\r\n");
+        assert_eq!((utf16.replaced, utf16.finish()), (0, String::new()));
+
+        let mut bom = OutputDecoder::default();
+        assert_eq!(bom.decode(&[0xFF, 0xFE, b'o', 0, b'k', 0]), "ok");
+
+        // One byte in all never gets to pick an encoding
+        let mut short = OutputDecoder::default();
+        assert_eq!(short.decode(b"1") + &short.finish(), "1");
+        assert_eq!(short.replaced, 0);
+    }
+
+    #[test]
+    fn test_auth_methods() {
+        let methods = [MethodKind::PublicKey, MethodKind::Password];
+        assert_eq!(auth_methods(&methods), "publickey,password");
+    }
+
+    #[test]
+    fn test_is_transient() {
+        let timeout: Box<dyn std::error::Error> = SshError::Timeout(Duration::from_secs(10)).into();
+        let auth: Box<dyn std::error::Error> = SshError::Auth { host: "web".into() }.into();
+        let cancelled: Box<dyn std::error::Error> = Cancelled.into();
+        assert!(is_transient(timeout.as_ref()));
+        assert!(!is_transient(auth.as_ref()));
+        assert!(!is_transient(cancelled.as_ref()));
     }
 }
@@ -0,0 +1,159 @@
+// =============================================================================
+// ENCRYPTED PASSWORD FILE
+// =============================================================================
+// For machines without a keyring (headless Linux, containers): remembered
+// passwords live in one file, encrypted with AES-256-GCM under a key derived
+// from a master passphrase with Argon2id. The passwords only ever exist in
+// memory in the clear; every save re-encrypts the whole file.
+//
+// File layout: MAGIC | salt (16) | nonce (12) | ciphertext of a JSON map
+// login -> password. The magic and salt are authenticated along with it.
+// =============================================================================
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+const MAGIC: &[u8] = b"server-manager vault 1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// An unlocked password file
+pub struct Vault {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    key: Zeroizing<[u8; 32]>,
+    passwords: HashMap<String, Zeroizing<String>>,
+}
+
+impl Vault {
+    /// Start an empty file at `path` locked with `passphrase`, writing it right away
+    pub fn create(path: &Path, passphrase: &str) -> Result<Vault, Box<dyn std::error::Error>> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let vault = Vault {
+            path: path.to_path_buf(),
+            salt,
+            key: derive_key(passphrase, &salt)?,
+            passwords: HashMap::new(),
+        };
+        vault.save()?;
+        Ok(vault)
+    }
+
+    /// Decrypt the file at `path`; a wrong passphrase and a tampered file look the same
+    pub fn open(path: &Path, passphrase: &str) -> Result<Vault, Box<dyn std::error::Error>> {
+        let bytes = fs::read(path)?;
+        let rest = bytes.strip_prefix(MAGIC)
+            .filter(|rest| rest.len() > SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| format!("{} is not a server-manager password file", path.display()))?;
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into()?;
+
+        let key = derive_key(passphrase, &salt)?;
+        let plaintext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*key))
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: &header(&salt) })
+            .map(Zeroizing::new)
+            .map_err(|_| "wrong passphrase, or the password file is damaged")?;
+        let passwords: HashMap<String, String> = serde_json::from_slice(&plaintext)?;
+
+        Ok(Vault {
+            path: path.to_path_buf(),
+            salt,
+            key,
+            passwords: passwords.into_iter().map(|(login, password)| (login, Zeroizing::new(password))).collect(),
+        })
+    }
+
+    pub fn get(&self, login: &str) -> Option<Zeroizing<String>> {
+        self.passwords.get(login).cloned()
+    }
+
+    /// Remember `password` for `login` and save
+    pub fn set(&mut self, login: &str, password: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.passwords.insert(login.to_string(), Zeroizing::new(password.to_string()));
+        self.save()
+    }
+
+    /// Forget `login`'s password, saving only if there was one
+    pub fn remove(&mut self, login: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match self.passwords.remove(login) {
+            Some(_) => self.save(),
+            None => Ok(()),
+        }
+    }
+
+    /// Encrypt under a fresh nonce and replace the file in one rename, so a
+    /// crash mid-write leaves the old file intact
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let plain: HashMap<&str, &str> = self.passwords.iter()
+            .map(|(login, password)| (login.as_str(), password.as_str()))
+            .collect();
+        let plaintext = Zeroizing::new(serde_json::to_vec(&plain)?);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let header = header(&self.salt);
+        let ciphertext = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&*self.key))
+            .encrypt(&nonce, Payload { msg: &plaintext, aad: &header })
+            .map_err(|_| "could not encrypt the password file")?;
+
+        let partial = self.path.with_extension("partial");
+        let mut file = private_file(&partial)?;
+        file.write_all(&header)?;
+        file.write_all(&nonce)?;
+        file.write_all(&ciphertext)?;
+        file.sync_all()?;
+        fs::rename(&partial, &self.path)?;
+        Ok(())
+    }
+}
+
+fn header(salt: &[u8]) -> Vec<u8> {
+    [MAGIC, salt].concat()
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, Box<dyn std::error::Error>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+        .map_err(|e| format!("could not derive the key: {}", e))?;
+    Ok(key)
+}
+
+/// Readable by the owner only, like the config file must be
+#[cfg(unix)]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)
+}
+
+#[cfg(not(unix))]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("passwords.vault");
+        let mut vault = Vault::create(&path, "correct horse").unwrap();
+        vault.set("root@web", "s3cret").unwrap();
+
+        let bytes = fs::read(&path).unwrap();
+        assert!(!bytes.windows(6).any(|window| window == b"s3cret"));
+
+        let reopened = Vault::open(&path, "correct horse").unwrap();
+        assert_eq!(reopened.get("root@web").as_deref().map(String::as_str), Some("s3cret"));
+        assert!(Vault::open(&path, "wrong").err().unwrap().to_string().contains("wrong passphrase"));
+    }
+}